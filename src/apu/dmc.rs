@@ -0,0 +1,200 @@
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// The delta modulation channel plays 1-bit delta-encoded samples from $C000-$FFFF.
+// Each sample byte is fetched over the CPU bus by the APU, which stalls the CPU;
+// the bus owner asks for the address with fetch_address and hands the byte to fill.
+#[derive(Clone, Debug)]
+pub struct Dmc {
+    rates: &'static [u16; 16],
+    rate_index: u8,
+    timer: u16,
+    irq_enabled: bool,
+    looping: bool,
+    level: u8, // 7-bit output level
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new(Region::Ntsc)
+    }
+}
+
+impl Dmc {
+    pub fn new(region: Region) -> Self {
+        Dmc {
+            rates: region.dmc_rates(),
+            rate_index: 0,
+            timer: 0,
+            irq_enabled: false,
+            looping: false,
+            level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.rates = region.dmc_rates();
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.rate_index);
+        state.u16(self.timer);
+        state.bool(self.irq_enabled);
+        state.bool(self.looping);
+        state.u8(self.level);
+        state.u16(self.sample_address);
+        state.u16(self.sample_length);
+        state.u16(self.current_address);
+        state.u16(self.bytes_remaining);
+        state.bool(self.sample_buffer.is_some());
+        state.u8(self.sample_buffer.unwrap_or(0));
+        state.u8(self.shift);
+        state.u8(self.bits_remaining);
+        state.bool(self.silence);
+        state.bool(self.irq);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rate_index = state.u8()? & 0x0F;
+        self.timer = state.u16()?;
+        self.irq_enabled = state.bool()?;
+        self.looping = state.bool()?;
+        self.level = state.u8()? & 0x7F;
+        self.sample_address = state.u16()?;
+        self.sample_length = state.u16()?;
+        self.current_address = state.u16()?;
+        self.bytes_remaining = state.u16()?;
+        let buffered = state.bool()?;
+        let sample = state.u8()?;
+        self.sample_buffer = if buffered { Some(sample) } else { None };
+        self.shift = state.u8()?;
+        self.bits_remaining = state.u8()?.clamp(1, 8);
+        self.silence = state.bool()?;
+        self.irq = state.bool()?;
+        Ok(())
+    }
+
+    // $4010: IRQ enable, loop and rate
+    pub fn write_control(&mut self, value: u8) {
+        self.irq_enabled = value & 0x80 != 0;
+        if !self.irq_enabled {
+            self.irq = false;
+        }
+        self.looping = value & 0x40 != 0;
+        self.rate_index = value & 0x0F;
+    }
+
+    // $4011: sets the output level directly
+    pub fn write_level(&mut self, value: u8) {
+        self.level = value & 0x7F;
+    }
+
+    // $4012: sample address = $C000 + A * 64
+    pub fn write_address(&mut self, value: u8) {
+        self.sample_address = 0xC000 | (value as u16) << 6;
+    }
+
+    // $4013: sample length = L * 16 + 1 bytes
+    pub fn write_length(&mut self, value: u8) {
+        self.sample_length = (value as u16) << 4 | 1;
+    }
+
+    // Bit 4 of $4015. Enabling restarts a finished sample; either way the IRQ is acknowledged.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    // The address the memory reader wants to fetch, once the sample buffer has emptied
+    pub fn fetch_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn fill(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // The address wraps from $FFFF around to $8000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle; the rate table is in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rates[self.rate_index as usize] - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
@@ -0,0 +1,76 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Volume envelope used by the pulse and noise channels: either a constant volume
+// or a sawtooth that decays from 15 to 0, optionally looping.
+#[derive(Clone, Debug, Default)]
+pub struct Envelope {
+    start: bool,
+    divider: u8,
+    decay: u8,
+    looping: bool,
+    constant: bool,
+    volume: u8, // Constant volume, or the divider period in decay mode
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.start);
+        state.u8(self.divider);
+        state.u8(self.decay);
+        state.bool(self.looping);
+        state.bool(self.constant);
+        state.u8(self.volume);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.start = state.bool()?;
+        self.divider = state.u8()?;
+        self.decay = state.u8()?;
+        self.looping = state.bool()?;
+        self.constant = state.bool()?;
+        self.volume = state.u8()?;
+        Ok(())
+    }
+
+    // Bits 0-5 of the channel's first register
+    pub fn write(&mut self, value: u8) {
+        self.looping = value & 0x20 != 0;
+        self.constant = value & 0x10 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    // Writing the length counter register restarts the decay on the next clock
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    // Clocked on every quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// A full-volume wave comes out about 2.4 times as loud as a full APU pulse
+const LEVEL: f32 = 2.4 * 0.149 / (63.0 * 32.0);
+// Master volume from $4089: 2/2, 2/3, 2/4 and 2/5
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+// Modulation table entries: how much each one moves the mod counter. 4 resets it.
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+// One of the two FDS envelopes: a gain that either holds what was written or
+// ramps up or down by one every tick
+#[derive(Clone, Debug, Default)]
+struct FdsEnvelope {
+    disabled: bool, // Gain comes straight from the register
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    timer: u32,
+}
+
+impl FdsEnvelope {
+    fn write(&mut self, value: u8) {
+        self.disabled = value & 0x80 != 0;
+        self.increase = value & 0x40 != 0;
+        self.speed = value & 0x3F;
+        self.timer = 0;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+    }
+
+    // Called once per CPU cycle; a tick comes every 8 * master speed * (speed + 1) cycles
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        self.timer += 1;
+        if self.timer >= 8 * master_speed as u32 * (self.speed as u32 + 1) {
+            self.timer = 0;
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.disabled);
+        state.bool(self.increase);
+        state.u8(self.speed);
+        state.u8(self.gain);
+        state.u32(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.disabled = state.bool()?;
+        self.increase = state.bool()?;
+        self.speed = state.u8()? & 0x3F;
+        // Gains are 6 bits wide and the timer stays below the longest period
+        self.gain = state.u8()? & 0x3F;
+        self.timer = state.u32()?.min(8 * 0xFF * (self.speed as u32 + 1) - 1);
+        Ok(())
+    }
+}
+
+// Famicom Disk System audio at $4040-$408A: one 64-step wavetable channel whose
+// pitch is bent by a modulation unit reading its own 64-step table of deltas
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool, // $4089 bit 7: the table can be written and the output holds still
+    wave_halt: bool,
+    envelopes_halt: bool,
+    frequency: u16,
+    phase: u32, // 16 fraction bits over the 64 steps
+    volume: FdsEnvelope,
+    master_volume: usize,
+    mod_table: [u8; 64],
+    mod_halt: bool,
+    mod_frequency: u16,
+    mod_phase: u32,
+    mod_counter: i8, // 7-bit signed
+    sweep: FdsEnvelope,
+    envelope_speed: u8,
+    output: u16, // Wave sample times gain, held while the table is writable
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FdsAudio {
+    pub const CHANNELS: [&str; 1] = ["fds"];
+
+    pub fn new() -> Self {
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            wave_halt: true,
+            envelopes_halt: false,
+            frequency: 0,
+            phase: 0,
+            volume: FdsEnvelope::default(),
+            master_volume: 0,
+            mod_table: [0; 64],
+            mod_halt: true,
+            mod_frequency: 0,
+            mod_phase: 0,
+            mod_counter: 0,
+            sweep: FdsEnvelope::default(),
+            envelope_speed: 0xE8,
+            output: 0,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.wave);
+        state.bool(self.wave_write);
+        state.bool(self.wave_halt);
+        state.bool(self.envelopes_halt);
+        state.u16(self.frequency);
+        state.u32(self.phase);
+        self.volume.save_state(state);
+        state.usize(self.master_volume);
+        state.bytes(&self.mod_table);
+        state.bool(self.mod_halt);
+        state.u16(self.mod_frequency);
+        state.u32(self.mod_phase);
+        state.u8(self.mod_counter as u8);
+        self.sweep.save_state(state);
+        state.u8(self.envelope_speed);
+        state.u16(self.output);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.wave, "FDS wavetable size")?;
+        self.wave_write = state.bool()?;
+        self.wave_halt = state.bool()?;
+        self.envelopes_halt = state.bool()?;
+        self.frequency = state.u16()? & 0x0FFF;
+        self.phase = state.u32()? & 0x3F_FFFF;
+        self.volume.load_state(state)?;
+        self.master_volume = state.usize()? & 0x03;
+        state.fill(&mut self.mod_table, "FDS modulation table size")?;
+        self.mod_halt = state.bool()?;
+        self.mod_frequency = state.u16()? & 0x0FFF;
+        self.mod_phase = state.u32()? & 0x3F_FFFF;
+        self.mod_counter = state.u8()? as i8;
+        self.sweep.load_state(state)?;
+        self.envelope_speed = state.u8()?;
+        self.output = state.u16()?;
+        Ok(())
+    }
+
+    // $4040-$407F and the gain readbacks at $4090/$4092. Only the low 6 bits are driven.
+    pub fn read(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4040..=0x407F => Some(self.wave[address as usize - 0x4040]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.sweep.gain),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4040..=0x407F if self.wave_write => self.wave[address as usize - 0x4040] = value & 0x3F,
+            0x4080 => self.volume.write(value),
+            0x4082 => self.frequency = (self.frequency & 0x0F00) | value as u16,
+            0x4083 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.envelopes_halt = value & 0x40 != 0;
+                self.wave_halt = value & 0x80 != 0;
+                if self.wave_halt {
+                    self.phase = 0;
+                }
+            }
+            0x4084 => self.sweep.write(value),
+            0x4085 => self.mod_counter = ((value << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | value as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.mod_halt = value & 0x80 != 0;
+            }
+            // Each write fills two entries; only possible while the unit is halted
+            0x4088 if self.mod_halt => {
+                let position = ((self.mod_phase >> 16) & 0x3E) as usize;
+                self.mod_table[position] = value & 0x07;
+                self.mod_table[position + 1] = value & 0x07;
+                self.mod_phase = (self.mod_phase + (2 << 16)) & 0x3F_FFFF;
+            }
+            0x4089 => {
+                self.wave_write = value & 0x80 != 0;
+                self.master_volume = (value & 0x03) as usize;
+            }
+            0x408A => self.envelope_speed = value,
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.wave_halt && !self.envelopes_halt {
+            self.volume.clock(self.envelope_speed);
+            self.sweep.clock(self.envelope_speed);
+        }
+
+        if !self.mod_halt && self.mod_frequency > 0 {
+            let before = self.mod_phase >> 16;
+            self.mod_phase = (self.mod_phase + self.mod_frequency as u32) & 0x3F_FFFF;
+            if self.mod_phase >> 16 != before {
+                let step = self.mod_table[before as usize];
+                self.mod_counter = if step == 4 {
+                    0
+                } else {
+                    // 7-bit wraparound
+                    (self.mod_counter.wrapping_add(MOD_STEPS[step as usize]) << 1) >> 1
+                };
+            }
+        }
+
+        if !self.wave_halt && !self.wave_write {
+            self.phase = (self.phase + self.pitch()) & 0x3F_FFFF;
+            let sample = self.wave[(self.phase >> 16) as usize];
+            self.output = sample as u16 * self.volume.gain.min(32) as u16;
+        }
+    }
+
+    // The wave's frequency bent by the modulation unit, as the hardware computes it
+    fn pitch(&self) -> u32 {
+        if self.mod_halt {
+            return self.frequency as u32;
+        }
+        let mut offset = self.mod_counter as i32 * self.sweep.gain as i32;
+        let remainder = offset & 0x0F;
+        offset >>= 4;
+        if remainder > 0 && offset & 0x80 == 0 {
+            offset += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if offset >= 192 {
+            offset -= 256;
+        } else if offset < -64 {
+            offset += 256;
+        }
+        let mut bend = self.frequency as i32 * offset;
+        let remainder = bend & 0x3F;
+        bend >>= 6;
+        if remainder >= 32 {
+            bend += 1;
+        }
+        (self.frequency as i32 + bend).max(0) as u32
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output as f32 * LEVEL * MASTER_VOLUME[self.master_volume]
+    }
+
+    pub fn channel_output(&self, _channel: usize) -> f32 {
+        self.output()
+    }
+}
@@ -0,0 +1,149 @@
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Which units a frame counter step clocks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameClocks {
+    pub quarter: bool, // Envelopes and the triangle's linear counter
+    pub half: bool,    // Length counters and sweep units
+}
+
+// The frame counter at $4017 divides the CPU clock into roughly 240 Hz steps.
+// The 4-step sequence clocks quarter frames on every step and half frames on
+// steps 2 and 4, and raises the frame IRQ over the last three cycles of the
+// sequence; the 5-step sequence has a silent fourth step and never raises it.
+#[derive(Clone, Debug)]
+pub struct FrameCounter {
+    four_step: [u32; 4],
+    five_step: [u32; 5],
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    irq: bool,
+    cycle: u32,
+    value: u8,                  // Last value written, written again on reset
+    pending: Option<(u8, u32)>, // A write waiting to restart the sequence, and the cycles left until it does
+}
+
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self::new(Region::Ntsc)
+    }
+}
+
+impl FrameCounter {
+    pub fn new(region: Region) -> Self {
+        FrameCounter {
+            four_step: region.frame_counter_four_step(),
+            five_step: region.frame_counter_five_step(),
+            five_step_mode: false,
+            irq_inhibit: false,
+            irq: false,
+            cycle: 0,
+            value: 0,
+            pending: None,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.four_step = region.frame_counter_four_step();
+        self.five_step = region.frame_counter_five_step();
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.five_step_mode);
+        state.u32(self.cycle);
+        state.bool(self.irq_inhibit);
+        state.bool(self.irq);
+        state.u8(self.value);
+        state.bool(self.pending.is_some());
+        let (value, delay) = self.pending.unwrap_or((0, 0));
+        state.u8(value);
+        state.u32(delay);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.five_step_mode = state.bool()?;
+        self.cycle = state.u32()?;
+        // Version 1 didn't have the IRQ or the write delay
+        if state.version < 2 {
+            self.irq_inhibit = false;
+            self.irq = false;
+            self.value = (self.five_step_mode as u8) << 7;
+            self.pending = None;
+            return Ok(());
+        }
+        self.irq_inhibit = state.bool()?;
+        self.irq = state.bool()?;
+        self.value = state.u8()?;
+        let pending = state.bool()?;
+        let value = state.u8()?;
+        let delay = state.u32()?;
+        self.pending = if pending { Some((value, delay)) } else { None };
+        Ok(())
+    }
+
+    // $4017. The inhibit flag applies at once, but the sequence restarts 3 or 4 CPU
+    // cycles later depending on whether the write landed on an APU cycle.
+    pub fn write(&mut self, value: u8, odd_cycle: bool) {
+        self.value = value;
+        self.irq_inhibit = value & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.pending = Some((value, if odd_cycle { 4 } else { 3 }));
+    }
+
+    // The reset button writes the last value again; power-on writes 0
+    pub fn reset(&mut self, odd_cycle: bool) {
+        self.write(self.value, odd_cycle);
+    }
+
+    // Frame IRQ flag, as read from bit 6 of $4015
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    // Reading $4015 clears the frame IRQ
+    pub fn acknowledge(&mut self) {
+        self.irq = false;
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock(&mut self) -> FrameClocks {
+        if let Some((value, delay)) = self.pending {
+            if delay <= 1 {
+                // Restarting in 5-step mode clocks everything straight away
+                self.pending = None;
+                self.five_step_mode = value & 0x80 != 0;
+                self.cycle = 0;
+                return FrameClocks { quarter: self.five_step_mode, half: self.five_step_mode };
+            }
+            self.pending = Some((value, delay - 1));
+        }
+
+        self.cycle += 1;
+        let clocks = if self.five_step_mode {
+            match self.five_step.iter().position(|&step| step == self.cycle) {
+                Some(0) | Some(2) => FrameClocks { quarter: true, half: false },
+                Some(1) | Some(4) => FrameClocks { quarter: true, half: true },
+                _ => FrameClocks::default(),
+            }
+        } else {
+            match self.four_step.iter().position(|&step| step == self.cycle) {
+                Some(0) | Some(2) => FrameClocks { quarter: true, half: false },
+                Some(1) | Some(3) => FrameClocks { quarter: true, half: true },
+                _ => FrameClocks::default(),
+            }
+        };
+
+        // The IRQ flag is set on the cycle before the last step, on it and on the one after
+        let last = if self.five_step_mode { self.five_step[4] } else { self.four_step[3] };
+        if !self.five_step_mode && !self.irq_inhibit && (last - 1..=last + 1).contains(&self.cycle) {
+            self.irq = true;
+        }
+        if self.cycle > last {
+            self.cycle = 0;
+        }
+        clocks
+    }
+}
@@ -0,0 +1,63 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Silences a channel after a number of half frames loaded from LENGTH_TABLE
+#[derive(Clone, Debug, Default)]
+pub struct LengthCounter {
+    counter: u8,
+    pub halt: bool,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.counter);
+        state.bool(self.halt);
+        state.bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.counter = state.u8()?;
+        self.halt = state.bool()?;
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+
+    // Takes the top five bits of the channel's length register
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    // Disabling a channel through the status register clears its counter
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    // Clocked on every half frame
+    pub fn clock(&mut self) {
+        if self.counter > 0 && !self.halt {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+
+    pub fn value(&self) -> u8 {
+        self.counter
+    }
+}
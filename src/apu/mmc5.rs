@@ -0,0 +1,161 @@
+use crate::apu::pulse::Pulse;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// The MMC5 clocks its envelopes and length counters from its own ~240 Hz divider
+const FRAME_PERIOD: u32 = 7457;
+
+// MMC5 expansion audio: two pulse channels without sweep units and an 8-bit PCM
+// channel, at $5000-$5015
+pub struct Mmc5Audio {
+    pulses: [Pulse; 2],
+    pcm: u8,
+    pcm_read_mode: bool, // PCM samples come from CPU reads of $8000-$BFFF instead of $5011
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    frame_timer: u32,
+    odd_cycle: bool,
+}
+
+impl Default for Mmc5Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mmc5Audio {
+    pub const CHANNELS: [&str; 3] = ["mmc5-pulse1", "mmc5-pulse2", "mmc5-pcm"];
+
+    pub fn new() -> Self {
+        Mmc5Audio {
+            pulses: [Pulse::without_sweep(), Pulse::without_sweep()],
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            frame_timer: 0,
+            odd_cycle: false,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for pulse in self.pulses.iter() {
+            pulse.save_state(state);
+        }
+        state.u8(self.pcm);
+        state.bool(self.pcm_read_mode);
+        state.bool(self.pcm_irq_enabled);
+        state.bool(self.pcm_irq);
+        state.u32(self.frame_timer);
+        state.bool(self.odd_cycle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for pulse in self.pulses.iter_mut() {
+            pulse.load_state(state)?;
+        }
+        self.pcm = state.u8()?;
+        self.pcm_read_mode = state.bool()?;
+        self.pcm_irq_enabled = state.bool()?;
+        self.pcm_irq = state.bool()?;
+        self.frame_timer = state.u32()? % FRAME_PERIOD;
+        self.odd_cycle = state.bool()?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000 | 0x5004 => self.pulses[(address as usize >> 2) & 1].write_control(value),
+            0x5002 | 0x5006 => self.pulses[(address as usize >> 2) & 1].write_timer_low(value),
+            0x5003 | 0x5007 => self.pulses[(address as usize >> 2) & 1].write_timer_high(value),
+            0x5010 => {
+                self.pcm_read_mode = value & 0x01 != 0;
+                self.pcm_irq_enabled = value & 0x80 != 0;
+            }
+            // Writing $00 has no effect
+            0x5011 if !self.pcm_read_mode && value != 0 => self.pcm = value,
+            0x5015 => {
+                self.pulses[0].length.set_enabled(value & 0x01 != 0);
+                self.pulses[1].length.set_enabled(value & 0x02 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    // $5010: PCM IRQ flag, acknowledged by the read
+    pub fn read_pcm_status(&mut self) -> u8 {
+        let value = (self.pcm_irq as u8) << 7 | self.pcm_read_mode as u8;
+        self.pcm_irq = false;
+        value
+    }
+
+    pub fn peek_pcm_status(&self) -> u8 {
+        (self.pcm_irq as u8) << 7 | self.pcm_read_mode as u8
+    }
+
+    // $5015: which pulse length counters are still running
+    pub fn status(&self) -> u8 {
+        self.pulses[0].length.active() as u8 | (self.pulses[1].length.active() as u8) << 1
+    }
+
+    // In read mode every CPU read of $8000-$BFFF is also a PCM sample; a $00 sample raises the IRQ
+    pub fn capture_pcm(&mut self, value: u8) {
+        if !self.pcm_read_mode {
+            return;
+        }
+        if value == 0 {
+            self.pcm_irq = true;
+        } else {
+            self.pcm = value;
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.pcm_irq && self.pcm_irq_enabled
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_timer();
+            }
+        }
+
+        self.frame_timer += 1;
+        if self.frame_timer == FRAME_PERIOD {
+            self.frame_timer = 0;
+            for pulse in self.pulses.iter_mut() {
+                pulse.envelope.clock();
+                pulse.length.clock();
+            }
+        }
+    }
+
+    // Mixed like the APU's channels: the pulses share the non-linear pulse DAC curve
+    // and the PCM channel is about as loud as a full-scale DMC
+    pub fn output(&self) -> f32 {
+        self.scaled_output([1.0; 3])
+    }
+
+    // The mix with each channel's DAC input scaled, indexed like CHANNELS
+    pub fn scaled_output(&self, gains: [f32; 3]) -> f32 {
+        let pulses = self.pulses[0].output() as f32 * gains[0] + self.pulses[1].output() as f32 * gains[1];
+        let pulse_out = if pulses == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulses + 100.0) };
+        let pcm = self.pcm as f32 / 2.0 * gains[2];
+        let pcm_out = if pcm == 0.0 { 0.0 } else { 159.79 / (22638.0 / pcm + 100.0) };
+        pulse_out + pcm_out
+    }
+
+    // Each channel through the DAC on its own, indexed like CHANNELS
+    pub fn channel_output(&self, channel: usize) -> f32 {
+        match channel {
+            0 | 1 => {
+                let pulse = self.pulses[channel].output() as f32;
+                if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) }
+            }
+            _ if self.pcm == 0 => 0.0,
+            _ => 159.79 / (22638.0 / (self.pcm as f32 / 2.0) + 100.0),
+        }
+    }
+}
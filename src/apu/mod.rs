@@ -0,0 +1,210 @@
+// Sound generation. The channel building blocks are shared between the 2A03's
+// APU and the expansion audio chips on cartridges.
+pub mod dmc;
+pub mod envelope;
+pub mod fds;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod mmc5;
+pub mod n163;
+pub mod noise;
+pub mod pulse;
+pub mod sunsoft5b;
+pub mod triangle;
+pub mod vrc6;
+pub mod vrc7;
+
+use crate::audio::Channel;
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+use dmc::Dmc;
+use frame_counter::{FrameClocks, FrameCounter};
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+// The 2A03's audio unit at $4000-$4017: two pulses, a triangle, noise and the DMC,
+// clocked once per CPU cycle by the bus.
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    frame_counter: FrameCounter,
+    odd_cycle: bool, // Pulse timers run at half the CPU clock
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(1),
+            pulse2: Pulse::new(2),
+            triangle: Triangle::new(),
+            noise: Noise::new(Region::Ntsc),
+            dmc: Dmc::new(Region::Ntsc),
+            frame_counter: FrameCounter::new(Region::Ntsc),
+            odd_cycle: false,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.frame_counter.set_region(region);
+    }
+
+    // The reset button silences every channel, as if $4015 was written with 0,
+    // and restarts the frame counter with the last value written to $4017
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+        self.frame_counter.reset(self.odd_cycle);
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        self.frame_counter.save_state(state);
+        state.bool(self.odd_cycle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.frame_counter.load_state(state)?;
+        self.odd_cycle = state.bool()?;
+        Ok(())
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000 => self.pulse1.write_control(value),
+            0x4001 => self.pulse1.write_sweep(value),
+            0x4002 => self.pulse1.write_timer_low(value),
+            0x4003 => self.pulse1.write_timer_high(value),
+            0x4004 => self.pulse2.write_control(value),
+            0x4005 => self.pulse2.write_sweep(value),
+            0x4006 => self.pulse2.write_timer_low(value),
+            0x4007 => self.pulse2.write_timer_high(value),
+            0x4008 => self.triangle.write_control(value),
+            0x400A => self.triangle.write_timer_low(value),
+            0x400B => self.triangle.write_timer_high(value),
+            0x400C => self.noise.write_control(value),
+            0x400E => self.noise.write_period(value),
+            0x400F => self.noise.write_length(value),
+            0x4010 => self.dmc.write_control(value),
+            0x4011 => self.dmc.write_level(value),
+            0x4012 => self.dmc.write_address(value),
+            0x4013 => self.dmc.write_length(value),
+            0x4015 => {
+                self.pulse1.length.set_enabled(value & 0x01 != 0);
+                self.pulse2.length.set_enabled(value & 0x02 != 0);
+                self.triangle.length.set_enabled(value & 0x04 != 0);
+                self.noise.length.set_enabled(value & 0x08 != 0);
+                self.dmc.set_enabled(value & 0x10 != 0);
+            }
+            0x4017 => self.frame_counter.write(value, self.odd_cycle),
+            _ => {}
+        }
+    }
+
+    // $4015: which length counters are running, whether the DMC has bytes left, and both IRQs.
+    // Reading it acknowledges the frame IRQ.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_counter.acknowledge();
+        status
+    }
+
+    pub fn peek_status(&self) -> u8 {
+        self.pulse1.length.active() as u8
+            | (self.pulse2.length.active() as u8) << 1
+            | (self.triangle.length.active() as u8) << 2
+            | (self.noise.length.active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (self.frame_counter.irq() as u8) << 6
+            | (self.dmc.irq() as u8) << 7
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq() || self.dmc.irq()
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        let clocks = self.frame_counter.clock();
+        self.clock_frame(clocks);
+    }
+
+    fn clock_frame(&mut self, clocks: FrameClocks) {
+        if clocks.quarter {
+            self.pulse1.envelope.clock();
+            self.pulse2.envelope.clock();
+            self.triangle.clock_linear_counter();
+            self.noise.envelope.clock();
+        }
+        if clocks.half {
+            self.pulse1.length.clock();
+            self.pulse1.clock_sweep();
+            self.pulse2.length.clock();
+            self.pulse2.clock_sweep();
+            self.triangle.length.clock();
+            self.noise.length.clock();
+        }
+    }
+
+    // The 2A03's non-linear DAC, approximated with the usual formulas. Roughly 0.0 to 1.0.
+    pub fn output(&self) -> f32 {
+        self.scaled_output([1.0; 5])
+    }
+
+    // The mix with each channel's DAC input scaled, pulse 1 to DMC, as if its volume had been turned down
+    pub fn scaled_output(&self, gains: [f32; 5]) -> f32 {
+        let pulse1 = self.pulse1.output() as f32 * gains[0];
+        let pulse2 = self.pulse2.output() as f32 * gains[1];
+        let triangle = self.triangle.output() as f32 * gains[2];
+        let noise = self.noise.output() as f32 * gains[3];
+        mix(pulse1, pulse2, triangle, noise, self.dmc.output() as f32 * gains[4])
+    }
+
+    // One channel through the DAC as if the others were silent, e.g. for rendering stems
+    pub fn channel_output(&self, channel: Channel) -> f32 {
+        match channel {
+            Channel::Pulse1 => mix(self.pulse1.output() as f32, 0.0, 0.0, 0.0, 0.0),
+            Channel::Pulse2 => mix(0.0, self.pulse2.output() as f32, 0.0, 0.0, 0.0),
+            Channel::Triangle => mix(0.0, 0.0, self.triangle.output() as f32, 0.0, 0.0),
+            Channel::Noise => mix(0.0, 0.0, 0.0, self.noise.output() as f32, 0.0),
+            Channel::Dmc => mix(0.0, 0.0, 0.0, 0.0, self.dmc.output() as f32),
+            Channel::Expansion => 0.0,
+        }
+    }
+}
+
+fn mix(pulse1: f32, pulse2: f32, triangle: f32, noise: f32, dmc: f32) -> f32 {
+    let pulses = pulse1 + pulse2;
+    let pulse_out = if pulses == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulses + 100.0) };
+
+    let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+    let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+    pulse_out + tnd_out
+}
@@ -0,0 +1,153 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// One channel is updated every 15 CPU cycles
+const CHANNEL_CYCLES: u32 = 15;
+// A lone channel at full volume swings about as far as a full APU pulse. With
+// more channels enabled each one gets a smaller share of the multiplexed output.
+const LEVEL: f32 = 0.149 / 225.0;
+
+// Namco 163 expansion audio: up to eight 4-bit wavetable channels that live in
+// 128 bytes of internal RAM, reached through an address port at $F800 and a data
+// port at $4800. The chip has one DAC and takes turns between the enabled
+// channels, so the more of them there are the quieter and lower-pitched each is.
+pub struct N163Audio {
+    ram: [u8; 0x80],
+    address: u8,
+    auto_increment: bool,
+    current: usize, // Channel being updated, counting down from 7
+    timer: u32,
+    outputs: [i16; 8], // Each channel's last sample times its volume, centred on zero
+}
+
+impl Default for N163Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl N163Audio {
+    // Numbered by their registers, so with fewer enabled channels the low ones are silent
+    pub const CHANNELS: [&str; 8] = [
+        "n163-0", "n163-1", "n163-2", "n163-3", "n163-4", "n163-5", "n163-6", "n163-7",
+    ];
+
+    pub fn new() -> Self {
+        N163Audio {
+            ram: [0; 0x80],
+            address: 0,
+            auto_increment: false,
+            current: 7,
+            timer: 0,
+            outputs: [0; 8],
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram);
+        state.u8(self.address);
+        state.bool(self.auto_increment);
+        state.usize(self.current);
+        state.u32(self.timer);
+        for &output in &self.outputs {
+            state.u16(output as u16);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.ram, "N163 RAM size")?;
+        self.address = state.u8()? & 0x7F;
+        self.auto_increment = state.bool()?;
+        self.current = state.usize()? & 0x07;
+        // Bounded to what the chip can reach, so a corrupt state cannot overflow the mix
+        self.timer = state.u32()?.min(CHANNEL_CYCLES - 1);
+        for output in self.outputs.iter_mut() {
+            *output = (state.u16()? as i16).clamp(-8 * 15, 7 * 15);
+        }
+        Ok(())
+    }
+
+    // $F800: bits 0-6 pick the RAM address, bit 7 steps it after each data access
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value & 0x7F;
+        self.auto_increment = value & 0x80 != 0;
+    }
+
+    // $4800 reads
+    pub fn read_data(&mut self) -> u8 {
+        let value = self.ram[self.address as usize];
+        self.step_address();
+        value
+    }
+
+    pub fn peek_data(&self) -> u8 {
+        self.ram[self.address as usize]
+    }
+
+    // $4800 writes
+    pub fn write_data(&mut self, value: u8) {
+        self.ram[self.address as usize] = value;
+        self.step_address();
+    }
+
+    fn step_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    // $7F bits 4-6 hold the number of enabled channels minus one; they are the last ones, 7 downwards
+    fn channel_count(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.timer += 1;
+        if self.timer < CHANNEL_CYCLES {
+            return;
+        }
+        self.timer = 0;
+
+        let channel = self.current;
+        self.update_channel(channel);
+        self.current = if channel <= 8 - self.channel_count() { 7 } else { channel - 1 };
+    }
+
+    // Channel n's registers are the eight bytes at $40 + 8n: a 24-bit phase and an
+    // 18-bit frequency interleaved, then the wave length, its start and the volume
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let registers = &self.ram[base..base + 8];
+        let frequency = registers[0] as u32 | (registers[2] as u32) << 8 | (registers[4] as u32 & 0x03) << 16;
+        let mut phase = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        let length = 256 - (registers[4] & 0xFC) as u32;
+        let start = registers[6] as u32;
+        let volume = (registers[7] & 0x0F) as i16;
+
+        phase = (phase + frequency) % (length << 16);
+        let sample_address = (((phase >> 16) + start) & 0xFF) as usize;
+        let byte = self.ram[sample_address >> 1];
+        let sample = if sample_address & 1 == 0 { byte & 0x0F } else { byte >> 4 };
+        self.outputs[channel] = (sample as i16 - 8) * volume;
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+    }
+
+    // The DAC's time-multiplexed output averages out to the mean of the enabled channels
+    pub fn output(&self) -> f32 {
+        let count = self.channel_count();
+        let sum: i16 = self.outputs[8 - count..].iter().sum();
+        sum as f32 / count as f32 * LEVEL
+    }
+
+    // A disabled channel contributes nothing
+    pub fn channel_output(&self, channel: usize) -> f32 {
+        let count = self.channel_count();
+        if channel < 8 - count {
+            return 0.0;
+        }
+        self.outputs[channel] as f32 / count as f32 * LEVEL
+    }
+}
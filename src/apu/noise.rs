@@ -0,0 +1,99 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// The noise channel: a 15-bit linear feedback shift register clocked by a timer.
+// Mode 1 takes the feedback from bit 6 instead of bit 1, giving a short 93-step
+// metallic loop instead of white noise.
+#[derive(Clone, Debug)]
+pub struct Noise {
+    periods: &'static [u16; 16],
+    period_index: u8,
+    timer: u16,
+    short_mode: bool,
+    shift: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new(Region::Ntsc)
+    }
+}
+
+impl Noise {
+    pub fn new(region: Region) -> Self {
+        Noise {
+            periods: region.noise_periods(),
+            period_index: 0,
+            timer: 0,
+            short_mode: false,
+            shift: 1,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = region.noise_periods();
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.period_index);
+        state.u16(self.timer);
+        state.bool(self.short_mode);
+        state.u16(self.shift);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.period_index = state.u8()? & 0x0F;
+        self.timer = state.u16()?;
+        self.short_mode = state.bool()?;
+        self.shift = state.u16()? & 0x7FFF;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)?;
+        Ok(())
+    }
+
+    // $400C: length counter halt, envelope
+    pub fn write_control(&mut self, value: u8) {
+        self.length.halt = value & 0x20 != 0;
+        self.envelope.write(value);
+    }
+
+    // $400E: mode and period
+    pub fn write_period(&mut self, value: u8) {
+        self.short_mode = value & 0x80 != 0;
+        self.period_index = value & 0x0F;
+    }
+
+    // $400F: reloads the length counter and restarts the envelope
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value >> 3);
+        self.envelope.restart();
+    }
+
+    // Clocked every CPU cycle; the period table is in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.periods[self.period_index as usize] - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift & 0x01 != 0 || !self.length.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
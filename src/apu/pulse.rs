@@ -0,0 +1,173 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+// A square wave channel: 11-bit timer, 8-step duty sequencer, envelope, length
+// counter and (on the 2A03) a sweep unit that bends the period.
+#[derive(Clone, Debug)]
+pub struct Pulse {
+    has_sweep: bool,
+    ones_complement: bool, // The first 2A03 pulse negates its sweep with one's complement
+    duty: usize,
+    step: usize,
+    period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    // One of the APU's two pulse channels, numbered from 1
+    pub fn new(channel: u8) -> Self {
+        Pulse {
+            has_sweep: true,
+            ones_complement: channel == 1,
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    // Expansion chips such as the MMC5 copy the channel without the sweep unit
+    pub fn without_sweep() -> Self {
+        Pulse {
+            has_sweep: false,
+            ..Self::new(2)
+        }
+    }
+
+    // Which channel this is is fixed at construction and not part of the state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.duty as u8);
+        state.u8(self.step as u8);
+        state.u16(self.period);
+        state.u16(self.timer);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+        state.bool(self.sweep_enabled);
+        state.u8(self.sweep_period);
+        state.bool(self.sweep_negate);
+        state.u8(self.sweep_shift);
+        state.u8(self.sweep_divider);
+        state.bool(self.sweep_reload);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.duty = (state.u8()? & 0x03) as usize;
+        self.step = (state.u8()? & 0x07) as usize;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)?;
+        self.sweep_enabled = state.bool()?;
+        self.sweep_period = state.u8()? & 0x07;
+        self.sweep_negate = state.bool()?;
+        self.sweep_shift = state.u8()? & 0x07;
+        self.sweep_divider = state.u8()?;
+        self.sweep_reload = state.bool()?;
+        Ok(())
+    }
+
+    // $4000/$4004: duty, length counter halt, envelope
+    pub fn write_control(&mut self, value: u8) {
+        self.duty = (value >> 6) as usize;
+        self.length.halt = value & 0x20 != 0;
+        self.envelope.write(value);
+    }
+
+    // $4001/$4005
+    pub fn write_sweep(&mut self, value: u8) {
+        self.sweep_enabled = value & 0x80 != 0;
+        self.sweep_period = (value >> 4) & 0x07;
+        self.sweep_negate = value & 0x08 != 0;
+        self.sweep_shift = value & 0x07;
+        self.sweep_reload = true;
+    }
+
+    // $4002/$4006
+    pub fn write_timer_low(&mut self, value: u8) {
+        self.period = (self.period & 0x0700) | value as u16;
+    }
+
+    // $4003/$4007: also reloads the length counter and restarts the waveform
+    pub fn write_timer_high(&mut self, value: u8) {
+        self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+        self.length.load(value >> 3);
+        self.step = 0;
+        self.envelope.restart();
+    }
+
+    // Clocked every APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Clocked on every half frame
+    pub fn clock_sweep(&mut self) {
+        if !self.has_sweep {
+            return;
+        }
+        let target = self.sweep_target();
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = target as u16;
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> i32 {
+        let period = self.period as i32;
+        let change = period >> self.sweep_shift;
+        if !self.sweep_negate {
+            period + change
+        } else if self.ones_complement {
+            period - change - 1
+        } else {
+            period - change
+        }
+    }
+
+    // The sweep unit silences periods that are too short, or that it would push past $7FF
+    fn muted(&self) -> bool {
+        self.has_sweep && (self.period < 8 || self.sweep_target() > 0x07FF)
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || DUTY_CYCLES[self.duty][self.step] == 0 || self.muted() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
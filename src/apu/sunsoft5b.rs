@@ -0,0 +1,220 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Tone and noise counters tick every 16 CPU cycles, the envelope every 8
+const TONE_CYCLES: u32 = 16;
+const ENVELOPE_CYCLES: u32 = 8;
+// A tone at volume 15 swings about as far as a full APU pulse
+const LEVEL: f32 = 0.149;
+
+// Sunsoft 5B expansion audio: a Yamaha YM2149F, the AY-3-8910's sibling, with
+// an address port at $C000 and a data port at $E000. Three square-wave tones
+// that can each mix in the shared noise and take their volume from the shared
+// envelope. Volume steps are logarithmic, 1.5 dB apart over 32 levels.
+pub struct Sunsoft5bAudio {
+    address: u8,
+    registers: [u8; 16],
+    tone_timers: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_timer: u8,
+    noise: u32, // 17-bit LFSR
+    envelope_timer: u16,
+    envelope_step: u8, // 0-31 through the current ramp
+    envelope_holding: bool,
+    envelope_attack: bool, // Counting up rather than down
+    prescaler: u32,
+    levels: [f32; 32],
+}
+
+impl Default for Sunsoft5bAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sunsoft5bAudio {
+    pub const CHANNELS: [&str; 3] = ["5b-a", "5b-b", "5b-c"];
+
+    pub fn new() -> Self {
+        let mut levels = [0.0; 32];
+        for (level, value) in levels.iter_mut().enumerate().skip(1) {
+            *value = 10f32.powf(-1.5 * (31 - level) as f32 / 20.0);
+        }
+        Sunsoft5bAudio {
+            address: 0,
+            registers: [0; 16],
+            tone_timers: [0; 3],
+            tone_outputs: [false; 3],
+            noise_timer: 0,
+            noise: 1,
+            envelope_timer: 0,
+            envelope_step: 0,
+            envelope_holding: false,
+            envelope_attack: false,
+            prescaler: 0,
+            levels,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.address);
+        state.bytes(&self.registers);
+        for index in 0..3 {
+            state.u16(self.tone_timers[index]);
+            state.bool(self.tone_outputs[index]);
+        }
+        state.u8(self.noise_timer);
+        state.u32(self.noise);
+        state.u16(self.envelope_timer);
+        state.u8(self.envelope_step);
+        state.bool(self.envelope_holding);
+        state.bool(self.envelope_attack);
+        state.u32(self.prescaler);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.address = state.u8()?;
+        state.fill(&mut self.registers, "5B register count")?;
+        // Counters are kept below their periods, which ends a count on the same tick
+        // as any larger value would and keeps a corrupt state from overflowing them
+        for index in 0..3 {
+            self.tone_timers[index] = state.u16()?.min(self.tone_period(index) - 1);
+            self.tone_outputs[index] = state.bool()?;
+        }
+        self.noise_timer = state.u8()?.min(self.noise_period() - 1);
+        self.noise = state.u32()? & 0x1_FFFF;
+        self.envelope_timer = state.u16()?.min(self.envelope_period() - 1);
+        self.envelope_step = state.u8()? & 0x1F;
+        self.envelope_holding = state.bool()?;
+        self.envelope_attack = state.bool()?;
+        self.prescaler = state.u32()? % TONE_CYCLES;
+        Ok(())
+    }
+
+    // $C000: the register the next $E000 write goes to. The upper bits have to be clear.
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        if self.address > 0x0F {
+            return;
+        }
+        self.registers[self.address as usize] = value;
+        // Writing the shape restarts the envelope
+        if self.address == 0x0D {
+            self.envelope_timer = 0;
+            self.envelope_step = 0;
+            self.envelope_holding = false;
+            self.envelope_attack = value & 0x04 != 0;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        (self.registers[channel * 2] as u16 | (self.registers[channel * 2 + 1] as u16 & 0x0F) << 8).max(1)
+    }
+
+    // Noise runs at half the tone rate
+    fn noise_period(&self) -> u8 {
+        (self.registers[0x06] & 0x1F).max(1) * 2
+    }
+
+    fn envelope_period(&self) -> u16 {
+        (self.registers[0x0B] as u16 | (self.registers[0x0C] as u16) << 8).max(1)
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.prescaler += 1;
+        if self.prescaler.is_multiple_of(ENVELOPE_CYCLES) {
+            self.clock_envelope();
+        }
+        if self.prescaler < TONE_CYCLES {
+            return;
+        }
+        self.prescaler = 0;
+
+        for channel in 0..3 {
+            self.tone_timers[channel] += 1;
+            if self.tone_timers[channel] >= self.tone_period(channel) {
+                self.tone_timers[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_timer += 1;
+        if self.noise_timer >= self.noise_period() {
+            self.noise_timer = 0;
+            let feedback = (self.noise ^ (self.noise >> 3)) & 1;
+            self.noise = (self.noise >> 1) | (feedback << 16);
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        self.envelope_timer += 1;
+        if self.envelope_timer < self.envelope_period() {
+            return;
+        }
+        self.envelope_timer = 0;
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+
+        // End of a ramp: the shape's continue, alternate and hold bits decide what comes next
+        let shape = self.registers[0x0D];
+        let (continues, alternate, hold) = (shape & 0x08 != 0, shape & 0x02 != 0, shape & 0x01 != 0);
+        if !continues {
+            self.envelope_attack = false;
+            self.envelope_step = 31;
+            self.envelope_holding = true;
+        } else if hold {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_holding = true;
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    fn envelope_level(&self) -> usize {
+        let shape = self.registers[0x0D];
+        if self.envelope_holding && shape & 0x08 == 0 {
+            return 0;
+        }
+        if self.envelope_attack {
+            self.envelope_step as usize
+        } else {
+            31 - self.envelope_step as usize
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        (0..3).map(|channel| self.channel_output(channel)).sum()
+    }
+
+    // Tones A, B and C
+    pub fn channel_output(&self, channel: usize) -> f32 {
+        let mixer = self.registers[0x07];
+        let tone_off = mixer & (0x01 << channel) != 0;
+        let noise_off = mixer & (0x08 << channel) != 0;
+        if !(self.tone_outputs[channel] || tone_off) || !(self.noise & 1 != 0 || noise_off) {
+            return 0.0;
+        }
+        let volume = self.registers[0x08 + channel];
+        let level = if volume & 0x10 != 0 {
+            self.envelope_level()
+        } else if volume & 0x0F == 0 {
+            0
+        } else {
+            (volume & 0x0F) as usize * 2 + 1
+        };
+        self.levels[level] * LEVEL
+    }
+}
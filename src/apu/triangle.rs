@@ -0,0 +1,98 @@
+use crate::apu::length_counter::LengthCounter;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// The triangle channel: a 32-step ramp with no volume control. Both its length
+// counter and its linear counter have to be non-zero for the sequencer to move;
+// when either runs out the output holds its last level instead of dropping to 0.
+#[derive(Clone, Debug, Default)]
+pub struct Triangle {
+    period: u16,
+    timer: u16,
+    step: usize,
+    pub length: LengthCounter,
+    linear_counter: u8,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    control: bool, // Halts the length counter and keeps the linear counter reloading
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.period);
+        state.u16(self.timer);
+        state.u8(self.step as u8);
+        self.length.save_state(state);
+        state.u8(self.linear_counter);
+        state.u8(self.linear_reload_value);
+        state.bool(self.linear_reload);
+        state.bool(self.control);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.step = (state.u8()? & 0x1F) as usize;
+        self.length.load_state(state)?;
+        self.linear_counter = state.u8()?;
+        self.linear_reload_value = state.u8()?;
+        self.linear_reload = state.bool()?;
+        self.control = state.bool()?;
+        Ok(())
+    }
+
+    // $4008: control flag and linear counter reload value
+    pub fn write_control(&mut self, value: u8) {
+        self.control = value & 0x80 != 0;
+        self.length.halt = self.control;
+        self.linear_reload_value = value & 0x7F;
+    }
+
+    // $400A
+    pub fn write_timer_low(&mut self, value: u8) {
+        self.period = (self.period & 0x0700) | value as u16;
+    }
+
+    // $400B: also reloads the length counter and sets the linear counter reload flag
+    pub fn write_timer_high(&mut self, value: u8) {
+        self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+        self.length.load(value >> 3);
+        self.linear_reload = true;
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Clocked on every quarter frame
+    pub fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step]
+    }
+}
@@ -0,0 +1,232 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// VRC6 pulse channels output their volume level while the duty step is at or
+// below the duty setting; in digitized mode they output it constantly.
+#[derive(Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    digitized: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.digitized = value & 0x80 != 0;
+                self.duty = (value >> 4) & 0x07;
+                self.volume = value & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value & 0x0F) as u16) << 8;
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.volume);
+        state.u8(self.duty);
+        state.bool(self.digitized);
+        state.u16(self.period);
+        state.bool(self.enabled);
+        state.u16(self.timer);
+        state.u8(self.step);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.volume = state.u8()? & 0x0F;
+        self.duty = state.u8()? & 0x07;
+        self.digitized = state.bool()?;
+        self.period = state.u16()?;
+        self.enabled = state.bool()?;
+        self.timer = state.u16()?;
+        self.step = state.u8()? & 0x0F;
+        Ok(())
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+// The sawtooth adds its rate to an accumulator on every other timer clock and
+// resets after the seventh addition; the top five bits are the output.
+#[derive(Default)]
+struct Vrc6Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value & 0x0F) as u16) << 8;
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.accumulator = 0;
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer != 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.rate);
+        state.u16(self.period);
+        state.bool(self.enabled);
+        state.u16(self.timer);
+        state.u8(self.step);
+        state.u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rate = state.u8()? & 0x3F;
+        self.period = state.u16()?;
+        self.enabled = state.bool()?;
+        self.timer = state.u16()?;
+        self.step = state.u8()? % 14;
+        self.accumulator = state.u8()?;
+        Ok(())
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+// A full-volume VRC6 pulse is about as loud as a full-volume APU pulse
+const LEVEL: f32 = 0.15 / 15.0;
+
+// VRC6 expansion audio: two pulses and a sawtooth at $9000-$B002, with the
+// frequency control register at $9003
+#[derive(Default)]
+pub struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    halt: bool,
+    shift: u8,
+}
+
+impl Vrc6Audio {
+    pub const CHANNELS: [&str; 3] = ["vrc6-pulse1", "vrc6-pulse2", "vrc6-saw"];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for pulse in self.pulses.iter() {
+            pulse.save_state(state);
+        }
+        self.saw.save_state(state);
+        state.bool(self.halt);
+        state.u8(self.shift);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for pulse in self.pulses.iter_mut() {
+            pulse.load_state(state)?;
+        }
+        self.saw.load_state(state)?;
+        self.halt = state.bool()?;
+        self.shift = state.u8()? & 0x0F;
+        Ok(())
+    }
+
+    // Takes the register address with the board's A0/A1 wiring already undone
+    pub fn write(&mut self, address: u16, value: u8) {
+        let register = address & 0x03;
+        match address & 0xF000 {
+            0x9000 if register == 3 => {
+                self.halt = value & 0x01 != 0;
+                // The x256 speed-up takes priority over x16
+                self.shift = if value & 0x04 != 0 {
+                    8
+                } else if value & 0x02 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0x9000 => self.pulses[0].write(register, value),
+            0xA000 if register < 3 => self.pulses[1].write(register, value),
+            0xB000 if register < 3 => self.saw.write(register, value),
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        for pulse in self.pulses.iter_mut() {
+            pulse.clock(self.shift);
+        }
+        self.saw.clock(self.shift);
+    }
+
+    pub fn output(&self) -> f32 {
+        let level = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        level as f32 * LEVEL
+    }
+
+    // Indexed like CHANNELS
+    pub fn channel_output(&self, channel: usize) -> f32 {
+        let level = match channel {
+            0 | 1 => self.pulses[channel].output(),
+            _ => self.saw.output(),
+        };
+        level as f32 * LEVEL
+    }
+}
@@ -0,0 +1,388 @@
+use std::f32::consts::TAU;
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+// The OPLL makes one sample every 72 ticks of its 3.58 MHz crystal, which is every 36 CPU cycles on NTSC
+const SAMPLE_CYCLES: u32 = 36;
+const SAMPLE_RATE: f32 = 3_579_545.0 / 72.0;
+// A carrier at full volume swings about as far as a full APU pulse
+const LEVEL: f32 = 0.075;
+// The envelope covers 48 dB; anything quieter is silence
+const MAX_ATTENUATION: f32 = 48.0;
+// Time for a decay to cover the whole range, and for an attack to finish, at rate 4. Both halve every 4 rates.
+const DECAY_TIME: f32 = 19.64;
+const ATTACK_TIME: f32 = 2.826;
+const MULTIPLIERS: [f32; 16] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0];
+// Key scale attenuation in dB at octave 7, by the top four bits of the frequency; 6 dB less per octave below
+const KEY_SCALE: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0,
+];
+// KSL 0-3 scales that to 0, 1.5, 3 and 6 dB per octave
+const KEY_SCALE_DEPTH: [f32; 4] = [0.0, 0.25, 0.5, 1.0];
+// Tremolo and vibrato LFOs
+const AM_RATE: f32 = 3.7;
+const AM_DEPTH: f32 = 4.8;
+const PM_RATE: f32 = 6.4;
+const PM_DEPTH: f32 = 0.008;
+
+// The VRC7's built-in instruments 1-15. Instrument 0 is the custom one in registers $00-$07.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    #[default]
+    Off,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [Stage::Attack, Stage::Decay, Stage::Sustain, Stage::Release, Stage::Off];
+}
+
+// What an operator takes from its half of an instrument
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool, // Holds at the sustain level instead of carrying on into the release
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: usize,
+    half_sine: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: f32,
+    release: u8,
+}
+
+impl OperatorPatch {
+    // Operator 0 is the modulator, 1 the carrier
+    fn new(patch: &[u8; 8], operator: usize) -> Self {
+        let flags = patch[operator];
+        OperatorPatch {
+            tremolo: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiplier: MULTIPLIERS[(flags & 0x0F) as usize],
+            key_scale_level: (patch[2 + operator] >> 6) as usize,
+            half_sine: patch[3] & (0x08 << operator) != 0,
+            attack: patch[4 + operator] >> 4,
+            decay: patch[4 + operator] & 0x0F,
+            sustain_level: (patch[6 + operator] >> 4) as f32 * 3.0,
+            release: patch[6 + operator] & 0x0F,
+        }
+    }
+}
+
+// One sine generator with its own envelope
+#[derive(Clone, Copy, Debug)]
+struct Operator {
+    phase: f32, // In cycles
+    stage: Stage,
+    envelope: f32, // Attenuation in dB
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Operator { phase: 0.0, stage: Stage::Off, envelope: MAX_ATTENUATION }
+    }
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.stage = Stage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.stage != Stage::Off {
+            self.stage = Stage::Release;
+        }
+    }
+
+    // rks is the key scale offset added to every rate
+    fn clock_envelope(&mut self, patch: &OperatorPatch, rks: u8, channel_sustain: bool) {
+        let rate = match self.stage {
+            Stage::Attack => patch.attack,
+            Stage::Decay => patch.decay,
+            Stage::Sustain if patch.sustained => 0,
+            Stage::Sustain => patch.release,
+            Stage::Release if channel_sustain => 5,
+            Stage::Release if patch.sustained => patch.release,
+            Stage::Release => 7,
+            Stage::Off => return,
+        };
+        if rate == 0 {
+            return;
+        }
+        let rate = (rate * 4 + rks).min(63);
+        let speed = 2f32.powf((rate as f32 - 4.0) / 4.0) / SAMPLE_RATE;
+
+        if self.stage == Stage::Attack {
+            // Attacks are exponential, fastest while the operator is quietest
+            if rate >= 60 {
+                self.envelope = 0.0;
+            } else {
+                self.envelope *= 1.0 - (7.0 * speed / ATTACK_TIME).min(1.0);
+            }
+            if self.envelope < 0.05 {
+                self.envelope = 0.0;
+                self.stage = Stage::Decay;
+            }
+            return;
+        }
+
+        self.envelope += MAX_ATTENUATION * speed / DECAY_TIME;
+        if self.stage == Stage::Decay && self.envelope >= patch.sustain_level {
+            self.envelope = patch.sustain_level;
+            self.stage = Stage::Sustain;
+        }
+        if self.envelope >= MAX_ATTENUATION {
+            self.envelope = MAX_ATTENUATION;
+            self.stage = Stage::Off;
+        }
+    }
+
+    // Phase offset in cycles, extra attenuation in dB; output is -1.0 to 1.0
+    fn output(&self, modulation: f32, attenuation: f32, half_sine: bool) -> f32 {
+        let attenuation = attenuation + self.envelope;
+        if self.stage == Stage::Off || attenuation >= MAX_ATTENUATION {
+            return 0.0;
+        }
+        let wave = (TAU * (self.phase + modulation)).sin();
+        if half_sine && wave < 0.0 {
+            return 0.0;
+        }
+        wave * 10f32.powf(-attenuation / 20.0)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u32(self.phase.to_bits());
+        state.u8(self.stage as u8);
+        state.u32(self.envelope.to_bits());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.phase = f32::from_bits(state.u32()?);
+        self.stage = Stage::ALL.get(state.u8()? as usize).copied().unwrap_or_default();
+        self.envelope = f32::from_bits(state.u32()?);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct FmChannel {
+    modulator: Operator,
+    carrier: Operator,
+    feedback: [f32; 2], // The modulator's last two outputs
+    key: bool,
+}
+
+// VRC7 expansion audio: a cut-down Yamaha OPLL programmed through an address
+// port at $9010 and a data port at $9030. Six two-operator FM channels, each
+// playing one of 15 fixed instruments or the custom one in $00-$07.
+pub struct Vrc7Audio {
+    address: u8,
+    registers: [u8; 0x40],
+    reset: bool,
+    channels: [FmChannel; 6],
+    am_phase: f32,
+    pm_phase: f32,
+    divider: u32,
+    output: f32,
+    channel_outputs: [f32; 6], // Each channel's part of output; not saved, it's back after one sample
+}
+
+impl Default for Vrc7Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vrc7Audio {
+    pub const CHANNELS: [&str; 6] = ["vrc7-fm1", "vrc7-fm2", "vrc7-fm3", "vrc7-fm4", "vrc7-fm5", "vrc7-fm6"];
+
+    pub fn new() -> Self {
+        Vrc7Audio {
+            address: 0,
+            registers: [0; 0x40],
+            reset: false,
+            channels: [FmChannel::default(); 6],
+            am_phase: 0.0,
+            pm_phase: 0.0,
+            divider: 0,
+            output: 0.0,
+            channel_outputs: [0.0; 6],
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.address);
+        state.bytes(&self.registers);
+        state.bool(self.reset);
+        for channel in &self.channels {
+            channel.modulator.save_state(state);
+            channel.carrier.save_state(state);
+            state.u32(channel.feedback[0].to_bits());
+            state.u32(channel.feedback[1].to_bits());
+            state.bool(channel.key);
+        }
+        state.u32(self.am_phase.to_bits());
+        state.u32(self.pm_phase.to_bits());
+        state.u32(self.divider);
+        state.u32(self.output.to_bits());
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.address = state.u8()? & 0x3F;
+        state.fill(&mut self.registers, "VRC7 register count")?;
+        self.reset = state.bool()?;
+        // Version 2 only had the registers; the channels start over silent
+        if state.version < 3 {
+            self.channels = [FmChannel::default(); 6];
+            self.output = 0.0;
+            return Ok(());
+        }
+        for channel in self.channels.iter_mut() {
+            channel.modulator.load_state(state)?;
+            channel.carrier.load_state(state)?;
+            channel.feedback = [f32::from_bits(state.u32()?), f32::from_bits(state.u32()?)];
+            channel.key = state.bool()?;
+        }
+        self.am_phase = f32::from_bits(state.u32()?);
+        self.pm_phase = f32::from_bits(state.u32()?);
+        self.divider = state.u32()? % SAMPLE_CYCLES;
+        self.output = f32::from_bits(state.u32()?);
+        Ok(())
+    }
+
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value & 0x3F;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        if !self.reset {
+            self.registers[self.address as usize] = value;
+        }
+    }
+
+    pub fn register(&self, index: u8) -> u8 {
+        self.registers[(index & 0x3F) as usize]
+    }
+
+    // $E000 bit 6 holds the sound chip in reset, clearing its registers
+    pub fn set_reset(&mut self, reset: bool) {
+        self.reset = reset;
+        if reset {
+            self.registers = [0; 0x40];
+            self.channels = [FmChannel::default(); 6];
+            self.output = 0.0;
+            self.channel_outputs = [0.0; 6];
+        }
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if self.reset {
+            return;
+        }
+        self.divider += 1;
+        if self.divider < SAMPLE_CYCLES {
+            return;
+        }
+        self.divider = 0;
+
+        self.am_phase = (self.am_phase + AM_RATE / SAMPLE_RATE).fract();
+        self.pm_phase = (self.pm_phase + PM_RATE / SAMPLE_RATE).fract();
+        let tremolo = AM_DEPTH * (1.0 - (TAU * self.am_phase).cos()) / 2.0;
+        let vibrato = 1.0 + PM_DEPTH * (TAU * self.pm_phase).sin();
+
+        for index in 0..self.channels.len() {
+            self.channel_outputs[index] = self.clock_channel(index, tremolo, vibrato) * LEVEL;
+        }
+        self.output = self.channel_outputs.iter().sum();
+    }
+
+    fn clock_channel(&mut self, index: usize, tremolo: f32, vibrato: f32) -> f32 {
+        let high = self.registers[0x20 + index];
+        let fnum = self.registers[0x10 + index] as u32 | ((high as u32 & 0x01) << 8);
+        let block = (high >> 1) & 0x07;
+        let key = high & 0x10 != 0;
+        let channel_sustain = high & 0x20 != 0;
+        let instrument = (self.registers[0x30 + index] >> 4) as usize;
+        let volume = (self.registers[0x30 + index] & 0x0F) as f32 * 3.0;
+        let patch = match instrument {
+            0 => {
+                let mut custom = [0; 8];
+                custom.copy_from_slice(&self.registers[..8]);
+                custom
+            }
+            _ => PATCHES[instrument - 1],
+        };
+        let patches = [OperatorPatch::new(&patch, 0), OperatorPatch::new(&patch, 1)];
+        let feedback = patch[3] & 0x07;
+        let total_level = (patch[2] & 0x3F) as f32 * 0.75;
+
+        let channel = &mut self.channels[index];
+        if key && !channel.key {
+            channel.modulator.key_on();
+            channel.carrier.key_on();
+        } else if !key && channel.key {
+            channel.modulator.key_off();
+            channel.carrier.key_off();
+        }
+        channel.key = key;
+
+        let key_scale = (KEY_SCALE[(fnum >> 5) as usize] - 6.0 * (7 - block) as f32).max(0.0);
+        let increment = (fnum << block) as f32 / (1 << 19) as f32;
+        let operators = [&mut channel.modulator, &mut channel.carrier];
+        let mut attenuation = [total_level, volume];
+        for ((operator, patch), attenuation) in operators.into_iter().zip(&patches).zip(attenuation.iter_mut()) {
+            let rks = ((block << 1) | (fnum >> 8) as u8) >> if patch.key_scale_rate { 0 } else { 2 };
+            operator.clock_envelope(patch, rks, channel_sustain);
+            let pitch = if patch.vibrato { increment * vibrato } else { increment };
+            operator.phase = (operator.phase + pitch * patch.multiplier).fract();
+            *attenuation += key_scale * KEY_SCALE_DEPTH[patch.key_scale_level];
+            if patch.tremolo {
+                *attenuation += tremolo;
+            }
+        }
+
+        // Feedback at 7 swings the modulator's own phase by up to 4 pi, halving per step below
+        let self_modulation = match feedback {
+            0 => 0.0,
+            _ => (channel.feedback[0] + channel.feedback[1]) / 2.0 * 2f32.powi(feedback as i32 - 7) * 2.0,
+        };
+        let modulator = channel.modulator.output(self_modulation, attenuation[0], patches[0].half_sine);
+        channel.feedback = [modulator, channel.feedback[0]];
+        // A full modulator swings the carrier's phase by up to 8 pi
+        channel.carrier.output(modulator * 4.0, attenuation[1], patches[1].half_sine)
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+
+    pub fn channel_output(&self, channel: usize) -> f32 {
+        self.channel_outputs[channel]
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use crate::region::Region;
+
+// Sub-sample positions a step can be placed at, and the taps spent on each step
+const PHASES: usize = 32;
+const WIDTH: usize = 16;
+// Finished samples kept for a host that isn't reading them, oldest dropped first
+const MAX_BUFFERED_SECONDS: usize = 1;
+
+// The sound sources that can be rendered on their own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    Expansion, // Whatever sound chip the cartridge has
+}
+
+impl Channel {
+    pub const ALL: [Channel; 6] =
+        [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc, Channel::Expansion];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+            Channel::Expansion => "expansion",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channels {
+    Mono,
+    Stereo, // Both sides carry the same signal
+}
+
+impl Channels {
+    pub fn count(&self) -> usize {
+        match self {
+            Channels::Mono => 1,
+            Channels::Stereo => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioConfig {
+    pub sample_rate: u32,
+    pub channels: Channels,
+    pub filters: bool, // The console's output filters: 90 Hz and 440 Hz high-pass, 14 kHz low-pass
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            sample_rate: 44100,
+            channels: Channels::Stereo,
+            filters: true,
+        }
+    }
+}
+
+// First-order RC filter, run at the output rate
+#[derive(Clone, Copy, Debug)]
+struct Filter {
+    high_pass: bool,
+    alpha: f64,
+    input: f64,
+    output: f64,
+}
+
+impl Filter {
+    fn new(high_pass: bool, cutoff: f64, sample_rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = if high_pass { rc / (rc + dt) } else { dt / (rc + dt) };
+        Filter { high_pass, alpha, input: 0.0, output: 0.0 }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        self.output = if self.high_pass {
+            self.alpha * (self.output + input - self.input)
+        } else {
+            self.output + self.alpha * (input - self.output)
+        };
+        self.input = input;
+        self.output
+    }
+}
+
+// Turns a level that changes at the CPU clock into samples at the host's rate.
+// Every change of level is drawn as a band-limited step (blip-buffer style):
+// its delta is spread over WIDTH samples with a windowed-sinc impulse, and the
+// impulses are summed up again when samples are finished, so there's no
+// aliasing from the square waves however fast they are.
+pub struct Resampler {
+    config: AudioConfig,
+    clock_rate: f64,   // Input clocks per second
+    rate_control: f64, // Host adjustment to the output rate, see set_rate_control
+    step: f64,         // Output samples per input clock
+    kernels: Vec<[f32; WIDTH]>,
+    time: f64,         // Position of the current clock, in samples past the start of `pending`
+    level: f32,        // Input level at the last clock
+    pending: Vec<f32>, // Impulses for samples that can still change
+    sum: f64,          // Integral of every finished impulse
+    filters: Vec<Filter>,
+    samples: VecDeque<f32>, // Finished mono samples, waiting for the host
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Self::new(AudioConfig::default(), Region::Ntsc.cpu_clock_hz())
+    }
+}
+
+impl Resampler {
+    pub fn new(config: AudioConfig, clock_rate: f64) -> Self {
+        let mut resampler = Resampler {
+            config,
+            clock_rate,
+            rate_control: 1.0,
+            step: 0.0,
+            kernels: build_kernels(),
+            time: 0.0,
+            level: 0.0,
+            pending: Vec::new(),
+            sum: 0.0,
+            filters: Vec::new(),
+            samples: VecDeque::new(),
+        };
+        resampler.set_config(config);
+        resampler
+    }
+
+    pub fn config(&self) -> AudioConfig {
+        self.config
+    }
+
+    // Samples already finished are dropped; the filters start over
+    pub fn set_config(&mut self, config: AudioConfig) {
+        // A rate of 0 would never finish a sample; config() reports the rate in use
+        self.config = AudioConfig { sample_rate: config.sample_rate.max(1), ..config };
+        let rate = self.config.sample_rate as f64;
+        self.filters = if config.filters {
+            vec![Filter::new(true, 90.0, rate), Filter::new(true, 440.0, rate), Filter::new(false, 14000.0, rate)]
+        } else {
+            Vec::new()
+        };
+        self.samples.clear();
+        self.update_step();
+    }
+
+    pub fn set_clock_rate(&mut self, clock_rate: f64) {
+        self.clock_rate = clock_rate;
+        self.update_step();
+    }
+
+    // For keeping audio and video in sync: above 1.0 makes more samples per emulated
+    // second, below 1.0 fewer. Hosts nudge it a little each frame, see dynamic_rate.
+    pub fn set_rate_control(&mut self, factor: f64) {
+        self.rate_control = factor;
+        self.update_step();
+    }
+
+    pub fn rate_control(&self) -> f64 {
+        self.rate_control
+    }
+
+    fn update_step(&mut self) {
+        self.step = self.config.sample_rate as f64 * self.rate_control / self.clock_rate;
+    }
+
+    // Called once per input clock with the mixed level
+    pub fn clock(&mut self, level: f32) {
+        if level != self.level {
+            self.add_step(level - self.level);
+            self.level = level;
+        }
+        self.time += self.step;
+    }
+
+    fn add_step(&mut self, delta: f32) {
+        let whole = self.time as usize;
+        let phase = ((self.time - whole as f64) * PHASES as f64).round() as usize;
+        if self.pending.len() < whole + WIDTH {
+            self.pending.resize(whole + WIDTH, 0.0);
+        }
+        for (sample, tap) in self.pending[whole..whole + WIDTH].iter_mut().zip(&self.kernels[phase]) {
+            *sample += delta * tap;
+        }
+    }
+
+    // Finishes every sample that no later step can reach. Called once per frame.
+    pub fn end_frame(&mut self) {
+        let ready = self.time as usize;
+        if self.pending.len() < ready {
+            self.pending.resize(ready, 0.0);
+        }
+        for &impulse in &self.pending[..ready] {
+            self.sum += impulse as f64;
+            let mut sample = self.sum;
+            for filter in self.filters.iter_mut() {
+                sample = filter.process(sample);
+            }
+            self.samples.push_back(sample as f32);
+        }
+        self.pending.drain(..ready);
+        self.time -= ready as f64;
+
+        let limit = self.config.sample_rate as usize * MAX_BUFFERED_SECONDS;
+        if self.samples.len() > limit {
+            self.samples.drain(..self.samples.len() - limit);
+        }
+    }
+
+    // Finished frames (one sample per channel) waiting to be read
+    pub fn available(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    // Fills `out` with interleaved frames and returns how many frames were written
+    pub fn read_f32(&mut self, out: &mut [f32]) -> usize {
+        let channels = self.config.channels.count();
+        let frames = (out.len() / channels).min(self.samples.len());
+        for (frame, sample) in out.chunks_exact_mut(channels).zip(self.samples.drain(..frames)) {
+            frame.fill(sample);
+        }
+        frames
+    }
+
+    pub fn read_i16(&mut self, out: &mut [i16]) -> usize {
+        let channels = self.config.channels.count();
+        let frames = (out.len() / channels).min(self.samples.len());
+        for (frame, sample) in out.chunks_exact_mut(channels).zip(self.samples.drain(..frames)) {
+            frame.fill(to_i16(sample));
+        }
+        frames
+    }
+}
+
+pub fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * 32767.0) as i16
+}
+
+// Rate control for hosts that play through a queue: given how full the queue is
+// (0.0 to 1.0) it returns a factor for set_rate_control that steers the queue
+// back to half full, never changing the pitch by more than `max_deviation`
+// (0.005 is inaudible).
+pub fn dynamic_rate(fill: f64, max_deviation: f64) -> f64 {
+    1.0 + max_deviation * (1.0 - 2.0 * fill.clamp(0.0, 1.0))
+}
+
+// Windowed-sinc impulses, one per phase, each summing to 1. The cutoff sits a
+// little under the output's Nyquist frequency.
+fn build_kernels() -> Vec<[f32; WIDTH]> {
+    const CUTOFF: f64 = 0.9;
+    (0..=PHASES)
+        .map(|phase| {
+            let center = (WIDTH / 2) as f64 + phase as f64 / PHASES as f64;
+            let mut kernel = [0.0; WIDTH];
+            for (tap, value) in kernel.iter_mut().enumerate() {
+                let x = tap as f64 - center;
+                if x.abs() >= (WIDTH / 2) as f64 {
+                    continue;
+                }
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x * CUTOFF).sin() / (PI * x * CUTOFF) };
+                let angle = PI * x / (WIDTH / 2) as f64;
+                let window = 0.42 + 0.5 * angle.cos() + 0.08 * (2.0 * angle).cos(); // Blackman
+                *value = sinc * window;
+            }
+            let total: f64 = kernel.iter().sum();
+            kernel.map(|value| (value / total) as f32)
+        })
+        .collect()
+}
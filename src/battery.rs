@@ -0,0 +1,161 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::cartridge::Header;
+use crate::mapper::Mapper;
+
+// Autosave every five seconds of NTSC play
+pub const DEFAULT_AUTOSAVE_FRAMES: u32 = 300;
+
+// Where a cartridge's battery-backed memory lives between sessions. Embedders
+// implement this to keep saves somewhere other than next to the ROM.
+pub trait SaveStorage {
+    // Returns None when nothing has been saved yet
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>>;
+
+    fn store(&mut self, data: &[u8]) -> io::Result<()>;
+}
+
+// A .sav file. Saves are written to a temporary file, synced and renamed over
+// the old one, so a crash or power cut never leaves half a save behind.
+pub struct SaveFile {
+    path: PathBuf,
+}
+
+impl SaveFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        SaveFile { path: path.as_ref().to_path_buf() }
+    }
+
+    // game.nes saves to game.sav in the same directory
+    pub fn beside_rom<P: AsRef<Path>>(rom_path: P) -> Self {
+        Self::new(rom_path.as_ref().with_extension("sav"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl SaveStorage for SaveFile {
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn store(&mut self, data: &[u8]) -> io::Result<()> {
+        let temporary = self.path.with_extension("sav.tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+// The battery-backed part of the inserted cartridge's memory: the NVRAM that
+// follows any volatile PRG-RAM (or all of it on iNES 1.0 boards with the battery
+// bit), or the whole EEPROM on boards that have one, and when it was last written out.
+pub struct Battery {
+    range: Range<usize>,
+    storage: Option<Box<dyn SaveStorage>>,
+    saved: Vec<u8>, // Contents at the last store, so unchanged saves aren't rewritten
+    autosave_frames: u32,
+    frames: u32,
+    error: Option<io::Error>,
+}
+
+impl Battery {
+    // None for cartridges without battery-backed memory
+    pub fn new(header: &Header) -> Option<Self> {
+        if header.prg_nvram_size == 0 {
+            return None;
+        }
+        Some(Battery {
+            range: header.prg_ram_size..header.prg_ram_size + header.prg_nvram_size,
+            storage: None,
+            saved: Vec::new(),
+            autosave_frames: DEFAULT_AUTOSAVE_FRAMES,
+            frames: 0,
+            error: None,
+        })
+    }
+
+    // The battery-backed range, clipped to the RAM the board actually has
+    fn span(&self, len: usize) -> Range<usize> {
+        self.range.start.min(len)..self.range.end.min(len)
+    }
+
+    pub fn data<'a>(&self, mapper: &'a dyn Mapper) -> &'a [u8] {
+        if !mapper.eeprom().is_empty() {
+            return mapper.eeprom();
+        }
+        let ram = mapper.prg_ram();
+        &ram[self.span(ram.len())]
+    }
+
+    // Copies a save into the cartridge. Short saves fill what they cover; extra bytes are ignored.
+    pub fn load(&mut self, mapper: &mut dyn Mapper, data: &[u8]) {
+        let target = if mapper.eeprom().is_empty() {
+            let span = self.span(mapper.prg_ram().len());
+            &mut mapper.prg_ram_mut()[span]
+        } else {
+            mapper.eeprom_mut()
+        };
+        let len = target.len().min(data.len());
+        target[..len].copy_from_slice(&data[..len]);
+        self.saved = self.data(mapper).to_vec();
+    }
+
+    // Switches to a new storage and loads whatever it already holds
+    pub fn attach(&mut self, mapper: &mut dyn Mapper, mut storage: Box<dyn SaveStorage>) -> io::Result<()> {
+        if let Some(data) = storage.load()? {
+            self.load(mapper, &data);
+        } else {
+            self.saved = self.data(mapper).to_vec();
+        }
+        self.storage = Some(storage);
+        Ok(())
+    }
+
+    // Writes the save out if it changed since the last store
+    pub fn flush(&mut self, mapper: &dyn Mapper) -> io::Result<()> {
+        let data = self.data(mapper);
+        if let Some(storage) = self.storage.as_mut() {
+            if data != self.saved.as_slice() {
+                storage.store(data)?;
+                self.saved = data.to_vec();
+            }
+        }
+        Ok(())
+    }
+
+    // 0 turns autosave off
+    pub fn set_autosave_interval(&mut self, frames: u32) {
+        self.autosave_frames = frames;
+        self.frames = 0;
+    }
+
+    // Called once per frame. A failed autosave is kept for take_error and retried next interval.
+    pub fn end_frame(&mut self, mapper: &dyn Mapper) {
+        if self.autosave_frames == 0 {
+            return;
+        }
+        self.frames += 1;
+        if self.frames >= self.autosave_frames {
+            self.frames = 0;
+            if let Err(err) = self.flush(mapper) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::ppu_bus::Mirroring;
+use crate::region::Region;
+
+pub const HEADER_SIZE: usize = 16;
+const PRG_ROM_UNIT: usize = 16 * 1024;
+const CHR_ROM_UNIT: usize = 8 * 1024;
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    InvalidHeader,
+    Truncated { expected: usize, actual: usize },
+    NoPrgRom,
+    SizeOverflow, // An NES 2.0 exponent-form size too large to address
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "could not read ROM: {}", err),
+            RomError::InvalidHeader => write!(f, "not an iNES or NES 2.0 file"),
+            RomError::Truncated { expected, actual } => {
+                write!(f, "ROM is truncated: expected {} bytes, got {}", expected, actual)
+            }
+            RomError::NoPrgRom => write!(f, "ROM has no PRG-ROM"),
+            RomError::SizeOverflow => write!(f, "ROM size in the header is too large"),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+// The 16-byte iNES / NES 2.0 header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,   // Volatile PRG-RAM
+    pub prg_nvram_size: usize, // Battery-backed PRG-RAM or EEPROM
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: Option<Region>, // Only NES 2.0 headers specify the region
+    pub console_type: u8,
+    pub default_expansion_device: u8,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Header, RomError> {
+        if data.len() < HEADER_SIZE || &data[0..4] != b"NES\x1A" {
+            return Err(RomError::InvalidHeader);
+        }
+
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let nes2 = flags7 & 0x0C == 0x08;
+
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flags6 & 0x02 != 0;
+        let trainer = flags6 & 0x04 != 0;
+
+        if nes2 {
+            let mapper = (flags6 >> 4) as u16 | (flags7 & 0xF0) as u16 | ((data[8] & 0x0F) as u16) << 8;
+            Ok(Header {
+                nes2,
+                mapper,
+                submapper: data[8] >> 4,
+                prg_rom_size: Self::nes2_rom_size(data[4], data[9] & 0x0F, PRG_ROM_UNIT)?,
+                chr_rom_size: Self::nes2_rom_size(data[5], data[9] >> 4, CHR_ROM_UNIT)?,
+                prg_ram_size: Self::nes2_ram_size(data[10] & 0x0F),
+                prg_nvram_size: Self::nes2_ram_size(data[10] >> 4),
+                chr_ram_size: Self::nes2_ram_size(data[11] & 0x0F),
+                chr_nvram_size: Self::nes2_ram_size(data[11] >> 4),
+                mirroring,
+                battery,
+                trainer,
+                timing: Some(Region::from_nes2_timing(data[12])),
+                console_type: flags7 & 0x03,
+                default_expansion_device: data[15] & 0x3F,
+            })
+        } else {
+            // Old dumps often have garbage such as "DiskDude!" in bytes 7-15
+            let clean = data[12..16].iter().all(|&b| b == 0);
+            let mapper_high = if clean { flags7 & 0xF0 } else { 0 };
+            let prg_ram_size = (data[8].max(1) as usize) * 8 * 1024;
+            let chr_rom_size = data[5] as usize * CHR_ROM_UNIT;
+            Ok(Header {
+                nes2,
+                mapper: ((flags6 >> 4) | mapper_high) as u16,
+                submapper: 0,
+                prg_rom_size: data[4] as usize * PRG_ROM_UNIT,
+                chr_rom_size,
+                prg_ram_size: if battery { 0 } else { prg_ram_size },
+                prg_nvram_size: if battery { prg_ram_size } else { 0 },
+                chr_ram_size: if chr_rom_size == 0 { CHR_ROM_UNIT } else { 0 },
+                chr_nvram_size: 0,
+                mirroring,
+                battery,
+                trainer,
+                timing: None,
+                console_type: if clean { flags7 & 0x03 } else { 0 },
+                default_expansion_device: 0,
+            })
+        }
+    }
+
+    // Region requested by the header, if it has an opinion
+    pub fn region(&self) -> Option<Region> {
+        self.timing
+    }
+
+    // Size in bytes of the data (trainer, PRG and CHR) that follows the header
+    pub fn rom_data_size(&self) -> usize {
+        let trainer = if self.trainer { 512 } else { 0 };
+        self.prg_rom_size.saturating_add(self.chr_rom_size).saturating_add(trainer)
+    }
+
+    fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> Result<usize, RomError> {
+        if msb == 0x0F {
+            // Exponent-multiplier notation: 2^E * (MM * 2 + 1)
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = (lsb & 0x03) as usize * 2 + 1;
+            1usize
+                .checked_shl(exponent)
+                .and_then(|size| size.checked_mul(multiplier))
+                .ok_or(RomError::SizeOverflow)
+        } else {
+            Ok(((msb as usize) << 8 | lsb as usize) * unit)
+        }
+    }
+
+    fn nes2_ram_size(shift: u8) -> usize {
+        if shift == 0 {
+            0
+        } else {
+            64 << shift
+        }
+    }
+}
+
+// A parsed .nes file
+pub struct Rom {
+    pub header: Header,
+    pub trainer: Option<Vec<u8>>, // 512 bytes loaded at $7000 before the game starts
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+}
+
+impl Rom {
+    pub fn parse(data: &[u8]) -> Result<Rom, RomError> {
+        let header = Header::parse(data)?;
+        if header.prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+        let expected = HEADER_SIZE.saturating_add(header.rom_data_size());
+        if data.len() < expected {
+            return Err(RomError::Truncated { expected, actual: data.len() });
+        }
+
+        let mut offset = HEADER_SIZE;
+        let trainer = if header.trainer {
+            offset += 512;
+            Some(data[HEADER_SIZE..offset].to_vec())
+        } else {
+            None
+        };
+        let prg_rom = data[offset..offset + header.prg_rom_size].to_vec();
+        offset += header.prg_rom_size;
+        let chr_rom = data[offset..offset + header.chr_rom_size].to_vec();
+
+        Ok(Rom { header, trainer, prg_rom, chr_rom })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let data = fs::read(path)?;
+        Self::parse(&data)
+    }
+
+    // CRC32 of the PRG-ROM followed by the CHR-ROM, the usual way to identify a dump
+    pub fn crc32(&self) -> u32 {
+        let mut crc = 0xFFFF_FFFFu32;
+        for &byte in self.prg_rom.iter().chain(self.chr_rom.iter()) {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    // Power-on contents of $6000-$7FFF, with the trainer at $7000 if there is one
+    pub fn prg_ram(&self) -> Vec<u8> {
+        let size = self.header.prg_ram_size + self.header.prg_nvram_size;
+        let mut ram = vec![0; size];
+        if let Some(trainer) = &self.trainer {
+            if ram.len() < 0x2000 {
+                ram.resize(0x2000, 0);
+            }
+            ram[0x1000..0x1200].copy_from_slice(trainer);
+        }
+        ram
+    }
+
+    // Pattern table memory: the CHR-ROM, or CHR-RAM when the board has none.
+    // Returns the memory and whether it is writable.
+    pub fn chr_memory(&self) -> (Vec<u8>, bool) {
+        if !self.chr_rom.is_empty() {
+            return (self.chr_rom.clone(), false);
+        }
+        let size = (self.header.chr_ram_size + self.header.chr_nvram_size).max(0x2000);
+        (vec![0; size], true)
+    }
+}
@@ -0,0 +1,65 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// A standard joypad: a latch that copies the buttons while $4016 bit 0 is high,
+// and a shift register that hands them out one per read, A first.
+#[derive(Clone, Debug, Default)]
+pub struct Controller {
+    pub buttons: u8, // Bit 0 A, B, Select, Start, Up, Down, Left, bit 7 Right
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub const A: u8 = 0x01;
+    pub const B: u8 = 0x02;
+    pub const SELECT: u8 = 0x04;
+    pub const START: u8 = 0x08;
+    pub const UP: u8 = 0x10;
+    pub const DOWN: u8 = 0x20;
+    pub const LEFT: u8 = 0x40;
+    pub const RIGHT: u8 = 0x80;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.buttons);
+        state.u8(self.shift);
+        state.bool(self.strobe);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.buttons = state.u8()?;
+        self.shift = state.u8()?;
+        self.strobe = state.bool()?;
+        Ok(())
+    }
+
+    // $4016 writes reach both ports. The latch keeps following the buttons for as
+    // long as the strobe is high, so the register holds whatever was pressed when it fell.
+    pub fn write_strobe(&mut self, value: u8) {
+        if self.strobe || value & 0x01 != 0 {
+            self.shift = self.buttons;
+        }
+        self.strobe = value & 0x01 != 0;
+    }
+
+    // Bit 0 of $4016/$4017. After all eight buttons the register reads back 1s.
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons & 0x01;
+        }
+        let bit = self.shift & 0x01;
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            self.buttons & 0x01
+        } else {
+            self.shift & 0x01
+        }
+    }
+}
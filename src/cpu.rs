@@ -1,5 +1,6 @@
 use crate::memory::Memory;
-use crate::opcodes::build_opcode_table;
+use crate::opcodes::opcode_table;
+use crate::state::{StateError, StateReader, StateWriter};
 pub use crate::opcodes::AddressingMode;
 
 pub struct CPU {
@@ -9,6 +10,14 @@ pub struct CPU {
     pub pc: u16, //Program Counter
     pub sp: u8, //Stack Pointer
     pub p:  u8, //Status Register
+    pub cycles: u64, //Total CPU cycles executed
+    pub stall: u32, //Cycles the CPU is halted for by DMA
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
@@ -20,21 +29,46 @@ impl CPU {
             pc: 0x0000, // This should be set by a reset method fetching the vector from memory.
             sp: 0xFD,   // Common startup value for the stack pointer.
             p: 0x34,    // Initial status register value (interrupts disabled).
+            cycles: 0,
+            stall: 0,
         }
     }
 
-    pub fn reset(&mut self, memory: &Memory) -> () {
-        let low_byte = memory.read(0xFFFC);
-        let high_byte = memory.read(0xFFFD);
+    pub fn reset(&mut self, memory: &Memory) {
+        let low_byte = memory.peek(0xFFFC);
+        let high_byte = memory.peek(0xFFFD);
 
         self.pc = (high_byte as u16) << 8 | (low_byte as u16);
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.a);
+        state.u8(self.x);
+        state.u8(self.y);
+        state.u16(self.pc);
+        state.u8(self.sp);
+        state.u8(self.p);
+        state.u64(self.cycles);
+        state.u32(self.stall);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.a = state.u8()?;
+        self.x = state.u8()?;
+        self.y = state.u8()?;
+        self.pc = state.u16()?;
+        self.sp = state.u8()?;
+        self.p = state.u8()?;
+        self.cycles = state.u64()?;
+        self.stall = state.u32()?;
+        Ok(())
+    }
+
     pub fn get_pc(&self) -> u16 {
         self.pc
     }
 
-    fn get_operand_address(&self, memory: &Memory, mode: &AddressingMode) -> u16 {
+    fn get_operand_address(&self, memory: &mut Memory, mode: &AddressingMode) -> u16 {
         match mode {
             AddressingMode::Immediate => self.pc,
             AddressingMode::ZeroPage => memory.read(self.pc) as u16,
@@ -48,23 +82,23 @@ impl CPU {
             }
             AddressingMode::Absolute => {
                 let low_byte = memory.read(self.pc) as u16;
-                let high_byte = memory.read(self.pc + 1) as u16;
+                let high_byte = memory.read(self.pc.wrapping_add(1)) as u16;
                 (high_byte << 8) | low_byte
             }
             AddressingMode::AbsoluteX => {
                 let low_byte = memory.read(self.pc) as u16;
-                let high_byte = memory.read(self.pc + 1) as u16;
+                let high_byte = memory.read(self.pc.wrapping_add(1)) as u16;
                 ((high_byte << 8) | low_byte).wrapping_add(self.x as u16)
             }
             AddressingMode::AbsoluteY => {
                 let low_byte = memory.read(self.pc) as u16;
-                let high_byte = memory.read(self.pc + 1) as u16;
+                let high_byte = memory.read(self.pc.wrapping_add(1)) as u16;
                 ((high_byte << 8) | low_byte).wrapping_add(self.y as u16)
             }
             AddressingMode::Indirect => {
-                let ptr = memory.read(self.pc) as u16 | (memory.read(self.pc + 1) as u16) << 8;
+                let ptr = memory.read(self.pc) as u16 | (memory.read(self.pc.wrapping_add(1)) as u16) << 8;
                 let low_byte = memory.read(ptr) as u16;
-                let high_byte = memory.read((ptr & 0xFF00) | ((ptr + 1) & 0x00FF)) as u16;
+                let high_byte = memory.read((ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF)) as u16;
                 (high_byte << 8) | low_byte
             }
             AddressingMode::IndexedIndirect => {
@@ -88,12 +122,66 @@ impl CPU {
         }
     }
 
-    pub fn execute_instruction(&mut self, memory: &mut Memory) {
-         let opcode = memory.read(self.pc); // Fetch the opcode
-        self.pc += 1; // Increment PC to the next byte
+    // Runs one instruction, services a pending NMI or IRQ, or burns the cycles the
+    // CPU is stalled for by DMA. Returns the number of CPU cycles that elapsed.
+    pub fn step(&mut self, memory: &mut Memory) -> u32 {
+        let cycles = if self.stall > 0 {
+            let cycles = std::mem::take(&mut self.stall);
+            self.cycles += cycles as u64;
+            cycles
+        } else if memory.take_nmi() {
+            self.interrupt(memory, 0xFFFA)
+        } else if memory.irq() && self.p & 0x04 == 0 {
+            self.interrupt(memory, 0xFFFE)
+        } else {
+            let cycles = self.execute_instruction(memory);
+
+            // A write to $4014 halts the CPU while 256 bytes are copied into OAM.
+            // The DMA needs one extra alignment cycle when it starts on an odd cycle.
+            if let Some(page) = memory.take_oam_dma() {
+                let base = (page as u16) << 8;
+                for offset in 0..256 {
+                    let value = memory.read(base | offset);
+                    memory.write(0x2004, value);
+                }
+                self.stall += 513 + (self.cycles % 2) as u32;
+            }
+            cycles
+        };
+
+        memory.tick(cycles);
+
+        // Other DMA units (e.g. DMC sample fetches) stall the CPU through the bus.
+        self.stall += memory.take_stall();
+
+        cycles
+    }
+
+    // Pushes PC and status, then jumps through the given vector. Takes 7 cycles.
+    fn interrupt(&mut self, memory: &mut Memory, vector: u16) -> u32 {
+        memory.write(0x0100 + self.sp as u16, (self.pc >> 8) as u8); // Push high byte of PC
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write(0x0100 + self.sp as u16, (self.pc & 0xFF) as u8); // Push low byte of PC
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write(0x0100 + self.sp as u16, (self.p & !0x10) | 0x20); // Push status with B clear
+        self.sp = self.sp.wrapping_sub(1);
+        self.p |= 0x04; // Set interrupt disable flag
+        self.pc = (memory.read(vector + 1) as u16) << 8 | memory.read(vector) as u16;
+        self.cycles += 7;
+        7
+    }
+
+    pub fn execute_instruction(&mut self, memory: &mut Memory) -> u32 {
+        let opcode = memory.read(self.pc); // Fetch the opcode
+        self.pc = self.pc.wrapping_add(1); // Increment PC to the next byte
+
+        if let Some(opcode_data) = &opcode_table()[opcode as usize] {
+            let mode = &opcode_data.addressing_mode;
+            let mut cycles = opcode_data.cycles as u32;
+            if Self::has_page_cross_penalty(opcode_data.name) && self.page_crossed(memory, mode) {
+                cycles += 1;
+            }
 
-        let opcode_table = build_opcode_table(); // Fetch opcode table
-        if let Some(opcode_data) = &opcode_table[opcode as usize] {
             match opcode_data.name {
                 "LDA" => self.lda(memory, &opcode_data.addressing_mode),
                 "STA" => self.sta(memory, &opcode_data.addressing_mode),
@@ -114,14 +202,14 @@ impl CPU {
                 "PHP" => self.php(memory),
                 "PLA" => self.pla(memory),
                 "PLP" => self.plp(memory),
-                "BEQ" => self.beq(memory),
-                "BNE" => self.bne(memory),
-                "BMI" => self.bmi(memory),
-                "BPL" => self.bpl(memory),
-                "BCS" => self.bcs(memory),
-                "BCC" => self.bcc(memory),
-                "BVS" => self.bvs(memory),
-                "BVC" => self.bvc(memory),
+                "BEQ" => cycles += self.beq(memory),
+                "BNE" => cycles += self.bne(memory),
+                "BMI" => cycles += self.bmi(memory),
+                "BPL" => cycles += self.bpl(memory),
+                "BCS" => cycles += self.bcs(memory),
+                "BCC" => cycles += self.bcc(memory),
+                "BVS" => cycles += self.bvs(memory),
+                "BVC" => cycles += self.bvc(memory),
                 "JMP" => self.jmp(memory, &opcode_data.addressing_mode),
                 "JSR" => self.jsr(memory),
                 "RTS" => self.rts(memory),
@@ -143,15 +231,90 @@ impl CPU {
                 "SRE" => self.sre(memory, &opcode_data.addressing_mode),
                 "RLA" => self.rla(memory, &opcode_data.addressing_mode),
                 "RRA" => self.rra(memory, &opcode_data.addressing_mode),
+                "SBC" => self.sbc(memory, &opcode_data.addressing_mode),
+                "LDX" => self.ldx(memory, &opcode_data.addressing_mode),
+                "LDY" => self.ldy(memory, &opcode_data.addressing_mode),
+                "STX" => self.stx(memory, &opcode_data.addressing_mode),
+                "STY" => self.sty(memory, &opcode_data.addressing_mode),
+                "ASL" => self.asl(memory, &opcode_data.addressing_mode),
+                "LSR" => self.lsr(memory, &opcode_data.addressing_mode),
+                "ROL" => self.rol(memory, &opcode_data.addressing_mode),
+                "ROR" => self.ror(memory, &opcode_data.addressing_mode),
+                "BIT" => self.bit(memory, &opcode_data.addressing_mode),
+                "TAX" => self.tax(),
+                "TAY" => self.tay(),
+                "TSX" => self.tsx(),
+                "TXA" => self.txa(),
+                "TXS" => self.txs(),
+                "TYA" => self.tya(),
+                "SHX" => self.shx(memory, &opcode_data.addressing_mode),
+                "STP" => self.stp(),
                 // Add cases for other opcodes
                 _ => panic!("Unimplemented opcode: {}", opcode_data.name),
             }
+
+            // Instructions that load PC themselves have already moved past their operand
+            if !Self::sets_pc(opcode_data.name) {
+                self.pc = self.pc.wrapping_add(mode.operand_length());
+            }
+
+            self.cycles += cycles as u64;
+            cycles
         } else {
             panic!("Unknown opcode: 0x{:X}", opcode);
         }
     }
 
-    fn lda(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn sets_pc(name: &str) -> bool {
+        matches!(
+            name,
+            "JMP" | "JSR" | "RTS" | "RTI" | "BRK"
+                | "BEQ" | "BNE" | "BMI" | "BPL" | "BCS" | "BCC" | "BVS" | "BVC"
+        )
+    }
+
+    // Read instructions take an extra cycle when indexing crosses a page boundary
+    fn has_page_cross_penalty(name: &str) -> bool {
+        matches!(
+            name,
+            "LDA" | "LDX" | "LDY" | "ADC" | "SBC" | "AND" | "ORA" | "EOR" | "CMP" | "LAX" | "NOP"
+        )
+    }
+
+    fn page_crossed(&self, memory: &mut Memory, mode: &AddressingMode) -> bool {
+        let (base, index) = match mode {
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                let low_byte = memory.read(self.pc) as u16;
+                let high_byte = memory.read(self.pc.wrapping_add(1)) as u16;
+                let index = if let AddressingMode::AbsoluteX = mode { self.x } else { self.y };
+                ((high_byte << 8) | low_byte, index)
+            }
+            AddressingMode::IndirectIndexed => {
+                let ptr = memory.read(self.pc) as u16;
+                let low_byte = memory.read(ptr) as u16;
+                let high_byte = memory.read(ptr.wrapping_add(1) & 0x00FF) as u16;
+                ((high_byte << 8) | low_byte, self.y)
+            }
+            _ => return false,
+        };
+        (base & 0xFF00) != (base.wrapping_add(index as u16) & 0xFF00)
+    }
+
+    // Moves PC past the offset byte and takes the branch if the condition holds.
+    // Returns the extra cycles: one for a taken branch, one more across a page.
+    fn branch(&mut self, memory: &mut Memory, condition: bool) -> u32 {
+        let target = self.get_operand_address(memory, &AddressingMode::Relative);
+        self.pc = self.pc.wrapping_add(1);
+        if !condition {
+            return 0;
+        }
+
+        let extra = if (self.pc & 0xFF00) != (target & 0xFF00) { 2 } else { 1 };
+        self.pc = target;
+        extra
+    }
+
+    fn lda(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         self.a = memory.read(addr);
         self.set_zero_and_negative_flags(self.a);
@@ -162,11 +325,23 @@ impl CPU {
         memory.write(addr, self.a);
     }
 
-    fn adc(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn adc(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let value = memory.read(addr);
+        self.add_with_carry(value);
+    }
+
+    fn sbc(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        let addr = self.get_operand_address(memory, mode);
+        let value = memory.read(addr);
+        self.add_with_carry(!value); // A - M - (1 - C) == A + !M + C
+    }
+
+    // Shared by ADC, SBC and the unofficial opcodes built on them
+    fn add_with_carry(&mut self, value: u8) {
         let carry = self.p & 0x01;
         let result = self.a as u16 + value as u16 + carry as u16;
+        let overflow = (self.a ^ result as u8) & (value ^ result as u8) & 0x80 != 0;
 
         self.a = result as u8;
         self.set_zero_and_negative_flags(self.a);
@@ -176,6 +351,11 @@ impl CPU {
         } else {
             self.p &= !0x01; // Clear carry flag
         }
+        if overflow {
+            self.p |= 0x40; // Set overflow flag
+        } else {
+            self.p &= !0x40; // Clear overflow flag
+        }
     }
 
     // Helper method for setting zero and negative flags
@@ -192,25 +372,25 @@ impl CPU {
         }
     }
 
-    fn and(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn and(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         self.a &= memory.read(addr);
         self.set_zero_and_negative_flags(self.a);
     }
     
-    fn ora(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn ora(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         self.a |= memory.read(addr);
         self.set_zero_and_negative_flags(self.a);
     }
     
-    fn eor(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn eor(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         self.a ^= memory.read(addr);
         self.set_zero_and_negative_flags(self.a);
     }
 
-    fn cmp(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn cmp(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let value = memory.read(addr);
         let result = self.a.wrapping_sub(value);
@@ -223,7 +403,7 @@ impl CPU {
         }
     }
     
-    fn cpx(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn cpx(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let value = memory.read(addr);
         let result = self.x.wrapping_sub(value);
@@ -236,7 +416,7 @@ impl CPU {
         }
     }
     
-    fn cpy(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn cpy(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let value = memory.read(addr);
         let result = self.y.wrapping_sub(value);
@@ -252,6 +432,7 @@ impl CPU {
     fn inc(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let mut value = memory.read(addr);
+        memory.write(addr, value); // Dummy write of the unmodified value
         value = value.wrapping_add(1);
         memory.write(addr, value);
         self.set_zero_and_negative_flags(value);
@@ -270,6 +451,7 @@ impl CPU {
     fn dec(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let mut value = memory.read(addr);
+        memory.write(addr, value); // Dummy write of the unmodified value
         value = value.wrapping_sub(1);
         memory.write(addr, value);
         self.set_zero_and_negative_flags(value);
@@ -291,97 +473,74 @@ impl CPU {
     }
     
     fn php(&mut self, memory: &mut Memory) {
-        memory.write(0x0100 + self.sp as u16, self.p);
+        memory.write(0x0100 + self.sp as u16, self.p | 0x30); // B and bit 5 are set on the stack
         self.sp = self.sp.wrapping_sub(1);
     }
     
-    fn pla(&mut self, memory: &Memory) {
+    fn pla(&mut self, memory: &mut Memory) {
         self.sp = self.sp.wrapping_add(1);
         self.a = memory.read(0x0100 + self.sp as u16);
         self.set_zero_and_negative_flags(self.a);
     }
     
-    fn plp(&mut self, memory: &Memory) {
+    fn plp(&mut self, memory: &mut Memory) {
         self.sp = self.sp.wrapping_add(1);
-        self.p = memory.read(0x0100 + self.sp as u16);
+        self.p = (memory.read(0x0100 + self.sp as u16) & !0x10) | 0x20; // B only exists on the stack
     }
 
-    fn beq(&mut self, memory: &Memory) {
-        if self.p & 0x02 != 0 { // Check if zero flag is set
-            let addr = self.get_operand_address(memory, &AddressingMode::Relative);
-            self.pc = addr;
-        }
+    fn beq(&mut self, memory: &mut Memory) -> u32 {
+        self.branch(memory, self.p & 0x02 != 0) // Check if zero flag is set
     }
     
-    fn bne(&mut self, memory: &Memory) {
-        if self.p & 0x02 == 0 { // Check if zero flag is clear
-            let addr = self.get_operand_address(memory, &AddressingMode::Relative);
-            self.pc = addr;
-        }
+    fn bne(&mut self, memory: &mut Memory) -> u32 {
+        self.branch(memory, self.p & 0x02 == 0) // Check if zero flag is clear
     }
     
-    fn bmi(&mut self, memory: &Memory) {
-        if self.p & 0x80 != 0 { // Check if negative flag is set
-            let addr = self.get_operand_address(memory, &AddressingMode::Relative);
-            self.pc = addr;
-        }
+    fn bmi(&mut self, memory: &mut Memory) -> u32 {
+        self.branch(memory, self.p & 0x80 != 0) // Check if negative flag is set
     }
     
-    fn bpl(&mut self, memory: &Memory) {
-        if self.p & 0x80 == 0 { // Check if negative flag is clear
-            let addr = self.get_operand_address(memory, &AddressingMode::Relative);
-            self.pc = addr;
-        }
+    fn bpl(&mut self, memory: &mut Memory) -> u32 {
+        self.branch(memory, self.p & 0x80 == 0) // Check if negative flag is clear
     }
     
-    fn bcs(&mut self, memory: &Memory) {
-        if self.p & 0x01 != 0 { // Check if carry flag is set
-            let addr = self.get_operand_address(memory, &AddressingMode::Relative);
-            self.pc = addr;
-        }
+    fn bcs(&mut self, memory: &mut Memory) -> u32 {
+        self.branch(memory, self.p & 0x01 != 0) // Check if carry flag is set
     }
     
-    fn bcc(&mut self, memory: &Memory) {
-        if self.p & 0x01 == 0 { // Check if carry flag is clear
-            let addr = self.get_operand_address(memory, &AddressingMode::Relative);
-            self.pc = addr;
-        }
+    fn bcc(&mut self, memory: &mut Memory) -> u32 {
+        self.branch(memory, self.p & 0x01 == 0) // Check if carry flag is clear
     }
     
-    fn bvs(&mut self, memory: &Memory) {
-        if self.p & 0x40 != 0 { // Check if overflow flag is set
-            let addr = self.get_operand_address(memory, &AddressingMode::Relative);
-            self.pc = addr;
-        }
+    fn bvs(&mut self, memory: &mut Memory) -> u32 {
+        self.branch(memory, self.p & 0x40 != 0) // Check if overflow flag is set
     }
     
-    fn bvc(&mut self, memory: &Memory) {
-        if self.p & 0x40 == 0 { // Check if overflow flag is clear
-            let addr = self.get_operand_address(memory, &AddressingMode::Relative);
-            self.pc = addr;
-        }
+    fn bvc(&mut self, memory: &mut Memory) -> u32 {
+        self.branch(memory, self.p & 0x40 == 0) // Check if overflow flag is clear
     }
 
-    fn jmp(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn jmp(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         self.pc = addr;
     }
     
     fn jsr(&mut self, memory: &mut Memory) {
         let addr = self.get_operand_address(memory, &AddressingMode::Absolute);
+        let return_addr = self.pc.wrapping_add(1); // Address of the last byte of JSR
+        memory.write(0x0100 + self.sp as u16, (return_addr >> 8) as u8); // Push high byte of PC
         self.sp = self.sp.wrapping_sub(1);
-        memory.write(0x0100 + self.sp as u16, ((self.pc >> 8) & 0xFF) as u8); // Push high byte of PC
+        memory.write(0x0100 + self.sp as u16, (return_addr & 0xFF) as u8); // Push low byte of PC
         self.sp = self.sp.wrapping_sub(1);
-        memory.write(0x0100 + self.sp as u16, (self.pc & 0xFF) as u8); // Push low byte of PC
         self.pc = addr;
     }
     
-    fn rts(&mut self, memory: &Memory) {
+    fn rts(&mut self, memory: &mut Memory) {
+        self.sp = self.sp.wrapping_add(1);
         let low_byte = memory.read(0x0100 + self.sp as u16) as u16;
         self.sp = self.sp.wrapping_add(1);
         let high_byte = memory.read(0x0100 + self.sp as u16) as u16;
-        self.sp = self.sp.wrapping_add(1);
-        self.pc = (high_byte << 8) | low_byte;
+        self.pc = ((high_byte << 8) | low_byte).wrapping_add(1);
     }
 
     fn clc(&mut self) {
@@ -417,20 +576,20 @@ impl CPU {
     }
     
     fn brk(&mut self, memory: &mut Memory) {
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         memory.write(0x0100 + self.sp as u16, (self.pc >> 8) as u8); // Push high byte of PC
         self.sp = self.sp.wrapping_sub(1);
         memory.write(0x0100 + self.sp as u16, (self.pc & 0xFF) as u8); // Push low byte of PC
         self.sp = self.sp.wrapping_sub(1);
-        memory.write(0x0100 + self.sp as u16, self.p | 0x10); // Push status register with B flag set
+        memory.write(0x0100 + self.sp as u16, self.p | 0x30); // Push status register with B flag set
         self.sp = self.sp.wrapping_sub(1);
         self.p |= 0x04; // Set interrupt disable flag
         self.pc = (memory.read(0xFFFF) as u16) << 8 | memory.read(0xFFFE) as u16; // Fetch IRQ vector
     }
     
-    fn rti(&mut self, memory: &Memory) {
+    fn rti(&mut self, memory: &mut Memory) {
         self.sp = self.sp.wrapping_add(1);
-        self.p = memory.read(0x0100 + self.sp as u16);
+        self.p = (memory.read(0x0100 + self.sp as u16) & !0x10) | 0x20;
         self.sp = self.sp.wrapping_add(1);
         let low_byte = memory.read(0x0100 + self.sp as u16) as u16;
         self.sp = self.sp.wrapping_add(1);
@@ -438,7 +597,7 @@ impl CPU {
         self.pc = (high_byte << 8) | low_byte;
     }
 
-    fn lax(&mut self, memory: &Memory, mode: &AddressingMode) {
+    fn lax(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let value = memory.read(addr);
         self.a = value;
@@ -455,6 +614,7 @@ impl CPU {
     fn dcp(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let mut value = memory.read(addr);
+        memory.write(addr, value); // Dummy write of the unmodified value
         value = value.wrapping_sub(1);
         memory.write(addr, value);
 
@@ -471,25 +631,18 @@ impl CPU {
     fn isc(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let mut value = memory.read(addr);
+        memory.write(addr, value); // Dummy write of the unmodified value
         value = value.wrapping_add(1);
         memory.write(addr, value);
 
         // Perform subtraction with carry (same logic as SBC)
-        let carry = self.p & 0x01;
-        let result = self.a as u16 + (!value as u16) + carry as u16;
-        self.a = result as u8;
-        self.set_zero_and_negative_flags(self.a);
-
-        if result > 0xFF {
-            self.p |= 0x01; // Set carry flag
-        } else {
-            self.p &= !0x01; // Clear carry flag
-        }
+        self.add_with_carry(!value);
     }
 
     fn slo(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let mut value = memory.read(addr);
+        memory.write(addr, value); // Dummy write of the unmodified value
         self.p = (self.p & !0x01) | (value >> 7); // Set carry flag to high bit
         value <<= 1;
         memory.write(addr, value);
@@ -501,6 +654,7 @@ impl CPU {
     fn sre(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let mut value = memory.read(addr);
+        memory.write(addr, value); // Dummy write of the unmodified value
         self.p = (self.p & !0x01) | (value & 0x01); // Set carry flag to low bit
         value >>= 1;
         memory.write(addr, value);
@@ -512,6 +666,7 @@ impl CPU {
     fn rla(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let mut value = memory.read(addr);
+        memory.write(addr, value); // Dummy write of the unmodified value
         let carry_in = self.p & 0x01;
         self.p = (self.p & !0x01) | (value >> 7); // Set carry flag to high bit
         value = (value << 1) | carry_in;
@@ -524,21 +679,126 @@ impl CPU {
     fn rra(&mut self, memory: &mut Memory, mode: &AddressingMode) {
         let addr = self.get_operand_address(memory, mode);
         let mut value = memory.read(addr);
+        memory.write(addr, value); // Dummy write of the unmodified value
         let carry_in = (self.p & 0x01) << 7;
         self.p = (self.p & !0x01) | (value & 0x01); // Set carry flag to low bit
         value = (value >> 1) | carry_in;
         memory.write(addr, value);
 
-        let carry = self.p & 0x01;
-        let result = self.a as u16 + value as u16 + carry as u16;
-        self.a = result as u8;
-        self.set_zero_and_negative_flags(self.a);
+        self.add_with_carry(value);
+    }
 
-        if result > 0xFF {
+    fn ldx(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        let addr = self.get_operand_address(memory, mode);
+        self.x = memory.read(addr);
+        self.set_zero_and_negative_flags(self.x);
+    }
+
+    fn ldy(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        let addr = self.get_operand_address(memory, mode);
+        self.y = memory.read(addr);
+        self.set_zero_and_negative_flags(self.y);
+    }
+
+    fn stx(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        let addr = self.get_operand_address(memory, mode);
+        memory.write(addr, self.x);
+    }
+
+    fn sty(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        let addr = self.get_operand_address(memory, mode);
+        memory.write(addr, self.y);
+    }
+
+    // Shifts and rotates work on A in accumulator mode, otherwise on memory.
+    // The closure returns the new value and the bit shifted out into carry.
+    fn shift(&mut self, memory: &mut Memory, mode: &AddressingMode, op: impl Fn(u8, u8) -> (u8, bool)) {
+        let carry_in = self.p & 0x01;
+        let (result, carry_out) = if let AddressingMode::Accumulator = mode {
+            let (result, carry_out) = op(self.a, carry_in);
+            self.a = result;
+            (result, carry_out)
+        } else {
+            let addr = self.get_operand_address(memory, mode);
+            let value = memory.read(addr);
+            memory.write(addr, value); // Dummy write of the unmodified value
+            let (result, carry_out) = op(value, carry_in);
+            memory.write(addr, result);
+            (result, carry_out)
+        };
+
+        self.set_zero_and_negative_flags(result);
+        if carry_out {
             self.p |= 0x01; // Set carry flag
         } else {
             self.p &= !0x01; // Clear carry flag
         }
     }
 
+    fn asl(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        self.shift(memory, mode, |value, _| (value << 1, value & 0x80 != 0));
+    }
+
+    fn lsr(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        self.shift(memory, mode, |value, _| (value >> 1, value & 0x01 != 0));
+    }
+
+    fn rol(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        self.shift(memory, mode, |value, carry| ((value << 1) | carry, value & 0x80 != 0));
+    }
+
+    fn ror(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        self.shift(memory, mode, |value, carry| ((value >> 1) | (carry << 7), value & 0x01 != 0));
+    }
+
+    fn bit(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        let addr = self.get_operand_address(memory, mode);
+        let value = memory.read(addr);
+        if self.a & value == 0 {
+            self.p |= 0x02; // Set zero flag
+        } else {
+            self.p &= !0x02; // Clear zero flag
+        }
+        self.p = (self.p & !0xC0) | (value & 0xC0); // Copy bits 7 and 6 into N and V
+    }
+
+    fn tax(&mut self) {
+        self.x = self.a;
+        self.set_zero_and_negative_flags(self.x);
+    }
+
+    fn tay(&mut self) {
+        self.y = self.a;
+        self.set_zero_and_negative_flags(self.y);
+    }
+
+    fn tsx(&mut self) {
+        self.x = self.sp;
+        self.set_zero_and_negative_flags(self.x);
+    }
+
+    fn txa(&mut self) {
+        self.a = self.x;
+        self.set_zero_and_negative_flags(self.a);
+    }
+
+    fn txs(&mut self) {
+        self.sp = self.x; // TXS does not affect flags
+    }
+
+    fn tya(&mut self) {
+        self.a = self.y;
+        self.set_zero_and_negative_flags(self.a);
+    }
+
+    fn shx(&mut self, memory: &mut Memory, mode: &AddressingMode) {
+        let addr = self.get_operand_address(memory, mode);
+        let value = self.x & ((addr >> 8) as u8).wrapping_add(1);
+        memory.write(addr, value);
+    }
+
+    fn stp(&mut self) {
+        self.pc = self.pc.wrapping_sub(1); // The CPU locks up on this opcode
+    }
+
 }
\ No newline at end of file
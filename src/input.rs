@@ -0,0 +1,197 @@
+use crate::controller::Controller;
+use crate::keyboard::Keyboard;
+use crate::power_pad::PowerPad;
+use crate::ppu::PPU;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::vaus::Vaus;
+use crate::zapper::Zapper;
+
+// What is plugged into one of the two controller ports
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PortDevice {
+    #[default]
+    Controller,
+    FourScore, // Multitap: players 1 and 3 on port 1, 2 and 4 on port 2
+    Zapper,
+    Vaus,
+    PowerPad,
+    Empty,
+}
+
+// What is plugged into the Famicom's expansion port
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExpansionDevice {
+    #[default]
+    Empty,
+    Controllers, // Players 3 and 4 on D1 of $4016 and $4017
+    Vaus,
+    FamilyTrainer,
+    Keyboard,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Devices {
+    pub ports: [PortDevice; 2],
+    pub expansion: ExpansionDevice,
+}
+
+impl Devices {
+    // Byte 15 of an NES 2.0 header. Devices not emulated here get plain joypads.
+    pub fn from_nes2_expansion(value: u8) -> Devices {
+        let mut devices = Devices::default();
+        match value {
+            0x02 => devices.ports = [PortDevice::FourScore; 2],
+            0x03 => devices.expansion = ExpansionDevice::Controllers,
+            0x08 | 0x09 => devices.ports[1] = PortDevice::Zapper,
+            0x0B | 0x0C => devices.ports[1] = PortDevice::PowerPad,
+            0x0D | 0x0E => devices.expansion = ExpansionDevice::FamilyTrainer,
+            0x0F => devices.ports[1] = PortDevice::Vaus,
+            0x10 | 0x11 => devices.expansion = ExpansionDevice::Vaus,
+            0x23 => devices.expansion = ExpansionDevice::Keyboard,
+            _ => {}
+        }
+        devices
+    }
+}
+
+// Everything read through $4016/$4017. Only D0-D4 are driven; the bus supplies the rest.
+pub struct Input {
+    pub devices: Devices,
+    pub controllers: [Controller; 4], // Players 3 and 4 need a Four Score or the expansion port
+    pub zapper: Zapper,
+    pub vaus: Vaus,
+    pub power_pad: PowerPad,
+    pub keyboard: Keyboard,
+    four_score: [u32; 2], // 24-bit shift registers: two players then the signature
+    strobe: bool,
+    latch: u8, // Last $4016 write, which the expansion port sees in full
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            devices: Devices::default(),
+            controllers: [Controller::new(), Controller::new(), Controller::new(), Controller::new()],
+            zapper: Zapper::new(),
+            vaus: Vaus::new(),
+            power_pad: PowerPad::new(),
+            keyboard: Keyboard::new(),
+            four_score: [0; 2],
+            strobe: false,
+            latch: 0,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for controller in self.controllers.iter() {
+            controller.save_state(state);
+        }
+        state.u32(self.four_score[0]);
+        state.u32(self.four_score[1]);
+        state.bool(self.strobe);
+        state.u8(self.latch);
+        self.vaus.save_state(state);
+        self.power_pad.save_state(state);
+        self.keyboard.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        // Version 3 only had the two joypads
+        if state.version < 4 {
+            self.controllers[0].load_state(state)?;
+            return self.controllers[1].load_state(state);
+        }
+        for controller in self.controllers.iter_mut() {
+            controller.load_state(state)?;
+        }
+        self.four_score = [state.u32()?, state.u32()?];
+        self.strobe = state.bool()?;
+        self.latch = state.u8()?;
+        self.vaus.load_state(state)?;
+        self.power_pad.load_state(state)?;
+        self.keyboard.load_state(state)
+    }
+
+    // $4016 writes: bit 0 strobes every port, bits 0-2 also go out to the expansion port
+    pub fn write(&mut self, value: u8) {
+        for controller in self.controllers.iter_mut() {
+            controller.write_strobe(value);
+        }
+        if self.strobe || value & 0x01 != 0 {
+            self.reload();
+        }
+        self.strobe = value & 0x01 != 0;
+        self.latch = value & 0x07;
+        if self.devices.expansion == ExpansionDevice::Keyboard {
+            self.keyboard.write(value);
+        }
+    }
+
+    // Copies the current state of every serial device into its shift register
+    fn reload(&mut self) {
+        for port in 0..2 {
+            let signature = if port == 0 { 0x08 } else { 0x04 };
+            self.four_score[port] = self.controllers[port].buttons as u32
+                | (self.controllers[port + 2].buttons as u32) << 8
+                | signature << 16;
+        }
+        self.vaus.latch();
+        self.power_pad.latch();
+    }
+
+    // $4016 (port 0) or $4017 (port 1), clocking whatever is on that port
+    pub fn read(&mut self, port: usize, ppu: &PPU) -> u8 {
+        let value = self.peek(port, ppu);
+        self.clock(port);
+        value
+    }
+
+    pub fn peek(&self, port: usize, ppu: &PPU) -> u8 {
+        let device = match self.devices.ports[port] {
+            PortDevice::Controller => self.controllers[port].peek(),
+            PortDevice::FourScore => (self.four_score[port] & 0x01) as u8,
+            PortDevice::Zapper => self.zapper.read(ppu),
+            PortDevice::Vaus => self.vaus.data() << 4 | self.vaus.button() << 3,
+            PortDevice::PowerPad => self.power_pad.data(),
+            PortDevice::Empty => 0,
+        };
+        let expansion = match (self.devices.expansion, port) {
+            (ExpansionDevice::Controllers, _) => self.controllers[port + 2].peek() << 1,
+            (ExpansionDevice::Vaus, 0) => self.vaus.button() << 1,
+            (ExpansionDevice::Vaus, 1) => self.vaus.data() << 1,
+            (ExpansionDevice::FamilyTrainer, 1) => self.power_pad.matrix(self.latch),
+            (ExpansionDevice::Keyboard, 1) => self.keyboard.data(),
+            _ => 0,
+        };
+        device | expansion
+    }
+
+    // The clock a read sends to a port. While the strobe is high the registers keep reloading.
+    pub fn clock(&mut self, port: usize) {
+        match self.devices.ports[port] {
+            PortDevice::Controller => {
+                self.controllers[port].read();
+            }
+            PortDevice::FourScore => self.four_score[port] = self.four_score[port] >> 1 | 0x80_0000,
+            PortDevice::Vaus => self.vaus.clock(),
+            PortDevice::PowerPad => self.power_pad.clock(),
+            PortDevice::Zapper | PortDevice::Empty => {}
+        }
+        match (self.devices.expansion, port) {
+            (ExpansionDevice::Controllers, _) => {
+                self.controllers[port + 2].read();
+            }
+            (ExpansionDevice::Vaus, 1) => self.vaus.clock(),
+            _ => {}
+        }
+        if self.strobe {
+            self.reload();
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// One frame of recorded input
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputFrame {
+    pub reset: bool,
+    pub buttons: [u8; 2], // Joypads 1 and 2, in Controller bit order
+}
+
+// Recorded input in FCEUX's .fm2 text format. Only the frame lines ("|c|RLDUTSBA|RLDUTSBA||")
+// are used; the header lines before them are ignored. Any character other than
+// '.' or ' ' in a button's column means it's held.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputLog {
+    pub frames: Vec<InputFrame>,
+}
+
+impl InputLog {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<InputLog> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> InputLog {
+        let frames = text
+            .lines()
+            .filter(|line| line.starts_with('|'))
+            .map(|line| {
+                let fields: Vec<&str> = line.split('|').collect();
+                let command: u8 = fields.get(1).and_then(|command| command.trim().parse().ok()).unwrap_or(0);
+                let pad = |index: usize| fields.get(index).map_or(0, |field| parse_buttons(field));
+                InputFrame { reset: command & 0x01 != 0, buttons: [pad(2), pad(3)] }
+            })
+            .collect();
+        InputLog { frames }
+    }
+
+    // Frames past the end of the log have nothing held
+    pub fn frame(&self, index: usize) -> InputFrame {
+        self.frames.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+// Columns run Right, Left, Down, Up, Start, Select, B, A: bit 7 down to bit 0
+fn parse_buttons(field: &str) -> u8 {
+    field
+        .chars()
+        .take(8)
+        .enumerate()
+        .filter(|&(_, column)| column != '.' && column != ' ')
+        .fold(0, |buttons, (index, _)| buttons | (0x80 >> index))
+}
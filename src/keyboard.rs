@@ -0,0 +1,70 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub const ROWS: usize = 9;
+
+// The Family BASIC keyboard on the Famicom expansion port: 72 keys in nine rows of
+// two four-key columns, scanned by writing $4016 and reading $4017 D1-D4.
+#[derive(Clone, Debug, Default)]
+pub struct Keyboard {
+    pub keys: [u8; ROWS], // Per row, bits 0-3 are column 0 and bits 4-7 column 1
+    row: usize,
+    column: usize,
+    enabled: bool,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.usize(self.row);
+        state.usize(self.column);
+        state.bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.row = state.usize()?.min(ROWS);
+        self.column = state.usize()? & 0x01;
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+
+    // Row 0-8, column 0-1 and key 0-3 within the column; anything else is ignored
+    pub fn set_key(&mut self, row: usize, column: usize, key: usize, pressed: bool) {
+        if row >= ROWS || column > 1 || key > 3 {
+            return;
+        }
+        let bit = 1 << (column * 4 + key);
+        if pressed {
+            self.keys[row] |= bit;
+        } else {
+            self.keys[row] &= !bit;
+        }
+    }
+
+    // Bit 0 resets the scan to row 0, bit 1 picks the column (going back to column 0
+    // moves to the next row) and bit 2 powers the keyboard
+    pub fn write(&mut self, value: u8) {
+        self.enabled = value & 0x04 != 0;
+        if !self.enabled {
+            return;
+        }
+        let column = (value >> 1 & 0x01) as usize;
+        if value & 0x01 != 0 {
+            self.row = 0;
+        } else if self.column == 1 && column == 0 {
+            self.row = (self.row + 1).min(ROWS);
+        }
+        self.column = column;
+    }
+
+    // D1-D4 of $4017, 0 for a pressed key. Past the last row nothing is pressed.
+    pub fn data(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let keys = self.keys.get(self.row).map_or(0, |keys| keys >> (self.column * 4) & 0x0F);
+        (!keys & 0x0F) << 1
+    }
+}
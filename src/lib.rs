@@ -1,3 +1,26 @@
+pub mod apu;
+pub mod audio;
+pub mod battery;
+pub mod cartridge;
+pub mod controller;
 pub mod cpu;
+pub mod input;
+pub mod input_log;
+pub mod keyboard;
+pub mod mapper;
 pub mod memory;
-pub mod opcodes;
\ No newline at end of file
+pub mod mixer;
+pub mod nes;
+pub mod nsf;
+pub mod ntsc;
+pub mod opcodes;
+pub mod palette;
+pub mod power_pad;
+pub mod ppu;
+pub mod ppu_bus;
+pub mod region;
+pub mod rewind;
+pub mod state;
+pub mod vaus;
+pub mod wav;
+pub mod zapper;
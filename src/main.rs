@@ -1,13 +1,199 @@
-pub mod cpu;
-pub mod memory;
-pub mod opcodes;
+use std::env;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
 
-use cpu::CPU;
-use memory::Memory;
+use rusty_nes::audio::{AudioConfig, Channels};
+use rusty_nes::input_log::InputLog;
+use rusty_nes::memory::Memory;
+use rusty_nes::nes::Nes;
+use rusty_nes::nsf::{Nsf, NsfPlayer};
+use rusty_nes::wav::{export_wav, WavExport, WavWriter};
 
 fn main() {
-    let memory = Memory::new();
-    let mut cpu = CPU::new();
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("wav") => return wav(&args[2..]),
+        Some("nsf") => return nsf(&args[2..]),
+        _ => {}
+    }
+    if args.len() < 2 {
+        eprintln!("Usage: {} <rom.nes> [frames]", args[0]);
+        eprintln!("       {} wav <rom.nes> --out <file.wav> [--frames N] [--input <movie.fm2>]", args[0]);
+        eprintln!("           [--rate HZ] [--mono] [--stems] [MIXER]");
+        eprintln!("       {} nsf <file.nsf> --out <file.wav> [--track N] [--seconds S] [--rate HZ] [--mono]", args[0]);
+        eprintln!("           [MIXER]");
+        eprintln!("MIXER: [--mute CHANNEL,...] [--solo CHANNEL,...] [--volume CHANNEL=GAIN], each repeatable");
+        process::exit(1);
+    }
 
-    cpu.reset(&memory);
+    let mut nes = load(&args[1]);
+    let frames: u64 = args.get(2).and_then(|frames| frames.parse().ok()).unwrap_or(60);
+    for _ in 0..frames {
+        nes.run_frame();
+    }
+    if let Err(err) = nes.flush_save() {
+        eprintln!("could not write save: {}", err);
+    }
+    println!("Ran {} frames, PC = 0x{:04X}", frames, nes.cpu.pc);
+}
+
+fn load(path: &str) -> Nes {
+    let mut nes = Nes::new();
+    if let Err(err) = nes.load_rom(path) {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    }
+    nes
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+// A subcommand's arguments: one file, `--name value` options and `--name` flags
+struct Options {
+    file: Option<String>,
+    values: Vec<(String, String)>,
+    flags: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String], valued: &[&str], flags: &[&str]) -> Options {
+        let mut options = Options { file: None, values: Vec::new(), flags: Vec::new() };
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            let name = arg.trim_start_matches("--");
+            if arg.starts_with("--") && valued.contains(&name) {
+                let value = rest.next().unwrap_or_else(|| fail(&format!("{} needs a value", arg)));
+                options.values.push((name.to_string(), value.clone()));
+            } else if arg.starts_with("--") && flags.contains(&name) {
+                options.flags.push(name.to_string());
+            } else if options.file.is_none() && !arg.starts_with("--") {
+                options.file = Some(arg.clone());
+            } else {
+                fail(&format!("unknown option {}", arg));
+            }
+        }
+        options
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values.iter().rev().find(|(option, _)| option == name).map(|(_, value)| value.as_str())
+    }
+
+    // Every value given for a repeatable option, with comma-separated lists split up
+    fn list(&self, name: &str) -> Vec<&str> {
+        let values = self.values.iter().filter(|(option, _)| option == name);
+        values.flat_map(|(_, value)| value.split(',')).filter(|item| !item.is_empty()).collect()
+    }
+
+    fn number<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.value(name).map(|value| value.parse().unwrap_or_else(|_| fail(&format!("--{} needs a number", name))))
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    fn audio_config(&self) -> AudioConfig {
+        let mut config = AudioConfig::default();
+        if let Some(rate) = self.number("rate") {
+            if rate == 0 {
+                fail("--rate must be above 0");
+            }
+            config.sample_rate = rate;
+        }
+        if self.flag("mono") {
+            config.channels = Channels::Mono;
+        }
+        config
+    }
+
+    // --mute, --solo and --volume, by the channel names the console reports
+    fn apply_mixer(&self, memory: &mut Memory) {
+        let inputs = memory.mixer_inputs();
+        let find = |name: &str| {
+            let found = inputs.iter().find(|(_, input)| *input == name).map(|(input, _)| *input);
+            found.unwrap_or_else(|| {
+                let names: Vec<_> = inputs.iter().map(|(_, name)| *name).collect();
+                fail(&format!("unknown channel {}, expected one of {}", name, names.join(", ")))
+            })
+        };
+        for name in self.list("mute") {
+            memory.mixer.set_muted(find(name), true);
+        }
+        for name in self.list("solo") {
+            memory.mixer.set_solo(find(name), true);
+        }
+        for setting in self.list("volume") {
+            let (name, gain) = setting.split_once('=').unwrap_or_else(|| fail("--volume needs CHANNEL=GAIN"));
+            let gain = gain.parse().unwrap_or_else(|_| fail(&format!("--volume {} needs a number", name)));
+            memory.mixer.set_volume(find(name), gain);
+        }
+    }
+}
+
+// Renders a ROM's audio to a .wav file without opening an audio device
+fn wav(args: &[String]) {
+    let valued = ["out", "frames", "input", "rate", "mute", "solo", "volume"];
+    let options = Options::parse(args, &valued, &["mono", "stems"]);
+    let rom = options.file.as_deref().unwrap_or_else(|| fail("wav needs a ROM"));
+    let out = options.value("out").unwrap_or_else(|| fail("wav needs --out <file.wav>"));
+    let export = WavExport {
+        frames: options.number("frames").unwrap_or(WavExport::default().frames),
+        config: options.audio_config(),
+        stems: options.flag("stems"),
+    };
+    let input = options
+        .value("input")
+        .map(|path| InputLog::load(path).unwrap_or_else(|err| fail(&format!("{}: {}", path, err))));
+
+    let mut nes = load(rom);
+    options.apply_mixer(&mut nes.memory);
+    if let Err(err) = export_wav(&mut nes, Path::new(out), &export, input.as_ref()) {
+        fail(&format!("{}: {}", out, err));
+    }
+    if let Err(err) = nes.flush_save() {
+        eprintln!("could not write save: {}", err);
+    }
+    println!("Wrote {} frames of audio to {}", export.frames, out);
+}
+
+// Renders one track of an NSF or NSFe rip to a .wav file
+fn nsf(args: &[String]) {
+    let valued = ["out", "track", "seconds", "rate", "mute", "solo", "volume"];
+    let options = Options::parse(args, &valued, &["mono"]);
+    let path = options.file.as_deref().unwrap_or_else(|| fail("nsf needs an NSF file"));
+    let out = options.value("out").unwrap_or_else(|| fail("nsf needs --out <file.wav>"));
+    let nsf = Nsf::load(path).unwrap_or_else(|err| fail(&format!("{}: {}", path, err)));
+    let songs = nsf.songs.max(1);
+
+    let mut player = NsfPlayer::new(nsf);
+    options.apply_mixer(&mut player.nes.memory);
+    let config = options.audio_config();
+    player.nes.memory.audio.set_config(config);
+    let config = player.nes.memory.audio.config();
+    // Tracks are numbered from 1 on the command line, as players show them
+    let track = options.number::<u8>("track").unwrap_or(player.track() + 1);
+    if track == 0 || track > songs {
+        fail(&format!("--track must be between 1 and {}", songs));
+    }
+    player.start_track(track - 1);
+
+    let seconds: f64 = options.number("seconds").unwrap_or(60.0);
+    let result = WavWriter::create(out, config.sample_rate, config.channels.count() as u16).and_then(|mut writer| {
+        let frames = (seconds * player.play_rate()).round() as u64;
+        for _ in 0..frames {
+            player.run_frame();
+            writer.write_resampler(&mut player.nes.memory.audio)?;
+        }
+        writer.finish().map(|_| ())
+    });
+    if let Err(err) = result {
+        fail(&format!("{}: {}", out, err));
+    }
+    let title = &player.nsf.title;
+    println!("Wrote {} seconds of track {}/{} of \"{}\" to {}", seconds, track, songs, title, out);
 }
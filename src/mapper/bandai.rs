@@ -0,0 +1,211 @@
+use crate::cartridge::Rom;
+use crate::mapper::eeprom::Eeprom;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Mappers 16 and 159: Bandai FCG boards. Registers repeat every 16 bytes: eight
+// 1 KiB CHR banks, a 16 KiB PRG bank at $8000 with the last bank fixed at $C000,
+// mirroring, and a 16-bit IRQ counter that runs down every CPU cycle. The FCG-1/2
+// take their registers at $6000-$7FFF and write the counter directly. The later
+// LZ93D50 takes them at $8000-$FFFF, loads a latch that the IRQ control copies to
+// the counter, and drives a serial EEPROM that games save to.
+pub struct Bandai {
+    fcg: bool,      // Registers at $6000-$7FFF, counter written directly
+    lz93d50: bool,  // Registers at $8000-$FFFF, counter loaded from the latch
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    chr_banks: [usize; 8],
+    prg_bank: usize,
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+    irq_pending: bool,
+    eeprom: Option<Eeprom>,
+    eeprom_read: bool, // $800D bit 7: the EEPROM's SDA output shows up at $6000-$7FFF
+}
+
+impl Bandai {
+    pub fn new(rom: Rom) -> Self {
+        // NES 2.0 submapper 4 is the FCG-1/2 and 5 the LZ93D50. iNES 1.0 files could
+        // be either, so they get both register ranges.
+        let (fcg, lz93d50) = match (rom.header.mapper, rom.header.submapper) {
+            (159, _) | (16, 5) => (false, true),
+            (16, 4) => (true, false),
+            _ => (true, true),
+        };
+        // Mapper 159 has a 24C01. On mapper 16 the save size tells a 24C01 from a 24C02.
+        let eeprom = match (rom.header.mapper, rom.header.prg_nvram_size) {
+            _ if !lz93d50 => None,
+            (159, _) | (_, 128) => Some(Eeprom::new(128)),
+            (_, 0) => None,
+            _ => Some(Eeprom::new(256)),
+        };
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Bandai {
+            fcg,
+            lz93d50,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            mirroring: rom.header.mirroring,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+            eeprom,
+            eeprom_read: false,
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank = match address {
+            0x8000..=0xBFFF => self.prg_bank,
+            _ => self.prg_rom.len() / 0x4000 - 1,
+        };
+        (bank * 0x4000 + (address as usize & 0x3FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 10) & 7];
+        (bank * 0x0400 + (address as usize & 0x03FF)) % self.chr.len()
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0x0..=0x7 => self.chr_banks[register as usize] = value as usize,
+            0x8 => self.prg_bank = (value & 0x0F) as usize,
+            0x9 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                };
+            }
+            0xA => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_pending = false;
+                if self.lz93d50 {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB | 0xC => {
+                let shift = if register == 0xB { 0 } else { 8 };
+                self.irq_latch = (self.irq_latch & !(0xFF << shift)) | (value as u16) << shift;
+                if self.fcg {
+                    self.irq_counter = (self.irq_counter & !(0xFF << shift)) | (value as u16) << shift;
+                }
+            }
+            0xD => {
+                self.eeprom_read = value & 0x80 != 0;
+                if let Some(eeprom) = self.eeprom.as_mut() {
+                    eeprom.write(value & 0x20 != 0, value & 0x40 != 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Bandai {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            // Only bit 4 is driven, by the EEPROM's data line
+            0x6000..=0x7FFF => match self.eeprom.as_ref() {
+                Some(eeprom) if self.eeprom_read => Some((eeprom.output() as u8) << 4),
+                _ => None,
+            },
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.fcg => self.write_register(address & 0x0F, value),
+            0x8000..=0xFFFF if self.lz93d50 => self.write_register(address & 0x0F, value),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn eeprom(&self) -> &[u8] {
+        self.eeprom.as_ref().map_or(&[], |eeprom| eeprom.data())
+    }
+
+    fn eeprom_mut(&mut self) -> &mut [u8] {
+        self.eeprom.as_mut().map_or(&mut [], |eeprom| eeprom.data_mut())
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    // The counter runs down every CPU cycle and raises the IRQ when it reaches zero
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        for bank in self.chr_banks.iter() {
+            state.usize(*bank);
+        }
+        state.usize(self.prg_bank);
+        state.mirroring(self.mirroring);
+        state.bool(self.irq_enabled);
+        state.u16(self.irq_counter);
+        state.u16(self.irq_latch);
+        state.bool(self.irq_pending);
+        state.bool(self.eeprom_read);
+        if let Some(eeprom) = self.eeprom.as_ref() {
+            eeprom.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.usize()? & 0xFF;
+        }
+        self.prg_bank = state.usize()? & 0x0F;
+        self.mirroring = state.mirroring()?;
+        self.irq_enabled = state.bool()?;
+        self.irq_counter = state.u16()?;
+        self.irq_latch = state.u16()?;
+        self.irq_pending = state.bool()?;
+        self.eeprom_read = state.bool()?;
+        if let Some(eeprom) = self.eeprom.as_mut() {
+            eeprom.load_state(state)?;
+        }
+        Ok(())
+    }
+}
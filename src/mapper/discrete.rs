@@ -0,0 +1,203 @@
+use crate::cartridge::Rom;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Boards built from a latch and a few logic chips. Writing to ROM loads the latch,
+// which drives the upper PRG and CHR address lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Board {
+    Uxrom,       // Mapper 2: 16 KiB PRG bank at $8000, last bank fixed at $C000
+    Cnrom,       // Mapper 3: 8 KiB CHR bank
+    Axrom,       // Mapper 7: 32 KiB PRG bank, single-screen mirroring select
+    ColorDreams, // Mapper 11: 32 KiB PRG bank (bits 0-1), 8 KiB CHR bank (bits 4-7)
+    Bnrom,       // Mapper 34: 32 KiB PRG bank, CHR-RAM
+    Nina001,     // Mapper 34: registers at $7FFD-$7FFF, two 4 KiB CHR banks
+    Gxrom,       // Mapper 66: 32 KiB PRG bank (bits 4-5), 8 KiB CHR bank (bits 0-1)
+}
+
+impl Board {
+    // Whether the ROM drives the data bus during latch writes on the original boards
+    fn has_bus_conflicts(&self) -> bool {
+        !matches!(self, Board::Axrom | Board::Nina001)
+    }
+}
+
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    prg_bank: usize,       // In units of the board's switchable PRG window
+    chr_banks: [usize; 2], // 4 KiB banks for $0000 and $1000
+}
+
+impl Discrete {
+    pub fn new(rom: Rom, board: Board) -> Self {
+        // NES 2.0 submappers 1 and 2 of UxROM, CNROM and AxROM say whether the board has bus conflicts
+        let bus_conflicts = match (board, rom.header.submapper) {
+            (Board::Uxrom | Board::Cnrom | Board::Axrom, 1) => false,
+            (Board::Uxrom | Board::Cnrom | Board::Axrom, 2) => true,
+            _ => board.has_bus_conflicts(),
+        };
+        let mirroring = if board == Board::Axrom {
+            Mirroring::SingleScreenA
+        } else {
+            rom.header.mirroring
+        };
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Discrete {
+            board,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            mirroring,
+            bus_conflicts,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    fn write_latch(&mut self, value: u8) {
+        let value = value as usize;
+        match self.board {
+            Board::Uxrom | Board::Bnrom => self.prg_bank = value,
+            Board::Cnrom => self.set_chr_8k(value),
+            Board::Axrom => {
+                self.prg_bank = value & 0x07;
+                self.mirroring = if value & 0x10 == 0 {
+                    Mirroring::SingleScreenA
+                } else {
+                    Mirroring::SingleScreenB
+                };
+            }
+            Board::ColorDreams => {
+                self.prg_bank = value & 0x03;
+                self.set_chr_8k(value >> 4);
+            }
+            Board::Gxrom => {
+                self.prg_bank = (value >> 4) & 0x03;
+                self.set_chr_8k(value & 0x03);
+            }
+            Board::Nina001 => {}
+        }
+    }
+
+    fn set_chr_8k(&mut self, bank: usize) {
+        self.chr_banks = [bank * 2, bank * 2 + 1];
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let address = address as usize - 0x8000;
+        let offset = match self.board {
+            Board::Uxrom if address < 0x4000 => self.prg_bank * 0x4000 + address,
+            Board::Uxrom => self.prg_rom.len().saturating_sub(0x4000) + (address & 0x3FFF),
+            Board::Cnrom => address,
+            _ => self.prg_bank * 0x8000 + address,
+        };
+        offset % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 12) & 1];
+        (bank * 0x1000 + (address as usize & 0x0FFF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Discrete {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if !self.prg_ram.is_empty() {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[(address as usize - 0x6000) % len] = value;
+                }
+                if self.board == Board::Nina001 {
+                    match address {
+                        0x7FFD => self.prg_bank = (value & 0x01) as usize,
+                        0x7FFE => self.chr_banks[0] = (value & 0x0F) as usize,
+                        0x7FFF => self.chr_banks[1] = (value & 0x0F) as usize,
+                        _ => {}
+                    }
+                }
+            }
+            0x8000..=0xFFFF if self.board != Board::Nina001 => {
+                // With bus conflicts the ROM drives the bus too, and a 0 bit wins
+                let value = if self.bus_conflicts {
+                    value & self.prg_rom[self.prg_offset(address)]
+                } else {
+                    value
+                };
+                self.write_latch(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        state.mirroring(self.mirroring);
+        state.usize(self.prg_bank);
+        state.usize(self.chr_banks[0]);
+        state.usize(self.chr_banks[1]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        self.mirroring = state.mirroring()?;
+        // Only banks the cartridge has, so a corrupt state cannot overflow the offsets
+        let window = if self.board == Board::Uxrom { 0x4000 } else { 0x8000 };
+        let chr_banks = (self.chr.len() / 0x1000).max(1);
+        self.prg_bank = state.usize()? % (self.prg_rom.len() / window).max(1);
+        self.chr_banks = [state.usize()? % chr_banks, state.usize()? % chr_banks];
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// What the bits clocked since the last start condition belong to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Idle,    // Waiting for a start condition
+    Device,  // 24C02 device select: 1010, three chip select bits, then read/write
+    Address, // Word address. On the 24C01 its top bit is read/write.
+    Write,
+    Read,
+}
+
+impl Phase {
+    const ALL: [Phase; 5] = [Phase::Idle, Phase::Device, Phase::Address, Phase::Write, Phase::Read];
+}
+
+// The serial EEPROMs on Bandai boards, driven by the mapper's SCL and SDA lines.
+// The 256-byte 24C02 is a standard I2C part. The 128-byte 24C01 skips the device
+// select byte and shifts every byte least significant bit first.
+pub struct Eeprom {
+    data: Vec<u8>,
+    phase: Phase,
+    next: Phase, // Where the byte after the acknowledge clock goes
+    bit: u8,     // Clocks into the current byte; the ninth is the acknowledge
+    shift: u8,
+    address: u8,
+    scl: bool,
+    sda: bool,
+    output: bool, // SDA as the chip drives it, high when released
+}
+
+impl Eeprom {
+    pub fn new(size: usize) -> Self {
+        Eeprom {
+            data: vec![0; size],
+            phase: Phase::Idle,
+            next: Phase::Idle,
+            bit: 0,
+            shift: 0,
+            address: 0,
+            scl: false,
+            sda: false,
+            output: true,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    fn is_24c01(&self) -> bool {
+        self.data.len() == 128
+    }
+
+    fn address_mask(&self) -> u8 {
+        (self.data.len() - 1) as u8
+    }
+
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        let (last_scl, last_sda) = (self.scl, self.sda);
+        self.scl = scl;
+        self.sda = sda;
+        if last_scl && scl && last_sda != sda {
+            // SDA changing while SCL is high: falling is a start condition, rising a stop
+            self.phase = match (sda, self.is_24c01()) {
+                (true, _) => Phase::Idle,
+                (false, true) => Phase::Address,
+                (false, false) => Phase::Device,
+            };
+            self.bit = 0;
+            self.output = true;
+        } else if !last_scl && scl {
+            self.clock_rising(sda);
+        } else if last_scl && !scl {
+            self.clock_falling();
+        }
+    }
+
+    // Data is sampled and bits are counted while SCL is high
+    fn clock_rising(&mut self, sda: bool) {
+        match self.phase {
+            Phase::Idle => return,
+            // A high SDA on the acknowledge clock is the CPU declining another byte
+            Phase::Read if self.bit == 8 && sda => self.next = Phase::Idle,
+            Phase::Read => {}
+            _ if self.bit < 8 && self.is_24c01() => self.shift = self.shift >> 1 | (sda as u8) << 7,
+            _ if self.bit < 8 => self.shift = self.shift << 1 | sda as u8,
+            _ => {}
+        }
+        self.bit = (self.bit + 1).min(9);
+    }
+
+    // The chip changes what it drives while SCL is low
+    fn clock_falling(&mut self) {
+        match self.bit {
+            _ if self.phase == Phase::Idle => {}
+            8 if self.phase == Phase::Read => self.output = true,
+            8 => self.receive(),
+            9 => {
+                if self.phase == Phase::Read && self.next == Phase::Read {
+                    self.address = self.address.wrapping_add(1) & self.address_mask();
+                }
+                self.phase = self.next;
+                self.bit = 0;
+                self.output = true;
+                if self.phase == Phase::Read {
+                    self.shift = self.data[self.address as usize];
+                    self.output = self.read_bit();
+                }
+            }
+            0..=7 if self.phase == Phase::Read => self.output = self.read_bit(),
+            _ => {}
+        }
+    }
+
+    fn read_bit(&self) -> bool {
+        let bit = if self.is_24c01() { self.bit } else { 7 - self.bit };
+        self.shift >> bit & 1 != 0
+    }
+
+    // A whole byte has come in; the chip acknowledges it by pulling SDA low
+    fn receive(&mut self) {
+        let byte = self.shift;
+        self.next = match self.phase {
+            Phase::Device if byte & 0xF0 != 0xA0 => Phase::Idle,
+            Phase::Device if byte & 0x01 != 0 => Phase::Read,
+            Phase::Device => Phase::Address,
+            Phase::Address if self.is_24c01() => {
+                self.address = byte & 0x7F;
+                if byte & 0x80 != 0 { Phase::Read } else { Phase::Write }
+            }
+            Phase::Address => {
+                self.address = byte;
+                Phase::Write
+            }
+            _ => {
+                // Writes wrap within a page: 4 bytes on the 24C01, 8 on the 24C02
+                self.data[self.address as usize] = byte;
+                let page = if self.is_24c01() { 0x03 } else { 0x07 };
+                self.address = (self.address & !page) | (self.address.wrapping_add(1) & page);
+                Phase::Write
+            }
+        };
+        self.output = self.next == Phase::Idle;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        state.u8(self.phase as u8);
+        state.u8(self.next as u8);
+        state.u8(self.bit);
+        state.u8(self.shift);
+        state.u8(self.address);
+        state.bool(self.scl);
+        state.bool(self.sda);
+        state.bool(self.output);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.data, "EEPROM size")?;
+        let mut phase = || Phase::ALL.get(state.u8()? as usize).copied().ok_or(StateError::Invalid("EEPROM phase"));
+        self.phase = phase()?;
+        self.next = phase()?;
+        self.bit = state.u8()?.min(9);
+        self.shift = state.u8()?;
+        self.address = state.u8()? & self.address_mask();
+        self.scl = state.bool()?;
+        self.sda = state.bool()?;
+        self.output = state.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use crate::apu::sunsoft5b::Sunsoft5bAudio;
+use crate::cartridge::Rom;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Mapper 69: Sunsoft FME-7, and the 5B that adds three PSG channels to it. A command
+// register at $8000 picks which bank or setting the parameter at $A000 goes to.
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    command: u8,
+    chr_banks: [usize; 8],
+    prg_banks: [usize; 4], // $6000, $8000, $A000 and $C000
+    prg_ram_selected: bool, // RAM rather than ROM at $6000
+    prg_ram_enabled: bool,
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+    pub audio: Sunsoft5bAudio,
+}
+
+impl Fme7 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Fme7 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            prg_ram_selected: false,
+            prg_ram_enabled: false,
+            mirroring: rom.header.mirroring,
+            irq_enabled: false,
+            counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5bAudio::new(),
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank = match address {
+            0x6000..=0xDFFF => self.prg_banks[(address as usize - 0x6000) / 0x2000],
+            _ => self.prg_rom.len() / 0x2000 - 1,
+        };
+        (bank * 0x2000 + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 10) & 7];
+        (bank * 0x0400 + (address as usize & 0x03FF)) % self.chr.len()
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = value as usize,
+            0x8 => {
+                self.prg_banks[0] = (value & 0x3F) as usize;
+                self.prg_ram_selected = value & 0x40 != 0;
+                self.prg_ram_enabled = value & 0x80 != 0;
+            }
+            0x9..=0xB => self.prg_banks[self.command as usize - 0x8] = (value & 0x3F) as usize,
+            0xC => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                };
+            }
+            0xD => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.counter_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_selected => {
+                if !self.prg_ram_enabled || self.prg_ram.is_empty() {
+                    return None;
+                }
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x6000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_selected && self.prg_ram_enabled && !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.write_address(value),
+            0xE000..=0xFFFF => self.audio.write_data(value),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    // The counter runs down every CPU cycle and raises the IRQ when it wraps past zero
+    fn cpu_clock(&mut self) {
+        if self.counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> &[&'static str] {
+        &Sunsoft5bAudio::CHANNELS
+    }
+
+    fn audio_channel_output(&self, channel: usize) -> f32 {
+        self.audio.channel_output(channel)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        state.u8(self.command);
+        for bank in self.chr_banks.iter().chain(self.prg_banks.iter()) {
+            state.usize(*bank);
+        }
+        state.bool(self.prg_ram_selected);
+        state.bool(self.prg_ram_enabled);
+        state.mirroring(self.mirroring);
+        state.bool(self.irq_enabled);
+        state.bool(self.counter_enabled);
+        state.u16(self.irq_counter);
+        state.bool(self.irq_pending);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        self.command = state.u8()? & 0x0F;
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.usize()? & 0xFF;
+        }
+        for bank in self.prg_banks.iter_mut() {
+            *bank = state.usize()? & 0x3F;
+        }
+        self.prg_ram_selected = state.bool()?;
+        self.prg_ram_enabled = state.bool()?;
+        self.mirroring = state.mirroring()?;
+        self.irq_enabled = state.bool()?;
+        self.counter_enabled = state.bool()?;
+        self.irq_counter = state.u16()?;
+        self.irq_pending = state.bool()?;
+        self.audio.load_state(state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use crate::cartridge::Rom;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Mapper 1: Nintendo MMC1 (SxROM boards). Registers are loaded one bit at a time
+// through a 5-bit shift register by writing to $8000-$FFFF.
+//
+// The larger boards reuse the CHR bank registers for extra address lines:
+// SNROM: CHR bit 4 disables PRG-RAM
+// SOROM: CHR bit 3 selects one of two 8 KiB PRG-RAM banks
+// SUROM: CHR bit 4 selects the 256 KiB half of a 512 KiB PRG-ROM
+// SXROM: SUROM plus CHR bits 2-3 selecting one of four PRG-RAM banks
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    shift: u8,       // Bits loaded so far, least significant bit first
+    shift_count: u8, // Number of bits in the shift register
+    control: u8,     // Mirroring (bits 0-1), PRG mode (bits 2-3), CHR mode (bit 4)
+    chr_bank: [u8; 2],
+    prg_bank: u8,
+    chr_a12: bool, // Last pattern table half the PPU used, picks the CHR register in 4 KiB mode
+    written_this_cycle: bool, // The serial port ignores a write on the cycle after another
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Mmc1 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            shift: 0,
+            shift_count: 0,
+            control: 0x0C, // Powers on with the last bank fixed at $C000
+            chr_bank: [0; 2],
+            prg_bank: 0,
+            chr_a12: false,
+            written_this_cycle: false,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank[0] = value,
+            0xC000..=0xDFFF => self.chr_bank[1] = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    // The CHR register currently driving the CHR address lines. SOROM, SUROM and
+    // SXROM take their extra PRG and RAM address bits from it.
+    fn active_chr_bank(&self) -> u8 {
+        if self.control & 0x10 != 0 && self.chr_a12 {
+            self.chr_bank[1]
+        } else {
+            self.chr_bank[0]
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let outer = if self.prg_rom.len() > 0x40000 {
+            (self.active_chr_bank() & 0x10) as usize
+        } else {
+            0
+        };
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let bank = match (self.control >> 2) & 0x03 {
+            0 | 1 => (bank & !1) | ((address as usize >> 14) & 1), // 32 KiB
+            2 if address < 0xC000 => 0,                           // First bank fixed at $8000
+            2 => bank,
+            _ if address < 0xC000 => bank,
+            _ => 0x0F, // Last bank fixed at $C000
+        };
+        let offset = ((outer | bank) * 0x4000) | (address as usize & 0x3FFF);
+        offset % self.prg_rom.len()
+    }
+
+    fn prg_ram_offset(&self, address: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() || self.prg_bank & 0x10 != 0 {
+            return None;
+        }
+        let chr_bank = self.active_chr_bank();
+        // SNROM wires CHR A16 to a second PRG-RAM enable
+        let snrom = self.chr_is_ram && self.chr.len() == 0x2000 && self.prg_rom.len() <= 0x40000;
+        if snrom && chr_bank & 0x10 != 0 {
+            return None;
+        }
+        let bank = match self.prg_ram.len() {
+            0x8000 => ((chr_bank >> 2) & 0x03) as usize,
+            0x4000 => ((chr_bank >> 3) & 0x01) as usize,
+            _ => 0,
+        };
+        Some(((bank * 0x2000) | (address as usize & 0x1FFF)) % self.prg_ram.len())
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let offset = if self.control & 0x10 == 0 {
+            (self.chr_bank[0] & 0x1E) as usize * 0x1000 + (address as usize & 0x1FFF)
+        } else {
+            let bank = self.chr_bank[(address >> 12) as usize & 1] as usize;
+            bank * 0x1000 + (address as usize & 0x0FFF)
+        };
+        offset % self.chr.len()
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram_offset(address).map(|offset| self.prg_ram[offset]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if let Some(offset) = self.prg_ram_offset(address) {
+                    self.prg_ram[offset] = value;
+                }
+            }
+            0x8000..=0xFFFF => {
+                // Read-modify-write instructions write twice in a row; only the first counts
+                if self.written_this_cycle {
+                    return;
+                }
+                self.written_this_cycle = true;
+
+                if value & 0x80 != 0 {
+                    self.shift = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+                self.shift |= (value & 0x01) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    self.write_register(address, self.shift);
+                    self.shift = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr_a12 = address & 0x1000 != 0;
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr_a12 = address & 0x1000 != 0;
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenA,
+            1 => Mirroring::SingleScreenB,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.written_this_cycle = false;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        state.u8(self.shift);
+        state.u8(self.shift_count);
+        state.u8(self.control);
+        state.bytes(&self.chr_bank);
+        state.u8(self.prg_bank);
+        state.bool(self.chr_a12);
+        state.bool(self.written_this_cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        self.shift = state.u8()?;
+        self.shift_count = state.u8()?;
+        self.control = state.u8()?;
+        state.fill(&mut self.chr_bank, "MMC1 CHR register count")?;
+        self.prg_bank = state.u8()?;
+        self.chr_a12 = state.bool()?;
+        self.written_this_cycle = state.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use crate::cartridge::Rom;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Mapper 9: Nintendo MMC2 (PxROM), and mapper 10: MMC4 (FxROM).
+// Each pattern table half has two CHR banks and a latch choosing between them.
+// The latch flips when the PPU fetches tile $FD or $FE, after that fetch completes,
+// so games can switch banks mid-screen without any CPU involvement.
+pub struct Mmc2 {
+    mmc4: bool,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    prg_bank: usize,
+    chr_banks: [[usize; 2]; 2], // 4 KiB banks for each half, selected by latch $FD (0) or $FE (1)
+    latches: [usize; 2],
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(rom: Rom) -> Self {
+        Self::with_chip(rom, false)
+    }
+
+    pub fn mmc4(rom: Rom) -> Self {
+        Self::with_chip(rom, true)
+    }
+
+    fn with_chip(rom: Rom, mmc4: bool) -> Self {
+        let prg_ram = rom.prg_ram();
+        let (chr, _) = rom.chr_memory();
+        Mmc2 {
+            mmc4,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1, 1],
+            mirroring: rom.header.mirroring,
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let address = address as usize - 0x8000;
+        let len = self.prg_rom.len();
+        let offset = if self.mmc4 {
+            // 16 KiB switchable at $8000, last 16 KiB fixed
+            if address < 0x4000 {
+                self.prg_bank * 0x4000 + address
+            } else {
+                len.saturating_sub(0x4000) + (address & 0x3FFF)
+            }
+        } else {
+            // 8 KiB switchable at $8000, last three 8 KiB banks fixed
+            if address < 0x2000 {
+                self.prg_bank * 0x2000 + address
+            } else {
+                len.saturating_sub(0x8000) + address
+            }
+        };
+        offset % len
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let half = (address as usize >> 12) & 1;
+        let bank = self.chr_banks[half][self.latches[half]];
+        (bank * 0x1000 + (address as usize & 0x0FFF)) % self.chr.len()
+    }
+
+    // The MMC2 only watches the exact $0FD8/$0FE8 fetch for the left table;
+    // the MMC4 and the right table react to the whole 8-byte row range.
+    fn update_latch(&mut self, address: u16) {
+        match address & 0x1FF8 {
+            0x0FD8 if self.mmc4 || address == 0x0FD8 => self.latches[0] = 0,
+            0x0FE8 if self.mmc4 || address == 0x0FE8 => self.latches[0] = 1,
+            0x1FD8 => self.latches[1] = 0,
+            0x1FE8 => self.latches[1] = 1,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        let value = value as usize;
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value as u8;
+            }
+            0xA000..=0xAFFF => self.prg_bank = value & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = value & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = value & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = value & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = value & 0x1F,
+            0xF000..=0xFFFF => {
+                self.mirroring = if value & 0x01 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        let value = self.chr[self.chr_offset(address)];
+        self.update_latch(address);
+        value
+    }
+
+    fn write_chr(&mut self, _address: u16, _value: u8) {}
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        state.usize(self.prg_bank);
+        for bank in self.chr_banks.as_flattened() {
+            state.usize(*bank);
+        }
+        state.usize(self.latches[0]);
+        state.usize(self.latches[1]);
+        state.mirroring(self.mirroring);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        self.prg_bank = state.usize()? & 0x0F;
+        for bank in self.chr_banks.as_flattened_mut() {
+            *bank = state.usize()? & 0x1F;
+        }
+        self.latches = [state.usize()? & 1, state.usize()? & 1];
+        self.mirroring = state.mirroring()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use crate::cartridge::Rom;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// PPU dots A12 has to stay low before a rising edge clocks the IRQ counter.
+// The MMC3 filters out the short lows between the tiles of a single fetch phase.
+const A12_LOW_DOTS: u32 = 10;
+
+// The two chip revisions disagree on when a zero counter raises an IRQ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqRevision {
+    Sharp, // MMC3B/MMC3C: IRQ on every clock that leaves the counter at zero
+    Nec,   // MMC3A: IRQ only when the counter changes to zero
+}
+
+// Mapper 4: Nintendo MMC3 (TxROM boards). Eight bank registers select 8 KiB PRG
+// banks and 1/2 KiB CHR banks, and a scanline counter clocked by PPU A12 raises IRQs.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    four_screen: bool,
+    bank_select: u8, // Register index (bits 0-2), PRG mode (bit 6), CHR inversion (bit 7)
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_protect: u8, // Enable (bit 7) and write protect (bit 6)
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    irq_revision: IrqRevision,
+    a12_low_dots: u32,
+}
+
+impl Mmc3 {
+    // NES 2.0 submapper 4 marks boards with the older NEC chip
+    pub fn new(rom: Rom) -> Self {
+        let revision = if rom.header.submapper == 4 {
+            IrqRevision::Nec
+        } else {
+            IrqRevision::Sharp
+        };
+        Self::with_revision(rom, revision)
+    }
+
+    pub fn with_revision(rom: Rom, irq_revision: IrqRevision) -> Self {
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Mmc3 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            four_screen: rom.header.mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.header.mirroring,
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            irq_revision,
+            a12_low_dots: 0,
+        }
+    }
+
+    pub fn irq_revision(&self) -> IrqRevision {
+        self.irq_revision
+    }
+
+    pub fn set_irq_revision(&mut self, revision: IrqRevision) {
+        self.irq_revision = revision;
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / 0x2000;
+        let second_last = banks.saturating_sub(2);
+        let slot = (address as usize - 0x8000) / 0x2000;
+        let bank = match (slot, self.bank_select & 0x40 != 0) {
+            (0, false) | (2, true) => self.registers[6] as usize,
+            (0, true) | (2, false) => second_last,
+            (1, _) => self.registers[7] as usize,
+            _ => banks - 1,
+        };
+        (bank * 0x2000 + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        // Inversion swaps the 2 KiB and 1 KiB halves of the pattern tables
+        let inversion = ((self.bank_select & 0x80) as usize) << 5;
+        let address = address as usize ^ inversion;
+        let bank = match address / 0x0400 {
+            0 => self.registers[0] & 0xFE,
+            1 => self.registers[0] | 0x01,
+            2 => self.registers[1] & 0xFE,
+            3 => self.registers[1] | 0x01,
+            slot => self.registers[slot - 2],
+        } as usize;
+        (bank * 0x0400 + (address & 0x03FF)) % self.chr.len()
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_ram_protect & 0x80 != 0
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let reloaded = self.irq_reload;
+        let previous = self.irq_counter;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+
+        let fire = match self.irq_revision {
+            IrqRevision::Sharp => self.irq_counter == 0,
+            IrqRevision::Nec => self.irq_counter == 0 && (previous != 0 || reloaded),
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_readable() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        let even = address & 0x01 == 0;
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_readable() && self.prg_ram_protect & 0x40 == 0 => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0x9FFF if even => self.bank_select = value,
+            0x8000..=0x9FFF => self.registers[(self.bank_select & 0x07) as usize] = value,
+            0xA000..=0xBFFF if even && !self.four_screen => {
+                self.mirroring = if value & 0x01 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            0xA000..=0xBFFF if even => {}
+            0xA000..=0xBFFF => self.prg_ram_protect = value,
+            0xC000..=0xDFFF if even => self.irq_latch = value,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn ppu_clock(&mut self, address: u16) {
+        if address & 0x1000 == 0 {
+            self.a12_low_dots = self.a12_low_dots.saturating_add(1);
+            return;
+        }
+        if self.a12_low_dots >= A12_LOW_DOTS {
+            self.clock_irq_counter();
+        }
+        self.a12_low_dots = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        state.u8(self.bank_select);
+        state.bytes(&self.registers);
+        state.mirroring(self.mirroring);
+        state.u8(self.prg_ram_protect);
+        state.u8(self.irq_latch);
+        state.u8(self.irq_counter);
+        state.bool(self.irq_reload);
+        state.bool(self.irq_enabled);
+        state.bool(self.irq_pending);
+        state.u32(self.a12_low_dots);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        self.bank_select = state.u8()?;
+        state.fill(&mut self.registers, "MMC3 register count")?;
+        self.mirroring = state.mirroring()?;
+        self.prg_ram_protect = state.u8()?;
+        self.irq_latch = state.u8()?;
+        self.irq_counter = state.u8()?;
+        self.irq_reload = state.bool()?;
+        self.irq_enabled = state.bool()?;
+        self.irq_pending = state.bool()?;
+        self.a12_low_dots = state.u32()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,549 @@
+use crate::apu::mmc5::Mmc5Audio;
+use crate::cartridge::Rom;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// PPU dots without a read after which the MMC5 decides rendering has stopped
+const IDLE_DOTS: u32 = 12;
+
+// Reads in one scanline: 32 background tiles (4 reads each), then 16 sprite
+// pattern reads, then the 2 tiles prefetched for the next line
+const BACKGROUND_READS: usize = 128;
+const SPRITE_READS: usize = 16;
+const PREFETCH_READS: usize = 8;
+
+// What the PPU is fetching, worked out from how many reads it has made since the
+// scanline started. Background fetches carry the tile column and the step within
+// the tile (nametable, attribute, pattern low, pattern high).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Fetch {
+    Background { tile: usize, step: usize, next_line: bool },
+    Sprite,
+    Other,
+}
+
+// Mapper 5: Nintendo MMC5 (ExROM). Besides banking it has 1 KiB of ExRAM that can
+// serve as a nametable or per-tile attributes, a fill-mode nametable, a vertical
+// split, a scanline IRQ, an 8x8 multiplier and expansion audio.
+//
+// Like the real chip, it follows the PPU by watching its reads: three reads of the
+// same nametable address in a row mark the start of a scanline.
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    exram: [u8; 1024],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2], // $5102 must be 2 and $5103 must be 1 to write PRG-RAM
+    exram_mode: u8,           // 0/1: nametable or extended attributes, 2: CPU RAM, 3: CPU ROM
+    nametable_mapping: u8,    // Two bits per nametable: CIRAM page 0/1, ExRAM, fill
+    fill_tile: u8,
+    fill_attribute: u8,
+    prg_banks: [u8; 5],       // $5113-$5117
+    chr_banks_a: [usize; 8],  // $5120-$5127, sprites and 8x8 mode
+    chr_banks_b: [usize; 4],  // $5128-$512B, background in 8x16 mode
+    chr_upper: usize,         // $5130, upper two bits latched into CHR bank writes
+    last_chr_set_b: bool,     // Whether $5128-$512B were written last
+    sprites_8x16: bool,       // Snooped from PPUCTRL
+
+    split_control: u8, // Enable (bit 7), right side (bit 6), tile threshold (bits 0-4)
+    split_scroll: u8,
+    split_bank: usize,
+
+    irq_target: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+
+    in_frame: bool,
+    scanline: u8,
+    fetch_index: usize,
+    idle_dots: u32,
+    last_nametable_address: u16,
+    nametable_repeats: u8,
+    exattr: u8,                         // ExRAM byte for the tile being fetched in extended attribute mode
+    split_tile: Option<(usize, usize)>, // Tile index and fine Y of the split tile being fetched
+
+    pub audio: Mmc5Audio,
+}
+
+impl Mmc5 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Mmc5 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            exram: [0; 1024],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_upper: 0,
+            last_chr_set_b: false,
+            sprites_8x16: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            in_frame: false,
+            scanline: 0,
+            fetch_index: 0,
+            idle_dots: 0,
+            last_nametable_address: 0,
+            nametable_repeats: 0,
+            exattr: 0,
+            split_tile: None,
+            audio: Mmc5Audio::new(),
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    // Returns whether the address maps to ROM, and the offset into ROM or RAM
+    fn prg_offset(&self, address: u16) -> (bool, usize) {
+        if address < 0x8000 {
+            let bank = (self.prg_banks[0] & 0x07) as usize;
+            return (false, bank * 0x2000 + (address as usize & 0x1FFF));
+        }
+
+        let slot = (address as usize - 0x8000) / 0x2000;
+        // Register ($5113 + index) and how many low bank bits the window size ignores
+        let (register, mask) = match (self.prg_mode, slot) {
+            (0, _) => (4, 0x03),
+            (1, 0 | 1) => (2, 0x01),
+            (1, _) => (4, 0x01),
+            (2, 0 | 1) => (2, 0x01),
+            _ => (slot + 1, 0x00),
+        };
+        let value = self.prg_banks[register];
+        let rom = register == 4 || value & 0x80 != 0;
+        let bank = (value as usize & 0x7F & !mask) | (slot & mask);
+        let bank = if rom { bank } else { bank & 0x07 };
+        (rom, bank * 0x2000 + (address as usize & 0x1FFF))
+    }
+
+    fn peek_prg_memory(&self, address: u16) -> Option<u8> {
+        match self.prg_offset(address) {
+            (true, offset) => Some(self.prg_rom[offset % self.prg_rom.len()]),
+            (false, _) if self.prg_ram.is_empty() => None,
+            (false, offset) => Some(self.prg_ram[offset % self.prg_ram.len()]),
+        }
+    }
+
+    fn write_chr_bank(&mut self, address: u16, value: u8) {
+        let bank = value as usize | self.chr_upper << 8;
+        match address {
+            0x5120..=0x5127 => {
+                self.chr_banks_a[address as usize - 0x5120] = bank;
+                self.last_chr_set_b = false;
+            }
+            _ => {
+                self.chr_banks_b[address as usize - 0x5128] = bank;
+                self.last_chr_set_b = true;
+            }
+        }
+    }
+
+    fn chr_bank_offset(&self, address: u16, set_b: bool) -> usize {
+        let address = address as usize & 0x1FFF;
+        let a = &self.chr_banks_a;
+        let b = &self.chr_banks_b;
+        let (bank, size) = match self.chr_mode {
+            0 => (if set_b { b[3] } else { a[7] }, 0x2000),
+            1 => (if set_b { b[3] } else { a[(address >> 12) * 4 + 3] }, 0x1000),
+            2 => (if set_b { b[((address >> 11) & 1) * 2 + 1] } else { a[(address >> 11) * 2 + 1] }, 0x0800),
+            _ => (if set_b { b[(address >> 10) & 3] } else { a[address >> 10] }, 0x0400),
+        };
+        (bank * size + (address & (size - 1))) % self.chr.len()
+    }
+
+    fn chr_offset(&self, address: u16, fetch: Fetch) -> usize {
+        match fetch {
+            Fetch::Background { step, .. } if step >= 2 => {
+                if let Some((_, fine_y)) = self.split_tile {
+                    // The split has its own 4 KiB bank and vertical scroll
+                    let row = (address as usize & 0x0FF8) | fine_y;
+                    return (self.split_bank * 0x1000 + row) % self.chr.len();
+                }
+                if self.exram_mode == 1 {
+                    let bank = self.chr_upper << 6 | (self.exattr & 0x3F) as usize;
+                    return (bank * 0x1000 + (address as usize & 0x0FFF)) % self.chr.len();
+                }
+                self.chr_bank_offset(address, self.sprites_8x16 || self.last_chr_set_b)
+            }
+            Fetch::Sprite => self.chr_bank_offset(address, !self.sprites_8x16 && self.last_chr_set_b),
+            _ => self.chr_bank_offset(address, self.last_chr_set_b),
+        }
+    }
+
+    // Counts a PPU read and classifies it. A repeat of the same nametable address
+    // for the third time starts a new scanline.
+    fn next_fetch(&mut self, nametable_address: Option<u16>) -> Fetch {
+        self.idle_dots = 0;
+        match nametable_address {
+            Some(address) if address == self.last_nametable_address => self.nametable_repeats += 1,
+            Some(address) => {
+                self.last_nametable_address = address;
+                self.nametable_repeats = 0;
+            }
+            None => self.nametable_repeats = 0,
+        }
+        if self.nametable_repeats == 2 {
+            self.start_scanline();
+        }
+
+        if !self.in_frame {
+            return Fetch::Other;
+        }
+        let index = self.fetch_index;
+        self.fetch_index += 1;
+        if index < BACKGROUND_READS {
+            Fetch::Background { tile: index / 4 + 2, step: index % 4, next_line: false }
+        } else if index < BACKGROUND_READS + SPRITE_READS {
+            Fetch::Sprite
+        } else if index < BACKGROUND_READS + SPRITE_READS + PREFETCH_READS {
+            let index = index - BACKGROUND_READS - SPRITE_READS;
+            Fetch::Background { tile: index / 4, step: index % 4, next_line: true }
+        } else {
+            Fetch::Other
+        }
+    }
+
+    fn start_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_target && self.irq_target != 0 {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        }
+        // The read that completed the pattern is the first background fetch of the line
+        self.fetch_index = 0;
+    }
+
+    fn in_split(&self, tile: usize) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+            return false;
+        }
+        let threshold = (self.split_control & 0x1F) as usize;
+        if self.split_control & 0x40 != 0 {
+            tile >= threshold
+        } else {
+            tile < threshold
+        }
+    }
+
+    // ExRAM contents as seen through a nametable mapped to it
+    fn exram_nametable(&self, offset: usize) -> u8 {
+        if self.exram_mode <= 1 {
+            self.exram[offset]
+        } else {
+            0
+        }
+    }
+
+    fn nametable_source(&self, address: u16) -> u8 {
+        let table = ((address as usize - 0x2000) >> 10) & 0x03;
+        (self.nametable_mapping >> (table * 2)) & 0x03
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x5010 => Some(self.audio.peek_pcm_status()),
+            0x5015 => Some(self.audio.status()),
+            0x5204 => Some((self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6),
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[address as usize - 0x5C00]),
+            0x6000..=0xFFFF => self.peek_prg_memory(address),
+            _ => None,
+        }
+    }
+
+    fn read_prg(&mut self, address: u16) -> Option<u8> {
+        let value = self.peek_prg(address);
+        match address {
+            0x5010 => {
+                self.audio.read_pcm_status();
+            }
+            0x5204 => self.irq_pending = false,
+            0x8000..=0xBFFF => self.audio.capture_pcm(value.unwrap_or(0)),
+            _ => {}
+        }
+        value
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5015 => self.audio.write(address, value),
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 | 0x5103 => self.prg_ram_protect[address as usize - 0x5102] = value & 0x03,
+            0x5104 => self.exram_mode = value & 0x03,
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0x03,
+            0x5113..=0x5117 => self.prg_banks[address as usize - 0x5113] = value,
+            0x5120..=0x512B => self.write_chr_bank(address, value),
+            0x5130 => self.chr_upper = (value & 0x03) as usize,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value as usize,
+            0x5203 => self.irq_target = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                // In the nametable modes the CPU can only write while the PPU renders
+                let offset = address as usize - 0x5C00;
+                match self.exram_mode {
+                    0 | 1 => self.exram[offset] = if self.in_frame { value } else { 0 },
+                    2 => self.exram[offset] = value,
+                    _ => {}
+                }
+            }
+            0x6000..=0xFFFF if self.prg_ram_writable() && !self.prg_ram.is_empty() => {
+                if let (false, offset) = self.prg_offset(address) {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[offset % len] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        let fetch = self.next_fetch(None);
+        self.chr[self.chr_offset(address, fetch)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address, Fetch::Other);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    // Only an approximation: the MMC5 can map each nametable independently
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_mapping {
+            0x00 => Mirroring::SingleScreenA,
+            0x55 => Mirroring::SingleScreenB,
+            0x50 => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        }
+    }
+
+    fn read_nametable(&mut self, address: u16, vram: &[u8; 4096]) -> u8 {
+        let fetch = self.next_fetch(Some(address));
+        let offset = address as usize & 0x03FF;
+
+        if let Fetch::Background { tile, step, next_line } = fetch {
+            if step == 0 {
+                self.split_tile = None;
+                if self.in_split(tile) {
+                    let line = self.scanline as usize + next_line as usize;
+                    let y = (self.split_scroll as usize + line) % 240;
+                    self.split_tile = Some(((y / 8) * 32 + tile, y % 8));
+                }
+            }
+            if let Some((index, _)) = self.split_tile {
+                return match step {
+                    0 => self.exram[index & 0x03FF],
+                    _ => {
+                        let (row, column) = (index / 32, index % 32);
+                        let attribute = self.exram[0x3C0 + (row / 4) * 8 + column / 4];
+                        let shift = ((row & 0x02) << 1) | (column & 0x02);
+                        ((attribute >> shift) & 0x03) * 0x55
+                    }
+                };
+            }
+            if self.exram_mode == 1 {
+                if step == 0 {
+                    self.exattr = self.exram[offset];
+                } else {
+                    return (self.exattr >> 6) * 0x55;
+                }
+            }
+        }
+
+        match self.nametable_source(address) {
+            page @ (0 | 1) => vram[page as usize * 0x0400 + offset],
+            2 => self.exram_nametable(offset),
+            _ if offset < 0x03C0 => self.fill_tile,
+            _ => self.fill_attribute * 0x55,
+        }
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8, vram: &mut [u8; 4096]) {
+        let offset = address as usize & 0x03FF;
+        match self.nametable_source(address) {
+            page @ (0 | 1) => vram[page as usize * 0x0400 + offset] = value,
+            2 if self.exram_mode <= 1 => self.exram[offset] = value,
+            _ => {}
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || self.audio.irq()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.audio.clock();
+    }
+
+    fn ppu_clock(&mut self, _address: u16) {
+        self.idle_dots = self.idle_dots.saturating_add(1);
+        if self.idle_dots >= IDLE_DOTS {
+            self.in_frame = false;
+            self.nametable_repeats = 0;
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        if address & 0x2007 == 0x2000 {
+            self.sprites_8x16 = value & 0x20 != 0;
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> &[&'static str] {
+        &Mmc5Audio::CHANNELS
+    }
+
+    fn audio_channel_output(&self, channel: usize) -> f32 {
+        self.audio.channel_output(channel)
+    }
+
+    fn audio_output_with_gains(&self, gains: &[f32]) -> f32 {
+        self.audio.scaled_output([gains[0], gains[1], gains[2]])
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        state.bytes(&self.exram);
+        state.u8(self.prg_mode);
+        state.u8(self.chr_mode);
+        state.bytes(&self.prg_ram_protect);
+        state.u8(self.exram_mode);
+        state.u8(self.nametable_mapping);
+        state.u8(self.fill_tile);
+        state.u8(self.fill_attribute);
+        state.bytes(&self.prg_banks);
+        for bank in self.chr_banks_a.iter().chain(self.chr_banks_b.iter()) {
+            state.usize(*bank);
+        }
+        state.usize(self.chr_upper);
+        state.bool(self.last_chr_set_b);
+        state.bool(self.sprites_8x16);
+
+        state.u8(self.split_control);
+        state.u8(self.split_scroll);
+        state.usize(self.split_bank);
+
+        state.u8(self.irq_target);
+        state.bool(self.irq_enabled);
+        state.bool(self.irq_pending);
+        state.u8(self.multiplicand);
+        state.u8(self.multiplier);
+
+        state.bool(self.in_frame);
+        state.u8(self.scanline);
+        state.usize(self.fetch_index);
+        state.u32(self.idle_dots);
+        state.u16(self.last_nametable_address);
+        state.u8(self.nametable_repeats);
+        state.u8(self.exattr);
+        let (split_tile, split_fine_y) = self.split_tile.unwrap_or((0, 0));
+        state.bool(self.split_tile.is_some());
+        state.usize(split_tile);
+        state.usize(split_fine_y);
+
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        state.fill(&mut self.exram, "ExRAM size")?;
+        self.prg_mode = state.u8()? & 0x03;
+        self.chr_mode = state.u8()? & 0x03;
+        state.fill(&mut self.prg_ram_protect, "MMC5 PRG-RAM protect")?;
+        self.exram_mode = state.u8()? & 0x03;
+        self.nametable_mapping = state.u8()?;
+        self.fill_tile = state.u8()?;
+        self.fill_attribute = state.u8()?;
+        state.fill(&mut self.prg_banks, "MMC5 PRG bank count")?;
+        for bank in self.chr_banks_a.iter_mut().chain(self.chr_banks_b.iter_mut()) {
+            *bank = state.usize()? & 0x3FF;
+        }
+        self.chr_upper = state.usize()? & 0x03;
+        self.last_chr_set_b = state.bool()?;
+        self.sprites_8x16 = state.bool()?;
+
+        self.split_control = state.u8()?;
+        self.split_scroll = state.u8()?;
+        self.split_bank = state.usize()? & 0xFF;
+
+        self.irq_target = state.u8()?;
+        self.irq_enabled = state.bool()?;
+        self.irq_pending = state.bool()?;
+        self.multiplicand = state.u8()?;
+        self.multiplier = state.u8()?;
+
+        self.in_frame = state.bool()?;
+        self.scanline = state.u8()?;
+        self.fetch_index = state.usize()?.min(BACKGROUND_READS + SPRITE_READS + PREFETCH_READS);
+        self.idle_dots = state.u32()?;
+        self.last_nametable_address = state.u16()?;
+        self.nametable_repeats = state.u8()?.min(2);
+        self.exattr = state.u8()?;
+        let splitting = state.bool()?;
+        let split_tile = (state.usize()? % 0x3C0, state.usize()? & 0x07);
+        self.split_tile = if splitting { Some(split_tile) } else { None };
+
+        self.audio.load_state(state)?;
+        Ok(())
+    }
+}
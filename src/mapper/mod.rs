@@ -0,0 +1,152 @@
+use crate::cartridge::{Rom, RomError};
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub mod bandai;
+pub mod discrete;
+mod eeprom;
+pub mod fme7;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namco163;
+pub mod nrom;
+pub mod nsf;
+pub mod vrc4;
+pub mod vrc6;
+pub mod vrc7;
+mod vrc_irq;
+
+pub use bandai::Bandai;
+pub use discrete::{Board, Discrete};
+pub use fme7::Fme7;
+pub use mmc1::Mmc1;
+pub use mmc2::Mmc2;
+pub use mmc3::{IrqRevision, Mmc3};
+pub use mmc5::Mmc5;
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use nsf::NsfMapper;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+// A cartridge board. The CPU bus hands it $4020-$FFFF and the PPU bus hands it
+// the pattern tables at $0000-$1FFF.
+pub trait Mapper {
+    // Returns None for addresses the board does not drive, leaving the bus open
+    fn peek_prg(&self, address: u16) -> Option<u8>;
+
+    // Boards whose registers react to reads override this
+    fn read_prg(&mut self, address: u16) -> Option<u8> {
+        self.peek_prg(address)
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8);
+
+    fn read_chr(&mut self, address: u16) -> u8;
+
+    fn write_chr(&mut self, address: u16, value: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    // Cartridge RAM at $6000-$7FFF, volatile part first. Battery saves come from here.
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // Serial EEPROM contents, for boards that save there instead of to PRG-RAM
+    fn eeprom(&self) -> &[u8] {
+        &[]
+    }
+
+    fn eeprom_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // Nametable accesses. Most boards only choose how the console's VRAM is mirrored;
+    // boards with their own nametable memory override these.
+    fn read_nametable(&mut self, address: u16, vram: &[u8; 4096]) -> u8 {
+        vram[self.mirroring().nametable_index(address)]
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8, vram: &mut [u8; 4096]) {
+        vram[self.mirroring().nametable_index(address)] = value;
+    }
+
+    // Called once per CPU cycle, after the instruction that used those cycles has run
+    fn cpu_clock(&mut self) {}
+
+    // Called once per PPU dot with the address currently on the PPU bus
+    fn ppu_clock(&mut self, _address: u16) {}
+
+    // The cartridge sees CPU writes to the PPU registers too
+    fn ppu_register_write(&mut self, _address: u16, _value: u8) {}
+
+    // Expansion audio level, mixed with the APU's output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // Names of the expansion audio's channels, so they can be mixed and metered one by one
+    fn audio_channels(&self) -> &[&'static str] {
+        &[]
+    }
+
+    // One of those channels on its own. Together they make up audio_output.
+    fn audio_channel_output(&self, _channel: usize) -> f32 {
+        0.0
+    }
+
+    // The expansion audio with each channel's input scaled by its gain before the DAC,
+    // indexed like audio_channels. Chips whose DAC isn't linear apply it after the gains.
+    fn audio_output_with_gains(&self, gains: &[f32]) -> f32 {
+        gains.iter().enumerate().map(|(channel, gain)| self.audio_channel_output(channel) * gain).sum()
+    }
+
+    // State of the cartridge's IRQ output, which is wired to the CPU's IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Registers and RAM for save states. The ROM contents and the board variant
+    // come from the cartridge itself, which the state's ROM hash guarantees is the same.
+    fn save_state(&self, state: &mut StateWriter);
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+// Builds the board for the mapper number in the ROM header
+pub fn create(rom: Rom) -> Result<Box<dyn Mapper>, RomError> {
+    match rom.header.mapper {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        1 => Ok(Box::new(Mmc1::new(rom))),
+        2 => Ok(Box::new(Discrete::new(rom, Board::Uxrom))),
+        3 => Ok(Box::new(Discrete::new(rom, Board::Cnrom))),
+        4 => Ok(Box::new(Mmc3::new(rom))),
+        5 => Ok(Box::new(Mmc5::new(rom))),
+        7 => Ok(Box::new(Discrete::new(rom, Board::Axrom))),
+        9 => Ok(Box::new(Mmc2::new(rom))),
+        10 => Ok(Box::new(Mmc2::mmc4(rom))),
+        11 => Ok(Box::new(Discrete::new(rom, Board::ColorDreams))),
+        16 | 159 => Ok(Box::new(Bandai::new(rom))),
+        19 => Ok(Box::new(Namco163::new(rom))),
+        21 | 22 | 23 | 25 => Ok(Box::new(Vrc4::new(rom))),
+        24 | 26 => Ok(Box::new(Vrc6::new(rom))),
+        34 => {
+            // Both boards share mapper 34; NINA-001 is the one with CHR-ROM
+            let nina = rom.header.submapper == 1 || (rom.header.submapper == 0 && !rom.chr_rom.is_empty());
+            let board = if nina { Board::Nina001 } else { Board::Bnrom };
+            Ok(Box::new(Discrete::new(rom, board)))
+        }
+        66 => Ok(Box::new(Discrete::new(rom, Board::Gxrom))),
+        69 => Ok(Box::new(Fme7::new(rom))),
+        85 => Ok(Box::new(Vrc7::new(rom))),
+        mapper => Err(RomError::UnsupportedMapper(mapper)),
+    }
+}
@@ -0,0 +1,247 @@
+use crate::apu::n163::N163Audio;
+use crate::cartridge::Rom;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Mapper 19: Namco 163. Three switchable 8 KiB PRG banks, eight 1 KiB CHR banks,
+// nametables that can come from CHR-ROM, a 15-bit IRQ counter clocked by the CPU
+// and up to eight channels of wavetable audio.
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_banks: [usize; 3],
+    chr_banks: [usize; 8],
+    nametables: [u8; 4], // $E0 and up pick a page of console VRAM, anything lower a CHR bank
+    prg_ram_protect: u8,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+    audio_enabled: bool,
+    pub audio: N163Audio,
+}
+
+impl Namco163 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        let nametables = match rom.header.mirroring {
+            Mirroring::Horizontal => [0xE0, 0xE0, 0xE1, 0xE1],
+            _ => [0xE0, 0xE1, 0xE0, 0xE1],
+        };
+        Namco163 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            prg_banks: [0, 1, 2],
+            chr_banks: [0; 8],
+            nametables,
+            prg_ram_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio_enabled: true,
+            audio: N163Audio::new(),
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank = match address {
+            0x8000..=0xDFFF => self.prg_banks[(address as usize - 0x8000) / 0x2000],
+            _ => self.prg_rom.len() / 0x2000 - 1,
+        };
+        (bank * 0x2000 + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    // Not emulated: the chip can also bank console VRAM into the pattern tables
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 10) & 7];
+        (bank * 0x0400 + (address as usize & 0x03FF)) % self.chr.len()
+    }
+
+    // $F800 upper nibble must be $4 for any write; bits 0-3 each protect a 2 KiB quarter
+    fn prg_ram_writable(&self, address: u16) -> bool {
+        let quarter = (address as usize - 0x6000) / 0x0800;
+        self.prg_ram_protect & 0xF0 == 0x40 && self.prg_ram_protect & (1 << quarter) == 0
+    }
+
+    fn nametable_slot(address: u16) -> usize {
+        ((address as usize - 0x2000) >> 10) & 0x03
+    }
+}
+
+impl Mapper for Namco163 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4FFF => Some(self.audio.peek_data()),
+            0x5000..=0x57FF => Some(self.irq_counter as u8),
+            0x5800..=0x5FFF => Some((self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7),
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn read_prg(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4FFF => Some(self.audio.read_data()),
+            _ => self.peek_prg(address),
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address & 0xF800 {
+            0x4800 => self.audio.write_data(value),
+            0x5000 => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800 => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((value as u16 & 0x7F) << 8);
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7800 if !self.prg_ram.is_empty() && self.prg_ram_writable(address) => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0xB800 => self.chr_banks[(address as usize - 0x8000) >> 11] = value as usize,
+            0xC000..=0xD800 => self.nametables[(address as usize - 0xC000) >> 11] = value,
+            0xE000 => {
+                self.prg_banks[0] = (value & 0x3F) as usize;
+                self.audio_enabled = value & 0x40 == 0;
+            }
+            0xE800 => self.prg_banks[1] = (value & 0x3F) as usize,
+            0xF000 => self.prg_banks[2] = (value & 0x3F) as usize,
+            0xF800 => {
+                // Shared by the PRG-RAM protect bits and the audio RAM address port
+                self.prg_ram_protect = value;
+                self.audio.write_address(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    // Only an approximation: nametables from CHR-ROM are handled by read_nametable
+    fn mirroring(&self) -> Mirroring {
+        match self.nametables.map(|value| value & 0x01) {
+            [0, 0, 1, 1] => Mirroring::Horizontal,
+            [0, 0, 0, 0] => Mirroring::SingleScreenA,
+            [1, 1, 1, 1] => Mirroring::SingleScreenB,
+            _ => Mirroring::Vertical,
+        }
+    }
+
+    fn read_nametable(&mut self, address: u16, vram: &[u8; 4096]) -> u8 {
+        let offset = address as usize & 0x03FF;
+        match self.nametables[Self::nametable_slot(address)] {
+            page @ 0xE0..=0xFF => vram[(page as usize & 0x01) * 0x0400 + offset],
+            bank => self.chr[(bank as usize * 0x0400 + offset) % self.chr.len()],
+        }
+    }
+
+    // CHR-ROM nametables are read-only
+    fn write_nametable(&mut self, address: u16, value: u8, vram: &mut [u8; 4096]) {
+        let page = self.nametables[Self::nametable_slot(address)];
+        if page >= 0xE0 {
+            vram[(page as usize & 0x01) * 0x0400 + (address as usize & 0x03FF)] = value;
+        }
+    }
+
+    // The counter stops at $7FFF and holds the IRQ until it is written
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.audio_enabled {
+            self.audio.output()
+        } else {
+            0.0
+        }
+    }
+
+    fn audio_channels(&self) -> &[&'static str] {
+        &N163Audio::CHANNELS
+    }
+
+    fn audio_channel_output(&self, channel: usize) -> f32 {
+        if self.audio_enabled {
+            self.audio.channel_output(channel)
+        } else {
+            0.0
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        for bank in self.prg_banks.iter().chain(self.chr_banks.iter()) {
+            state.usize(*bank);
+        }
+        state.bytes(&self.nametables);
+        state.u8(self.prg_ram_protect);
+        state.u16(self.irq_counter);
+        state.bool(self.irq_enabled);
+        state.bool(self.irq_pending);
+        state.bool(self.audio_enabled);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        for bank in self.prg_banks.iter_mut() {
+            *bank = state.usize()? & 0x3F;
+        }
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.usize()? & 0xFF;
+        }
+        state.fill(&mut self.nametables, "nametable register count")?;
+        self.prg_ram_protect = state.u8()?;
+        self.irq_counter = state.u16()? & 0x7FFF;
+        self.irq_enabled = state.bool()?;
+        self.irq_pending = state.bool()?;
+        self.audio_enabled = state.bool()?;
+        self.audio.load_state(state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+use crate::apu::fds::FdsAudio;
+use crate::apu::mmc5::Mmc5Audio;
+use crate::apu::n163::N163Audio;
+use crate::apu::sunsoft5b::Sunsoft5bAudio;
+use crate::apu::vrc6::Vrc6Audio;
+use crate::apu::vrc7::Vrc7Audio;
+use crate::mapper::Mapper;
+use crate::nsf::{Nsf, FDS, IDLE_ADDRESS, MMC5, N163, SUNSOFT_5B, VRC6, VRC7};
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// The hardware an NSF expects: 4 KiB PRG banks at $8000-$FFFF switched through
+// $5FF8-$5FFF, 8 KiB of RAM at $6000-$7FFF, and the sound chips the rip asks
+// for at their usual registers. Also serves the player's idle loop.
+// FDS rips get the Disk System's memory instead: $6000-$DFFF is RAM, and the
+// bank registers (plus $5FF6/$5FF7 for $6000-$7FFF) copy banks into it.
+pub struct NsfMapper {
+    rom: Vec<u8>, // The rip's data, placed so its load address lands where it should
+    banks: [usize; 8],
+    bankswitched: bool,
+    ram: Vec<u8>,
+    fds_ram: Vec<u8>, // $6000-$FFFF for FDS rips, empty otherwise
+    chr: Vec<u8>,
+    pub vrc6: Option<Vrc6Audio>,
+    pub vrc7: Option<Vrc7Audio>,
+    pub fds: Option<FdsAudio>,
+    pub mmc5: Option<Mmc5Audio>,
+    pub n163: Option<N163Audio>,
+    pub sunsoft5b: Option<Sunsoft5bAudio>,
+    exram: Vec<u8>, // MMC5 ExRAM, plain RAM at $5C00-$5FF5 for NSFs
+    multiplier: [u8; 2],
+    channels: Vec<&'static str>, // Every chip's channels, in the order audio_output adds them up
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> Self {
+        let chip = |flag: u8| nsf.expansion & flag != 0;
+        // Bankswitched rips are aligned within their 4 KiB bank, the others are placed at their load address
+        let base = if chip(FDS) { 0x6000 } else { 0x8000 };
+        let padding = if nsf.banks.is_some() {
+            nsf.load_address as usize & 0x0FFF
+        } else {
+            (nsf.load_address as usize).saturating_sub(base)
+        };
+        let mut rom = vec![0; padding];
+        rom.extend_from_slice(&nsf.data);
+        let minimum = if chip(FDS) && nsf.banks.is_none() { 0xA000 } else { 0x1000 };
+        rom.resize((rom.len().div_ceil(0x1000) * 0x1000).max(minimum), 0);
+
+        let mut mapper = NsfMapper {
+            rom,
+            banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            bankswitched: nsf.banks.is_some(),
+            ram: vec![0; 0x2000],
+            fds_ram: if chip(FDS) { vec![0; 0xA000] } else { Vec::new() },
+            chr: vec![0; 0x2000],
+            vrc6: chip(VRC6).then(Vrc6Audio::new),
+            vrc7: chip(VRC7).then(Vrc7Audio::new),
+            fds: chip(FDS).then(FdsAudio::new),
+            mmc5: chip(MMC5).then(Mmc5Audio::new),
+            n163: chip(N163).then(N163Audio::new),
+            sunsoft5b: chip(SUNSOFT_5B).then(Sunsoft5bAudio::new),
+            exram: vec![0; 0x400],
+            multiplier: [0xFF; 2],
+            channels: Vec::new(),
+        };
+        let chips: [(u8, &[&str]); 6] = [
+            (VRC6, &Vrc6Audio::CHANNELS),
+            (VRC7, &Vrc7Audio::CHANNELS),
+            (FDS, &FdsAudio::CHANNELS),
+            (MMC5, &Mmc5Audio::CHANNELS),
+            (N163, &N163Audio::CHANNELS),
+            (SUNSOFT_5B, &Sunsoft5bAudio::CHANNELS),
+        ];
+        for (flag, channels) in chips {
+            if chip(flag) {
+                mapper.channels.extend_from_slice(channels);
+            }
+        }
+        for (register, bank) in nsf.bank_writes() {
+            mapper.write_prg(register, bank);
+        }
+        mapper
+    }
+
+    fn rom_index(&self, address: u16) -> usize {
+        let bank = self.banks[(address as usize - 0x8000) >> 12] % (self.rom.len() / 0x1000);
+        bank * 0x1000 + (address as usize & 0x0FFF)
+    }
+
+    // FDS banking: slot 0 is $6000, slot 9 is $F000
+    fn copy_bank(&mut self, slot: usize, bank: u8) {
+        let bank = bank as usize % (self.rom.len() / 0x1000);
+        self.fds_ram[slot * 0x1000..(slot + 1) * 0x1000].copy_from_slice(&self.rom[bank * 0x1000..(bank + 1) * 0x1000]);
+    }
+
+    // The chips' ports can overlap each other and the memory map, so each one looks for itself
+    fn write_audio(&mut self, address: u16, value: u8) {
+        if let Some(audio) = self.vrc6.as_mut() {
+            if matches!(address, 0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002) {
+                audio.write(address, value);
+            }
+        }
+        if let Some(audio) = self.vrc7.as_mut() {
+            match address {
+                0x9010 => audio.write_address(value),
+                0x9030 => audio.write_data(value),
+                _ => {}
+            }
+        }
+        if let Some(audio) = self.fds.as_mut() {
+            if (0x4040..=0x408A).contains(&address) {
+                audio.write(address, value);
+            }
+        }
+        if let Some(audio) = self.mmc5.as_mut() {
+            if (0x5000..=0x5015).contains(&address) {
+                audio.write(address, value);
+            }
+        }
+        if let Some(audio) = self.n163.as_mut() {
+            match address {
+                0x4800 => audio.write_data(value),
+                0xF800..=0xFFFF => audio.write_address(value),
+                _ => {}
+            }
+        }
+        if let Some(audio) = self.sunsoft5b.as_mut() {
+            match address {
+                0xC000..=0xDFFF => audio.write_address(value),
+                0xE000..=0xFFFF => audio.write_data(value),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        let mmc5 = self.mmc5.is_some();
+        let fds = self.fds.is_some();
+        let product = self.multiplier[0] as u16 * self.multiplier[1] as u16;
+        match address {
+            _ if (IDLE_ADDRESS..IDLE_ADDRESS + 3).contains(&address) => {
+                let idle_loop = [0x4C, IDLE_ADDRESS as u8, (IDLE_ADDRESS >> 8) as u8]; // JMP IDLE_ADDRESS
+                Some(idle_loop[(address - IDLE_ADDRESS) as usize])
+            }
+            // Only the low 6 bits are driven; the rest is what was last on the bus, the $40 of the address
+            0x4040..=0x4092 if fds => self.fds.as_ref().and_then(|audio| audio.read(address)).map(|value| value | 0x40),
+            0x4800 => self.n163.as_ref().map(|audio| audio.peek_data()),
+            0x5015 if mmc5 => self.mmc5.as_ref().map(|audio| audio.status()),
+            0x5205 if mmc5 => Some(product as u8),
+            0x5206 if mmc5 => Some((product >> 8) as u8),
+            0x5C00..=0x5FF5 if mmc5 => Some(self.exram[address as usize - 0x5C00]),
+            0x6000..=0xFFFF if fds => Some(self.fds_ram[address as usize - 0x6000]),
+            0x6000..=0x7FFF => Some(self.ram[address as usize - 0x6000]),
+            0x8000..=0xFFFF => Some(self.rom[self.rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn read_prg(&mut self, address: u16) -> Option<u8> {
+        match (address, self.n163.as_mut()) {
+            (0x4800, Some(audio)) => Some(audio.read_data()),
+            _ => self.peek_prg(address),
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x5FF6..=0x5FFF if self.fds.is_some() => self.copy_bank(address as usize - 0x5FF6, value),
+            0x5FF8..=0x5FFF if self.bankswitched => self.banks[address as usize - 0x5FF8] = value as usize,
+            0x5205 | 0x5206 if self.mmc5.is_some() => self.multiplier[address as usize - 0x5205] = value,
+            0x5C00..=0x5FF5 if self.mmc5.is_some() => self.exram[address as usize - 0x5C00] = value,
+            // $E000 up is the BIOS on a real Disk System
+            0x6000..=0xDFFF if self.fds.is_some() => self.fds_ram[address as usize - 0x6000] = value,
+            0x6000..=0x7FFF => self.ram[address as usize - 0x6000] = value,
+            _ => {}
+        }
+        self.write_audio(address, value);
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[address as usize & 0x1FFF]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr[address as usize & 0x1FFF] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn cpu_clock(&mut self) {
+        if let Some(audio) = self.vrc6.as_mut() {
+            audio.clock();
+        }
+        if let Some(audio) = self.vrc7.as_mut() {
+            audio.clock();
+        }
+        if let Some(audio) = self.fds.as_mut() {
+            audio.clock();
+        }
+        if let Some(audio) = self.mmc5.as_mut() {
+            audio.clock();
+        }
+        if let Some(audio) = self.n163.as_mut() {
+            audio.clock();
+        }
+        if let Some(audio) = self.sunsoft5b.as_mut() {
+            audio.clock();
+        }
+    }
+
+    // Each chip already scales itself relative to the APU
+    fn audio_output(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, |audio| audio.output())
+            + self.vrc7.as_ref().map_or(0.0, |audio| audio.output())
+            + self.fds.as_ref().map_or(0.0, |audio| audio.output())
+            + self.mmc5.as_ref().map_or(0.0, |audio| audio.output())
+            + self.n163.as_ref().map_or(0.0, |audio| audio.output())
+            + self.sunsoft5b.as_ref().map_or(0.0, |audio| audio.output())
+    }
+
+    fn audio_channels(&self) -> &[&'static str] {
+        &self.channels
+    }
+
+    // The MMC5's pulses share a non-linear DAC, so its gains go in ahead of it.
+    // Every other chip is linear and can be scaled channel by channel.
+    fn audio_output_with_gains(&self, gains: &[f32]) -> f32 {
+        let start = self.channels.iter().position(|&name| name == Mmc5Audio::CHANNELS[0]);
+        let mmc5 = start.map_or(0..0, |start| start..start + Mmc5Audio::CHANNELS.len());
+        let others: f32 = (0..gains.len())
+            .filter(|channel| !mmc5.contains(channel))
+            .map(|channel| self.audio_channel_output(channel) * gains[channel])
+            .sum();
+        let mmc5 = match (self.mmc5.as_ref(), start) {
+            (Some(audio), Some(start)) => audio.scaled_output([gains[start], gains[start + 1], gains[start + 2]]),
+            _ => 0.0,
+        };
+        others + mmc5
+    }
+
+    // Walks the chips in the same order as the channel list
+    fn audio_channel_output(&self, mut channel: usize) -> f32 {
+        if let Some(audio) = self.vrc6.as_ref() {
+            if channel < Vrc6Audio::CHANNELS.len() {
+                return audio.channel_output(channel);
+            }
+            channel -= Vrc6Audio::CHANNELS.len();
+        }
+        if let Some(audio) = self.vrc7.as_ref() {
+            if channel < Vrc7Audio::CHANNELS.len() {
+                return audio.channel_output(channel);
+            }
+            channel -= Vrc7Audio::CHANNELS.len();
+        }
+        if let Some(audio) = self.fds.as_ref() {
+            if channel < FdsAudio::CHANNELS.len() {
+                return audio.channel_output(channel);
+            }
+            channel -= FdsAudio::CHANNELS.len();
+        }
+        if let Some(audio) = self.mmc5.as_ref() {
+            if channel < Mmc5Audio::CHANNELS.len() {
+                return audio.channel_output(channel);
+            }
+            channel -= Mmc5Audio::CHANNELS.len();
+        }
+        if let Some(audio) = self.n163.as_ref() {
+            if channel < N163Audio::CHANNELS.len() {
+                return audio.channel_output(channel);
+            }
+            channel -= N163Audio::CHANNELS.len();
+        }
+        match self.sunsoft5b.as_ref() {
+            Some(audio) if channel < Sunsoft5bAudio::CHANNELS.len() => audio.channel_output(channel),
+            _ => 0.0,
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for &bank in &self.banks {
+            state.usize(bank);
+        }
+        state.bytes(&self.ram);
+        state.bytes(&self.chr);
+        state.bytes(&self.exram);
+        state.u8(self.multiplier[0]);
+        state.u8(self.multiplier[1]);
+        if let Some(audio) = self.vrc6.as_ref() {
+            audio.save_state(state);
+        }
+        if let Some(audio) = self.vrc7.as_ref() {
+            audio.save_state(state);
+        }
+        if let Some(audio) = self.mmc5.as_ref() {
+            audio.save_state(state);
+        }
+        state.bytes(&self.fds_ram);
+        if let Some(audio) = self.fds.as_ref() {
+            audio.save_state(state);
+        }
+        if let Some(audio) = self.n163.as_ref() {
+            audio.save_state(state);
+        }
+        if let Some(audio) = self.sunsoft5b.as_ref() {
+            audio.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for bank in self.banks.iter_mut() {
+            *bank = state.usize()?;
+        }
+        state.fill(&mut self.ram, "PRG-RAM size")?;
+        state.fill(&mut self.chr, "CHR-RAM size")?;
+        state.fill(&mut self.exram, "ExRAM size")?;
+        self.multiplier = [state.u8()?, state.u8()?];
+        if let Some(audio) = self.vrc6.as_mut() {
+            audio.load_state(state)?;
+        }
+        if let Some(audio) = self.vrc7.as_mut() {
+            audio.load_state(state)?;
+        }
+        if let Some(audio) = self.mmc5.as_mut() {
+            audio.load_state(state)?;
+        }
+        // Version 2 had no FDS, N163 or 5B
+        if state.version < 3 {
+            return Ok(());
+        }
+        state.fill(&mut self.fds_ram, "FDS RAM size")?;
+        if let Some(audio) = self.fds.as_mut() {
+            audio.load_state(state)?;
+        }
+        if let Some(audio) = self.n163.as_mut() {
+            audio.load_state(state)?;
+        }
+        if let Some(audio) = self.sunsoft5b.as_mut() {
+            audio.load_state(state)?;
+        }
+        Ok(())
+    }
+}
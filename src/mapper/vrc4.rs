@@ -0,0 +1,199 @@
+use crate::cartridge::Rom;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Mappers 21, 22, 23 and 25: Konami VRC2 and VRC4. Each board wires two CPU address
+// lines to the chip's register select inputs. NES 2.0 submappers say which; for
+// iNES 1.0 files both candidates are combined, which works because games only
+// write to the addresses their own wiring decodes.
+pub struct Vrc4 {
+    vrc2: bool,
+    address_lines: (u16, u16), // CPU address bits that drive register select 0 and 1
+    chr_shift: u8,             // VRC2a ignores the low bit of CHR bank numbers
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_banks: [usize; 2],
+    prg_swap: bool,
+    chr_banks: [usize; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(rom: Rom) -> Self {
+        // (VRC2, register select lines, CHR shift) for each mapper and submapper
+        let (vrc2, address_lines, chr_shift) = match (rom.header.mapper, rom.header.submapper) {
+            (21, 1) => (false, (0x02, 0x04), 0), // VRC4a
+            (21, 2) => (false, (0x40, 0x80), 0), // VRC4c
+            (21, _) => (false, (0x42, 0x84), 0),
+            (22, _) => (true, (0x02, 0x01), 1), // VRC2a
+            (23, 1) => (false, (0x01, 0x02), 0), // VRC4f
+            (23, 2) => (false, (0x04, 0x08), 0), // VRC4e
+            (23, 3) => (true, (0x01, 0x02), 0),  // VRC2b
+            (23, _) => (false, (0x05, 0x0A), 0),
+            (25, 1) => (false, (0x02, 0x01), 0), // VRC4b
+            (25, 2) => (false, (0x08, 0x04), 0), // VRC4d
+            (25, 3) => (true, (0x02, 0x01), 0),  // VRC2c
+            (_, _) => (false, (0x0A, 0x05), 0),
+        };
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Vrc4 {
+            vrc2,
+            address_lines,
+            chr_shift,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            prg_banks: [0, 0],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: rom.header.mirroring,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    // Collapses the board's wiring to $x000-$x003
+    fn register(&self, address: u16) -> u16 {
+        let select0 = (address & self.address_lines.0 != 0) as u16;
+        let select1 = (address & self.address_lines.1 != 0) as u16;
+        (address & 0xF000) | select1 << 1 | select0
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / 0x2000;
+        let bank = match ((address - 0x8000) / 0x2000, self.prg_swap) {
+            (0, false) | (2, true) => self.prg_banks[0],
+            (0, true) | (2, false) => banks.saturating_sub(2),
+            (1, _) => self.prg_banks[1],
+            _ => banks - 1,
+        };
+        (bank * 0x2000 + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 10) & 7] >> self.chr_shift;
+        (bank * 0x0400 + (address as usize & 0x03FF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            if address >= 0x6000 && !self.prg_ram.is_empty() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            return;
+        }
+
+        match self.register(address) {
+            0x8000..=0x8003 => self.prg_banks[0] = (value & 0x1F) as usize,
+            0x9000..=0x9001 if self.vrc2 => {
+                self.mirroring = if value & 0x01 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            0x9000..=0x9001 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                };
+            }
+            0x9002 if !self.vrc2 => self.prg_swap = value & 0x02 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = (value & 0x1F) as usize,
+            register @ 0xB000..=0xE003 => {
+                // Each 1 KiB bank is written as a low nibble and a high part
+                let slot = ((register as usize >> 12) - 0xB) * 2 + ((register as usize >> 1) & 1);
+                let bank = &mut self.chr_banks[slot];
+                if register & 0x01 == 0 {
+                    *bank = (*bank & !0x0F) | (value & 0x0F) as usize;
+                } else {
+                    *bank = (*bank & 0x0F) | ((value & 0x1F) as usize) << 4;
+                }
+            }
+            0xF000 if !self.vrc2 => self.irq.latch = (self.irq.latch & 0xF0) | (value & 0x0F),
+            0xF001 if !self.vrc2 => self.irq.latch = (self.irq.latch & 0x0F) | (value << 4),
+            0xF002 if !self.vrc2 => self.irq.write_control(value),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        state.usize(self.prg_banks[0]);
+        state.usize(self.prg_banks[1]);
+        state.bool(self.prg_swap);
+        for bank in self.chr_banks.iter() {
+            state.usize(*bank);
+        }
+        state.mirroring(self.mirroring);
+        self.irq.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        // Masked to the register widths, as the register writes are
+        self.prg_banks = [state.usize()? & 0x1F, state.usize()? & 0x1F];
+        self.prg_swap = state.bool()?;
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.usize()? & 0x1FF;
+        }
+        self.mirroring = state.mirroring()?;
+        self.irq.load_state(state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::apu::vrc6::Vrc6Audio;
+use crate::cartridge::Rom;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Mappers 24 and 26: Konami VRC6a and VRC6b, which differ only in having the A0
+// and A1 register select lines swapped. Carries three channels of expansion audio.
+pub struct Vrc6 {
+    swapped_lines: bool,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_16k: usize,
+    prg_8k: usize,
+    chr_banks: [usize; 8],
+    prg_ram_enabled: bool,
+    mirroring: Mirroring,
+    irq: VrcIrq,
+    pub audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Vrc6 {
+            swapped_lines: rom.header.mapper == 26,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            prg_ram_enabled: false,
+            mirroring: rom.header.mirroring,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    fn register(&self, address: u16) -> u16 {
+        if self.swapped_lines {
+            (address & 0xF000) | (address & 0x01) << 1 | (address & 0x02) >> 1
+        } else {
+            address & 0xF003
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let offset = match address {
+            0x8000..=0xBFFF => self.prg_16k * 0x4000 + (address as usize & 0x3FFF),
+            0xC000..=0xDFFF => self.prg_8k * 0x2000 + (address as usize & 0x1FFF),
+            _ => self.prg_rom.len().saturating_sub(0x2000) + (address as usize & 0x1FFF),
+        };
+        offset % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 10) & 7];
+        (bank * 0x0400 + (address as usize & 0x03FF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            if address >= 0x6000 && self.prg_ram_enabled && !self.prg_ram.is_empty() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            return;
+        }
+
+        match self.register(address) {
+            0x8000..=0x8003 => self.prg_16k = (value & 0x0F) as usize,
+            register @ (0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002) => self.audio.write(register, value),
+            0xB003 => {
+                // Only the usual PPU banking style is supported: eight 1 KiB CHR banks
+                // with the nametables taken from console VRAM
+                self.mirroring = match (value >> 2) & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                };
+                self.prg_ram_enabled = value & 0x80 != 0;
+            }
+            0xC000..=0xC003 => self.prg_8k = (value & 0x1F) as usize,
+            register @ 0xD000..=0xE003 => {
+                let slot = ((register as usize >> 12) - 0xD) * 4 + (register as usize & 0x03);
+                self.chr_banks[slot] = value as usize;
+            }
+            0xF000 => self.irq.latch = value,
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> &[&'static str] {
+        &Vrc6Audio::CHANNELS
+    }
+
+    fn audio_channel_output(&self, channel: usize) -> f32 {
+        self.audio.channel_output(channel)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        state.usize(self.prg_16k);
+        state.usize(self.prg_8k);
+        for bank in self.chr_banks.iter() {
+            state.usize(*bank);
+        }
+        state.bool(self.prg_ram_enabled);
+        state.mirroring(self.mirroring);
+        self.irq.save_state(state);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        // Masked to the register widths, as the register writes are
+        self.prg_16k = state.usize()? & 0x0F;
+        self.prg_8k = state.usize()? & 0x1F;
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.usize()? & 0xFF;
+        }
+        self.prg_ram_enabled = state.bool()?;
+        self.mirroring = state.mirroring()?;
+        self.irq.load_state(state)?;
+        self.audio.load_state(state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use crate::apu::vrc7::Vrc7Audio;
+use crate::cartridge::Rom;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::Mapper;
+use crate::ppu_bus::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Mapper 85: Konami VRC7. Registers are paired within each $1000 block; VRC7a
+// boards (submapper 2) select the second one with A4 and VRC7b boards
+// (submapper 1) with A3. iNES 1.0 files accept either.
+pub struct Vrc7 {
+    select_line: u16,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_banks: [usize; 3],
+    chr_banks: [usize; 8],
+    prg_ram_enabled: bool,
+    mirroring: Mirroring,
+    irq: VrcIrq,
+    pub audio: Vrc7Audio,
+}
+
+impl Vrc7 {
+    pub fn new(rom: Rom) -> Self {
+        let select_line = match rom.header.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        let prg_ram = rom.prg_ram();
+        let (chr, chr_is_ram) = rom.chr_memory();
+        Vrc7 {
+            select_line,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            prg_ram_enabled: false,
+            mirroring: rom.header.mirroring,
+            irq: VrcIrq::new(),
+            audio: Vrc7Audio::new(),
+        }
+    }
+
+    // Collapses the board's wiring to $x000 or $x010
+    fn register(&self, address: u16) -> u16 {
+        (address & 0xF000) | if address & self.select_line != 0 { 0x10 } else { 0 }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank = match (address - 0x8000) / 0x2000 {
+            slot @ 0..=2 => self.prg_banks[slot as usize],
+            _ => self.prg_rom.len() / 0x2000 - 1,
+        };
+        (bank * 0x2000 + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 10) & 7];
+        (bank * 0x0400 + (address as usize & 0x03FF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            if address >= 0x6000 && self.prg_ram_enabled && !self.prg_ram.is_empty() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            return;
+        }
+
+        // The audio ports sit at $9010 and $9030 on both board variants
+        match address & 0xF030 {
+            0x9010 => return self.audio.write_address(value),
+            0x9030 => return self.audio.write_data(value),
+            _ => {}
+        }
+
+        match self.register(address) {
+            0x8000 => self.prg_banks[0] = (value & 0x3F) as usize,
+            0x8010 => self.prg_banks[1] = (value & 0x3F) as usize,
+            0x9000 => self.prg_banks[2] = (value & 0x3F) as usize,
+            register @ 0xA000..=0xD010 => {
+                let slot = ((register as usize >> 12) - 0xA) * 2 + ((register as usize >> 4) & 1);
+                self.chr_banks[slot] = value as usize;
+            }
+            0xE000 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                };
+                self.audio.set_reset(value & 0x40 != 0);
+                self.prg_ram_enabled = value & 0x80 != 0;
+            }
+            0xE010 => self.irq.latch = value,
+            0xF000 => self.irq.write_control(value),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> &[&'static str] {
+        &Vrc7Audio::CHANNELS
+    }
+
+    fn audio_channel_output(&self, channel: usize) -> f32 {
+        self.audio.channel_output(channel)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        for bank in self.prg_banks.iter().chain(self.chr_banks.iter()) {
+            state.usize(*bank);
+        }
+        state.bool(self.prg_ram_enabled);
+        state.mirroring(self.mirroring);
+        self.irq.save_state(state);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.prg_ram, "PRG-RAM size")?;
+        if self.chr_is_ram {
+            state.fill(&mut self.chr, "CHR-RAM size")?;
+        }
+        // Masked to the register widths, as the register writes are
+        for bank in self.prg_banks.iter_mut() {
+            *bank = state.usize()? & 0x3F;
+        }
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.usize()? & 0xFF;
+        }
+        self.prg_ram_enabled = state.bool()?;
+        self.mirroring = state.mirroring()?;
+        self.irq.load_state(state)?;
+        self.audio.load_state(state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// The IRQ counter shared by the VRC4, VRC6 and VRC7. An 8-bit counter counts up
+// from the latch and raises an IRQ when it overflows. In scanline mode a prescaler
+// that counts 341 PPU dots in steps of 3 per CPU cycle clocks it once per scanline;
+// in cycle mode it is clocked every CPU cycle.
+#[derive(Clone, Debug, Default)]
+pub struct VrcIrq {
+    pub latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.latch);
+        state.u8(self.counter);
+        state.u16(self.prescaler as u16);
+        state.bool(self.enabled);
+        state.bool(self.enable_after_ack);
+        state.bool(self.cycle_mode);
+        state.bool(self.pending);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.latch = state.u8()?;
+        self.counter = state.u8()?;
+        self.prescaler = state.u16()? as i16;
+        if !(0..=341).contains(&self.prescaler) {
+            return Err(StateError::Invalid("VRC IRQ prescaler"));
+        }
+        self.enabled = state.bool()?;
+        self.enable_after_ack = state.bool()?;
+        self.cycle_mode = state.bool()?;
+        self.pending = state.bool()?;
+        Ok(())
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0x01 != 0;
+        self.enabled = value & 0x02 != 0;
+        self.cycle_mode = value & 0x04 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+        self.pending = false;
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
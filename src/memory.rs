@@ -1,17 +1,321 @@
+use crate::apu::APU;
+use crate::audio::{AudioConfig, Channel, Resampler};
+use crate::cartridge::Header;
+use crate::input::Input;
+use crate::mapper::Mapper;
+use crate::mixer::{Mixer, MixerInput};
+use crate::ppu::PPU;
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// The CPU's address space. Without a cartridge, $4020-$FFFF is plain RAM.
 pub struct Memory {
     data: [u8; 65536],
+    pub ppu: PPU,
+    pub apu: APU,
+    pub audio: Resampler,     // The mixed audio, resampled for the host
+    pub stems: Vec<Resampler>, // One per Channel while rendering stems, otherwise empty
+    pub mixer: Mixer,
+    pub input: Input,
+    pub cartridge: Option<Box<dyn Mapper>>,
+    region: Region,
+    master_clock: u64, // Master clock cycles elapsed, shared by the CPU and PPU
+    ppu_clock: u64,    // Master clock cycle the PPU has caught up to
+    open_bus: u8,      // Last value driven on the data bus
+    oam_dma: Option<u8>, // Page written to $4014, waiting for the CPU to run the DMA
+    stall: u32,          // CPU cycles requested by DMA units since the last step
+    controller_read: Option<usize>, // Joypad port the current instruction read, for the DMC conflict
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
-        Memory { data: [0; 65536] }
+        Memory {
+            data: [0; 65536],
+            ppu: PPU::new(),
+            apu: APU::new(),
+            audio: Resampler::new(AudioConfig::default(), Region::Ntsc.cpu_clock_hz()),
+            stems: Vec::new(),
+            mixer: Mixer::new(),
+            input: Input::new(),
+            cartridge: None,
+            region: Region::Ntsc,
+            master_clock: 0,
+            ppu_clock: 0,
+            open_bus: 0,
+            oam_dma: None,
+            stall: 0,
+            controller_read: None,
+        }
     }
 
-    pub fn read(&self, address: u16) -> u8 {
-        self.data[address as usize]
+    pub fn insert_cartridge(&mut self, mapper: Box<dyn Mapper>) {
+        self.cartridge = Some(mapper);
+    }
+
+    // The bus itself; the PPU and cartridge are saved separately
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        state.u64(self.master_clock);
+        state.u64(self.ppu_clock);
+        state.u8(self.open_bus);
+        state.bool(self.oam_dma.is_some());
+        state.u8(self.oam_dma.unwrap_or(0));
+        state.u32(self.stall);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.data, "RAM size")?;
+        self.master_clock = state.u64()?;
+        self.ppu_clock = state.u64()?;
+        self.open_bus = state.u8()?;
+        let oam_dma = state.bool()?;
+        let page = state.u8()?;
+        self.oam_dma = if oam_dma { Some(page) } else { None };
+        self.stall = state.u32()?;
+        Ok(())
+    }
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        let value = match address {
+            0x0000..=0x1FFF => self.data[(address & 0x07FF) as usize], // 2 KiB RAM, mirrored
+            0x2000..=0x3FFF => self.ppu.read_register(address, &mut self.cartridge),
+            // $4015 is inside the 2A03, so reading it doesn't drive the external bus
+            0x4015 => return self.apu.read_status() | (self.open_bus & 0x20),
+            // Only the low bits are driven; the rest is whatever was last on the bus
+            0x4016 | 0x4017 => {
+                let port = (address & 1) as usize;
+                self.controller_read = Some(port);
+                self.input.read(port, &self.ppu) | (self.open_bus & 0xE0)
+            }
+            0x4020..=0xFFFF if self.cartridge.is_some() => {
+                let open_bus = self.open_bus;
+                self.cartridge.as_mut().and_then(|mapper| mapper.read_prg(address)).unwrap_or(open_bus)
+            }
+            // The other APU and I/O registers are write-only
+            0x4000..=0x401F => self.open_bus,
+            _ => self.data[address as usize],
+        };
+        self.open_bus = value;
+        value
+    }
+
+    // Reads without the side effects a CPU read would have (register latches, etc.)
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.data[(address & 0x07FF) as usize],
+            0x2000..=0x3FFF => self.ppu.peek_register(address),
+            0x4015 => self.apu.peek_status() | (self.open_bus & 0x20),
+            0x4016 | 0x4017 => self.input.peek((address & 1) as usize, &self.ppu) | (self.open_bus & 0xE0),
+            0x4020..=0xFFFF if self.cartridge.is_some() => self
+                .cartridge
+                .as_ref()
+                .and_then(|mapper| mapper.peek_prg(address))
+                .unwrap_or(self.open_bus),
+            0x4000..=0x401F => self.open_bus,
+            _ => self.data[address as usize],
+        }
     }
 
     pub fn write(&mut self, address: u16, value: u8) {
-        self.data[address as usize] = value;
+        self.open_bus = value;
+        match address {
+            0x0000..=0x1FFF => self.data[(address & 0x07FF) as usize] = value,
+            0x2000..=0x3FFF => {
+                self.ppu.write_register(address, value, &mut self.cartridge);
+                if let Some(mapper) = self.cartridge.as_mut() {
+                    mapper.ppu_register_write(address, value);
+                }
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(address, value),
+            0x4014 => self.oam_dma = Some(value),
+            0x4016 => self.input.write(value),
+            0x4020..=0xFFFF if self.cartridge.is_some() => {
+                if let Some(mapper) = self.cartridge.as_mut() {
+                    mapper.write_prg(address, value);
+                }
+            }
+            _ => self.data[address as usize] = value,
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.audio.set_clock_rate(region.cpu_clock_hz());
+        for stem in self.stems.iter_mut() {
+            stem.set_clock_rate(region.cpu_clock_hz());
+        }
+    }
+
+    // Switches to the region a ROM's header asks for. iNES 1.0 headers have no
+    // timing field, so they leave the current region alone.
+    pub fn select_region(&mut self, header: &Header) {
+        if let Some(region) = header.region() {
+            self.set_region(region);
+        }
+    }
+
+    // Starts resampling every Channel on its own as well as the mix
+    pub fn enable_stems(&mut self, config: AudioConfig) {
+        let clock_rate = self.region.cpu_clock_hz();
+        self.stems = Channel::ALL.iter().map(|_| Resampler::new(config, clock_rate)).collect();
+    }
+
+    pub fn disable_stems(&mut self) {
+        self.stems.clear();
+    }
+
+    pub fn stem_mut(&mut self, channel: Channel) -> Option<&mut Resampler> {
+        self.stems.get_mut(channel as usize)
+    }
+
+    // Runs the rest of the system for the CPU cycles that just elapsed.
+    // Both chips are driven from the master clock, so PAL gets 3.2 dots per CPU cycle.
+    pub fn tick(&mut self, cpu_cycles: u32) {
+        let controller_read = self.controller_read.take();
+        for cycle in 0..cpu_cycles {
+            if let Some(mapper) = self.cartridge.as_mut() {
+                mapper.cpu_clock();
+            }
+            self.apu.clock();
+
+            // The DMC fetches its next sample byte over the CPU bus, halting the CPU meanwhile
+            if let Some(address) = self.apu.dmc.fetch_address() {
+                let value = self.read(address);
+                self.apu.dmc.fill(value);
+                self.stall_cpu(4);
+                // Landing on the cycle an instruction reads a joypad, the fetch makes the halted
+                // CPU read it again. The extra clock shifts out a button that is never seen.
+                if let Some(port) = controller_read.filter(|_| cycle + 1 == cpu_cycles) {
+                    self.input.clock(port);
+                }
+            }
+
+            let level = self.audio_output();
+            self.audio.clock(level);
+            if !self.stems.is_empty() {
+                for channel in Channel::ALL {
+                    let level = self.channel_output(channel);
+                    self.stems[channel as usize].clock(level);
+                }
+            }
+            if self.mixer.metering() {
+                self.record_meters();
+            }
+        }
+
+        self.master_clock += (cpu_cycles * self.region.cpu_divider()) as u64;
+        let ppu_divider = self.region.ppu_divider() as u64;
+        while self.ppu_clock + ppu_divider <= self.master_clock {
+            self.ppu.tick(&mut self.cartridge);
+            self.ppu_clock += ppu_divider;
+        }
+    }
+
+    pub fn take_nmi(&mut self) -> bool {
+        self.ppu.take_nmi()
+    }
+
+    // Level of the shared IRQ line; any device can hold it
+    pub fn irq(&self) -> bool {
+        self.apu.irq() || self.cartridge.as_ref().is_some_and(|mapper| mapper.irq())
+    }
+
+    // The APU mixed with the cartridge's expansion audio, through the mixer's settings
+    pub fn audio_output(&self) -> f32 {
+        if self.mixer.is_neutral() {
+            let expansion = self.cartridge.as_ref().map_or(0.0, |mapper| mapper.audio_output());
+            return self.apu.output() + expansion;
+        }
+        let gains = [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc]
+            .map(|channel| self.mixer.gain(MixerInput::Channel(channel)));
+        self.apu.scaled_output(gains) + self.mixed_expansion()
+    }
+
+    // The cartridge's audio after the mixer: channel by channel when the board names them
+    fn mixed_expansion(&self) -> f32 {
+        let Some(mapper) = self.cartridge.as_ref() else {
+            return 0.0;
+        };
+        let channels = mapper.audio_channels().len();
+        if channels == 0 {
+            return mapper.audio_output() * self.mixer.gain(MixerInput::Channel(Channel::Expansion));
+        }
+        let gains: Vec<f32> = (0..channels).map(|index| self.mixer.gain(MixerInput::Expansion(index))).collect();
+        mapper.audio_output_with_gains(&gains)
+    }
+
+    // Every input the mixer can address, with the name it goes by
+    pub fn mixer_inputs(&self) -> Vec<(MixerInput, &'static str)> {
+        let mut inputs: Vec<_> =
+            Channel::ALL.iter().map(|&channel| (MixerInput::Channel(channel), channel.name())).collect();
+        if let Some(mapper) = self.cartridge.as_ref() {
+            for (index, &name) in mapper.audio_channels().iter().enumerate() {
+                inputs.push((MixerInput::Expansion(index), name));
+            }
+        }
+        inputs
     }
-}
\ No newline at end of file
+
+    // Each input's level on its own this cycle, after its gain
+    fn record_meters(&mut self) {
+        for channel in [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc] {
+            let level = self.apu.channel_output(channel) * self.mixer.gain(MixerInput::Channel(channel));
+            self.mixer.record(channel as usize, level);
+        }
+        let mut cartridge = 0.0;
+        if let Some(mapper) = self.cartridge.as_ref() {
+            let channels = mapper.audio_channels().len();
+            if channels == 0 {
+                cartridge = mapper.audio_output() * self.mixer.gain(MixerInput::Channel(Channel::Expansion));
+            }
+            for index in 0..channels {
+                let level = mapper.audio_channel_output(index) * self.mixer.gain(MixerInput::Expansion(index));
+                self.mixer.record(Channel::ALL.len() + index, level);
+                cartridge += level;
+            }
+        }
+        self.mixer.record(Channel::Expansion as usize, cartridge);
+        self.mixer.next_sample();
+    }
+
+    // Finishes the audio for a frame: the resampled mix, any stems and the meters
+    pub fn end_audio_frame(&mut self) {
+        self.audio.end_frame();
+        for stem in self.stems.iter_mut() {
+            stem.end_frame();
+        }
+        self.mixer.end_frame();
+    }
+
+    pub fn channel_output(&self, channel: Channel) -> f32 {
+        match channel {
+            Channel::Expansion => self.cartridge.as_ref().map_or(0.0, |mapper| mapper.audio_output()),
+            _ => self.apu.channel_output(channel),
+        }
+    }
+
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        self.oam_dma.take()
+    }
+
+    // Halts the CPU for the given number of cycles once the current instruction ends
+    pub fn stall_cpu(&mut self, cycles: u32) {
+        self.stall += cycles;
+    }
+
+    pub fn take_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.stall)
+    }
+}
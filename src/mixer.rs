@@ -0,0 +1,185 @@
+use crate::audio::Channel;
+
+// Something the mixer can address: one of the APU's channels, the cartridge as a
+// whole (Channel::Expansion), or one of the cartridge's own channels by index
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MixerInput {
+    Channel(Channel),
+    Expansion(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelMix {
+    pub volume: f32,
+    pub muted: bool,
+    pub solo: bool, // Once anything is soloed, only soloed inputs are heard
+}
+
+impl Default for ChannelMix {
+    fn default() -> Self {
+        ChannelMix { volume: 1.0, muted: false, solo: false }
+    }
+}
+
+// How loud an input was over the last frame, after its volume, mute and solo
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Meter {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct MeterSum {
+    peak: f32,
+    squares: f64,
+}
+
+// Per-channel volume, mute and solo, and level meters for what each channel
+// plays. It only changes what the host hears; the chips run exactly as they
+// would without it, and none of it is part of a save state.
+pub struct Mixer {
+    channels: [ChannelMix; 6], // By Channel; Expansion scales the cartridge on top of its own channels
+    expansion: Vec<ChannelMix>,
+    metering: bool,
+    sums: Vec<MeterSum>, // Channel::ALL first, then the expansion channels
+    samples: u32,
+    meters: Vec<Meter>, // The last finished frame, laid out like sums
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Mixer {
+            channels: [ChannelMix::default(); 6],
+            expansion: Vec::new(),
+            metering: false,
+            sums: Vec::new(),
+            samples: 0,
+            meters: Vec::new(),
+        }
+    }
+
+    pub fn input(&self, input: MixerInput) -> ChannelMix {
+        match input {
+            MixerInput::Channel(channel) => self.channels[channel as usize],
+            MixerInput::Expansion(index) => self.expansion.get(index).copied().unwrap_or_default(),
+        }
+    }
+
+    pub fn input_mut(&mut self, input: MixerInput) -> &mut ChannelMix {
+        match input {
+            MixerInput::Channel(channel) => &mut self.channels[channel as usize],
+            MixerInput::Expansion(index) => {
+                if index >= self.expansion.len() {
+                    self.expansion.resize(index + 1, ChannelMix::default());
+                }
+                &mut self.expansion[index]
+            }
+        }
+    }
+
+    pub fn set_volume(&mut self, input: MixerInput, volume: f32) {
+        self.input_mut(input).volume = volume.max(0.0);
+    }
+
+    pub fn set_muted(&mut self, input: MixerInput, muted: bool) {
+        self.input_mut(input).muted = muted;
+    }
+
+    pub fn set_solo(&mut self, input: MixerInput, solo: bool) {
+        self.input_mut(input).solo = solo;
+    }
+
+    // Everything back at full volume, unmuted and unsoloed
+    pub fn reset(&mut self) {
+        self.channels = [ChannelMix::default(); 6];
+        self.expansion.clear();
+    }
+
+    // Nothing has been changed, so the chips' own mix can be used as it is
+    pub fn is_neutral(&self) -> bool {
+        self.channels.iter().chain(&self.expansion).all(|mix| *mix == ChannelMix::default())
+    }
+
+    fn any_solo(&self) -> bool {
+        self.channels.iter().chain(&self.expansion).any(|mix| mix.solo)
+    }
+
+    // What an input's level is multiplied by, once mute and solo are taken into account.
+    // An expansion channel also goes through Channel::Expansion's settings.
+    pub fn gain(&self, input: MixerInput) -> f32 {
+        let mix = self.input(input);
+        let (volume, muted, solo) = match input {
+            MixerInput::Channel(_) => (mix.volume, mix.muted, mix.solo),
+            MixerInput::Expansion(_) => {
+                let cartridge = self.channels[Channel::Expansion as usize];
+                (mix.volume * cartridge.volume, mix.muted || cartridge.muted, mix.solo || cartridge.solo)
+            }
+        };
+        if muted || (!solo && self.any_solo()) {
+            0.0
+        } else {
+            volume
+        }
+    }
+
+    // Meters cost a little time every cycle, so they're off until asked for
+    pub fn set_metering(&mut self, metering: bool) {
+        self.metering = metering;
+        self.sums.clear();
+        self.samples = 0;
+        self.meters.clear();
+    }
+
+    pub fn metering(&self) -> bool {
+        self.metering
+    }
+
+    // Called once per CPU cycle for each input, in meter order, with its level after gain
+    pub fn record(&mut self, slot: usize, level: f32) {
+        if slot >= self.sums.len() {
+            self.sums.resize(slot + 1, MeterSum::default());
+        }
+        let sum = &mut self.sums[slot];
+        sum.peak = sum.peak.max(level.abs());
+        sum.squares += (level as f64) * (level as f64);
+    }
+
+    // Called once per CPU cycle after every input has been recorded
+    pub fn next_sample(&mut self) {
+        self.samples += 1;
+    }
+
+    // Publishes the frame's meters and starts the next frame's
+    pub fn end_frame(&mut self) {
+        if !self.metering {
+            return;
+        }
+        let samples = self.samples.max(1) as f64;
+        self.meters = self
+            .sums
+            .iter()
+            .map(|sum| Meter { peak: sum.peak, rms: (sum.squares / samples).sqrt() as f32 })
+            .collect();
+        self.sums.iter_mut().for_each(|sum| *sum = MeterSum::default());
+        self.samples = 0;
+    }
+
+    // Every meter from the last frame: Channel::ALL first, then the expansion channels
+    pub fn meters(&self) -> &[Meter] {
+        &self.meters
+    }
+
+    pub fn meter(&self, input: MixerInput) -> Meter {
+        let slot = match input {
+            MixerInput::Channel(channel) => channel as usize,
+            MixerInput::Expansion(index) => Channel::ALL.len() + index,
+        };
+        self.meters.get(slot).copied().unwrap_or_default()
+    }
+}
@@ -0,0 +1,225 @@
+use std::io;
+use std::path::Path;
+
+use crate::battery::{Battery, SaveFile, SaveStorage};
+use crate::cartridge::{Rom, RomError};
+use crate::cpu::CPU;
+use crate::input::{Devices, PortDevice};
+use crate::mapper;
+use crate::memory::Memory;
+use crate::state::{StateError, StateHeader, StateReader, StateWriter};
+
+// The whole console: a CPU and everything on its bus
+pub struct Nes {
+    pub cpu: CPU,
+    pub memory: Memory,
+    pub battery: Option<Battery>, // Only for cartridges with battery-backed memory
+    rom_hash: u32,                // CRC32 of the inserted ROM, save states must match it
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Nes {
+    pub fn new() -> Self {
+        Nes {
+            cpu: CPU::new(),
+            memory: Memory::new(),
+            battery: None,
+            rom_hash: 0,
+        }
+    }
+
+    // Loads a ROM file. Battery saves go to a .sav file next to it.
+    pub fn load_rom<P: AsRef<Path>>(&mut self, path: P) -> Result<(), RomError> {
+        let rom = Rom::load(&path)?;
+        self.insert_rom(rom)?;
+        if self.battery.is_some() {
+            self.set_save_storage(Box::new(SaveFile::beside_rom(&path)))?;
+        }
+        Ok(())
+    }
+
+    // Plugs the cartridge in and powers the console on. NES 2.0 headers pick the region
+    // and the input devices. The previous cartridge's save is flushed first.
+    pub fn insert_rom(&mut self, rom: Rom) -> Result<(), RomError> {
+        self.flush_save()?;
+        let header = rom.header.clone();
+        let devices = Devices::from_nes2_expansion(rom.header.default_expansion_device);
+        let battery = Battery::new(&rom.header);
+        let rom_hash = rom.crc32();
+        let mapper = mapper::create(rom)?;
+
+        self.cpu = CPU::new();
+        self.memory = Memory::new();
+        self.memory.select_region(&header);
+        self.memory.input.devices = devices;
+        self.memory.insert_cartridge(mapper);
+        self.battery = battery;
+        self.rom_hash = rom_hash;
+        self.cpu.reset(&self.memory);
+        Ok(())
+    }
+
+    // Where battery saves are loaded from and flushed to. Loads the existing save
+    // right away; does nothing for cartridges without a battery.
+    pub fn set_save_storage(&mut self, storage: Box<dyn SaveStorage>) -> io::Result<()> {
+        match (self.battery.as_mut(), self.memory.cartridge.as_mut()) {
+            (Some(battery), Some(mapper)) => battery.attach(mapper.as_mut(), storage),
+            _ => Ok(()),
+        }
+    }
+
+    // The battery-backed memory as it would be saved
+    pub fn save_data(&self) -> Option<&[u8]> {
+        match (self.battery.as_ref(), self.memory.cartridge.as_ref()) {
+            (Some(battery), Some(mapper)) => Some(battery.data(mapper.as_ref())),
+            _ => None,
+        }
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        if let (Some(battery), Some(mapper)) = (self.battery.as_mut(), self.memory.cartridge.as_mut()) {
+            battery.load(mapper.as_mut(), data);
+        }
+    }
+
+    // Writes the save out now if it changed
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        match (self.battery.as_mut(), self.memory.cartridge.as_ref()) {
+            (Some(battery), Some(mapper)) => battery.flush(mapper.as_ref()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    // Snapshot of the whole machine: CPU, RAM, PPU, APU, joypads and cartridge
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(self.memory.region(), self.rom_hash);
+        state.section(b"CPU ", |state| self.cpu.save_state(state));
+        state.section(b"BUS ", |state| self.memory.save_state(state));
+        state.section(b"PPU ", |state| self.memory.ppu.save_state(state));
+        state.section(b"APU ", |state| self.memory.apu.save_state(state));
+        state.section(b"JOYP", |state| self.memory.input.save_state(state));
+        if let Some(mapper) = self.memory.cartridge.as_ref() {
+            state.section(b"CART", |state| mapper.save_state(state));
+        }
+        state.finish()
+    }
+
+    // Restores a snapshot taken with the same ROM. If the state turns out to be
+    // damaged part way through, the machine is put back the way it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let header = StateHeader::parse(data)?;
+        if header.rom_hash != self.rom_hash {
+            return Err(StateError::RomMismatch { expected: header.rom_hash, actual: self.rom_hash });
+        }
+
+        let backup = self.save_state();
+        let region = self.memory.region();
+        self.memory.set_region(header.region);
+        if let Err(err) = self.load_sections(data) {
+            self.memory.set_region(region);
+            let _ = self.load_sections(&backup);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn load_sections(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (_, sections) = StateReader::parse(data)?;
+        for (tag, mut state) in sections {
+            match &tag {
+                b"CPU " => self.cpu.load_state(&mut state)?,
+                b"BUS " => self.memory.load_state(&mut state)?,
+                b"PPU " => self.memory.ppu.load_state(&mut state)?,
+                b"APU " => self.memory.apu.load_state(&mut state)?,
+                b"JOYP" => self.memory.input.load_state(&mut state)?,
+                b"CART" => {
+                    if let Some(mapper) = self.memory.cartridge.as_mut() {
+                        mapper.load_state(&mut state)?;
+                    }
+                }
+                _ => {} // Written by a newer version, nothing here knows about it
+            }
+        }
+        Ok(())
+    }
+
+    // The reset button: the CPU reloads PC, sets I and moves SP down by three
+    pub fn reset(&mut self) {
+        self.cpu.sp = self.cpu.sp.wrapping_sub(3);
+        self.cpu.p |= 0x04;
+        self.memory.apu.reset();
+        self.cpu.reset(&self.memory);
+    }
+
+    pub fn step(&mut self) -> u32 {
+        self.cpu.step(&mut self.memory)
+    }
+
+    // What a player (0-3) is holding on a joypad, as Controller button bits.
+    // Hosts call this before each frame; it stays held until changed. Other players are ignored.
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) {
+        if let Some(controller) = self.memory.input.controllers.get_mut(player) {
+            controller.buttons = buttons;
+        }
+    }
+
+    // Plugs devices into the controller and expansion ports, replacing the header's choice
+    pub fn set_devices(&mut self, devices: Devices) {
+        self.memory.input.devices = devices;
+    }
+
+    // Aims a Zapper at a pixel (None points it off screen) and sets its trigger.
+    // Unless a port already has it, it is plugged into port 2 in place of the second joypad.
+    pub fn set_zapper(&mut self, aim: Option<(usize, usize)>, trigger: bool) {
+        let input = &mut self.memory.input;
+        if !input.devices.ports.contains(&PortDevice::Zapper) {
+            input.devices.ports[1] = PortDevice::Zapper;
+        }
+        input.zapper.aim = aim;
+        input.zapper.trigger = trigger;
+    }
+
+    // The Arkanoid paddle's knob and button, on whichever port it is plugged into
+    pub fn set_vaus(&mut self, position: u8, button: bool) {
+        self.memory.input.vaus.position = position;
+        self.memory.input.vaus.button = button;
+    }
+
+    // Power Pad or Family Trainer buttons, bit 0 for button 1
+    pub fn set_power_pad(&mut self, buttons: u16) {
+        self.memory.input.power_pad.buttons = buttons;
+    }
+
+    // Runs until the PPU finishes the current frame
+    pub fn run_frame(&mut self) {
+        let frame = self.memory.ppu.frame_count;
+        while self.memory.ppu.frame_count == frame {
+            self.step();
+        }
+        self.memory.end_audio_frame();
+        if let (Some(battery), Some(mapper)) = (self.battery.as_mut(), self.memory.cartridge.as_ref()) {
+            battery.end_frame(mapper.as_ref());
+        }
+    }
+
+    // The last frame, as 9-bit pixels for the palette or NTSC filter
+    pub fn frame(&self) -> &[u16] {
+        &self.memory.ppu.frame
+    }
+}
+
+// Powering off keeps the save, as long as it can still be written
+impl Drop for Nes {
+    fn drop(&mut self) {
+        let _ = self.flush_save();
+    }
+}
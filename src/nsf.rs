@@ -0,0 +1,348 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::mapper::NsfMapper;
+use crate::nes::Nes;
+use crate::region::Region;
+
+// Expansion sound chips an NSF can ask for, as flagged in its header
+pub const VRC6: u8 = 0x01;
+pub const VRC7: u8 = 0x02;
+pub const FDS: u8 = 0x04;
+pub const MMC5: u8 = 0x08;
+pub const N163: u8 = 0x10;
+pub const SUNSOFT_5B: u8 = 0x20;
+
+const HEADER_SIZE: usize = 0x80;
+// The player parks the CPU in a JMP-to-itself here between calls into the tune
+pub const IDLE_ADDRESS: u16 = 0x4100;
+
+#[derive(Debug)]
+pub enum NsfError {
+    Io(io::Error),
+    InvalidHeader,
+    Truncated,
+    MissingChunk(&'static str),
+    UnsupportedChunk([u8; 4]), // An NSFe chunk the file says players must understand
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NsfError::Io(err) => write!(f, "could not read NSF: {}", err),
+            NsfError::InvalidHeader => write!(f, "not an NSF or NSFe file"),
+            NsfError::Truncated => write!(f, "NSF is truncated"),
+            NsfError::MissingChunk(chunk) => write!(f, "NSFe has no {} chunk", chunk),
+            NsfError::UnsupportedChunk(chunk) => {
+                write!(f, "NSFe chunk {} is not supported", String::from_utf8_lossy(chunk))
+            }
+        }
+    }
+}
+
+impl std::error::Error for NsfError {}
+
+impl From<io::Error> for NsfError {
+    fn from(err: io::Error) -> Self {
+        NsfError::Io(err)
+    }
+}
+
+// An NES Sound Format rip: the game's music code and data, where to load it,
+// and the routines to call to start a track and to advance it by a tick.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Nsf {
+    pub songs: u8,
+    pub starting_song: u8, // Counted from 0
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ntsc_speed: u16, // Microseconds between play calls
+    pub pal_speed: u16,
+    pub region_flags: u8,       // Bit 0 PAL, bit 1 both
+    pub expansion: u8,          // VRC6, VRC7, FDS, MMC5, N163 and SUNSOFT_5B bits
+    pub banks: Option<[u8; 8]>, // Initial 4 KiB banks for $8000-$FFFF, None when the rip isn't bankswitched
+    pub data: Vec<u8>,
+    pub track_labels: Vec<String>,     // NSFe only
+    pub track_times: Vec<Option<u32>>, // NSFe only, in milliseconds
+}
+
+impl Nsf {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Nsf, NsfError> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Nsf, NsfError> {
+        if data.starts_with(b"NESM\x1A") {
+            Self::parse_nsf(data)
+        } else if data.starts_with(b"NSFE") {
+            Self::parse_nsfe(data)
+        } else {
+            Err(NsfError::InvalidHeader)
+        }
+    }
+
+    fn parse_nsf(data: &[u8]) -> Result<Nsf, NsfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(NsfError::Truncated);
+        }
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&data[0x70..0x78]);
+
+        // NSF2 may give the program's length, leaving metadata after it
+        let length = data[0x7D] as usize | (data[0x7E] as usize) << 8 | (data[0x7F] as usize) << 16;
+        let end = if data[5] >= 2 && length > 0 { (HEADER_SIZE + length).min(data.len()) } else { data.len() };
+
+        Ok(Nsf {
+            songs: data[6],
+            starting_song: data[7].saturating_sub(1),
+            load_address: word(0x08),
+            init_address: word(0x0A),
+            play_address: word(0x0C),
+            title: text(&data[0x0E..0x2E]),
+            artist: text(&data[0x2E..0x4E]),
+            copyright: text(&data[0x4E..0x6E]),
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            region_flags: data[0x7A],
+            expansion: data[0x7B],
+            banks: if banks.iter().any(|&bank| bank != 0) { Some(banks) } else { None },
+            data: data[HEADER_SIZE..end].to_vec(),
+            track_labels: Vec::new(),
+            track_times: Vec::new(),
+        })
+    }
+
+    // NSFe is a list of chunks: length, four-letter id, body. Chunks whose id starts
+    // with a capital letter must be understood; the others can be skipped.
+    fn parse_nsfe(data: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = Nsf { ntsc_speed: 16639, pal_speed: 19997, ..Nsf::default() };
+        let mut info = false;
+        let mut program = false;
+        let mut offset = 4;
+        while offset + 8 <= data.len() {
+            let length = u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+            let id = [data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]];
+            let body = data.get(offset + 8..offset + 8 + length as usize).ok_or(NsfError::Truncated)?;
+            offset += 8 + length as usize;
+            let byte = |index: usize| body.get(index).copied().unwrap_or(0);
+            let word = |index: usize| u16::from_le_bytes([byte(index), byte(index + 1)]);
+
+            match &id {
+                b"INFO" => {
+                    if body.len() < 8 {
+                        return Err(NsfError::Truncated);
+                    }
+                    nsf.load_address = word(0);
+                    nsf.init_address = word(2);
+                    nsf.play_address = word(4);
+                    nsf.region_flags = byte(6);
+                    nsf.expansion = byte(7);
+                    nsf.songs = if body.len() > 8 { byte(8) } else { 1 };
+                    nsf.starting_song = byte(9);
+                    info = true;
+                }
+                b"DATA" => {
+                    nsf.data = body.to_vec();
+                    program = true;
+                }
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (index, bank) in banks.iter_mut().enumerate() {
+                        *bank = byte(index);
+                    }
+                    nsf.banks = Some(banks);
+                }
+                b"RATE" => {
+                    nsf.ntsc_speed = word(0);
+                    if body.len() >= 4 {
+                        nsf.pal_speed = word(2);
+                    }
+                }
+                b"auth" => {
+                    let mut strings = body.split(|&byte| byte == 0).map(text);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_labels = body.split(|&byte| byte == 0).map(text).collect();
+                    nsf.track_labels.pop(); // Each label ends with a zero, so the last split is empty
+                }
+                b"time" => {
+                    nsf.track_times = body
+                        .chunks_exact(4)
+                        .map(|time| i32::from_le_bytes([time[0], time[1], time[2], time[3]]))
+                        .map(|time| u32::try_from(time).ok())
+                        .collect();
+                }
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => return Err(NsfError::UnsupportedChunk(id)),
+                _ => {}
+            }
+        }
+        if !info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if !program {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        Ok(nsf)
+    }
+
+    // PAL-only rips play on a PAL console; everything else on NTSC
+    pub fn region(&self) -> Region {
+        if self.region_flags & 0x03 == 0x01 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+
+    // Play calls per second on the given console
+    pub fn play_rate(&self, region: Region) -> f64 {
+        let speed = match region {
+            Region::Ntsc => self.ntsc_speed,
+            Region::Pal | Region::Dendy => self.pal_speed,
+        };
+        if speed == 0 {
+            region.frame_rate()
+        } else {
+            1_000_000.0 / speed as f64
+        }
+    }
+
+    // The bank register writes that set up memory before a track starts. FDS rips
+    // also bank $6000-$7FFF through $5FF6/$5FF7, and since that's all RAM even an
+    // unbanked FDS rip is copied back in through its identity banks.
+    pub fn bank_writes(&self) -> Vec<(u16, u8)> {
+        if self.expansion & FDS != 0 {
+            let banks = match self.banks {
+                Some(banks) => {
+                    let mut slots = [banks[6], banks[7], 0, 0, 0, 0, 0, 0, 0, 0];
+                    slots[2..].copy_from_slice(&banks);
+                    slots
+                }
+                None => [0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            };
+            return (0x5FF6..=0x5FFF).zip(banks).collect();
+        }
+        self.banks.map_or(Vec::new(), |banks| (0x5FF8..=0x5FFF).zip(banks).collect())
+    }
+}
+
+// Header strings are zero-padded and usually ASCII
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+// Plays an NSF on an otherwise empty console: the rip's code runs on the CPU,
+// init is called when a track starts and play is called at the rate the rip asks
+// for. Between calls the CPU idles in a loop at IDLE_ADDRESS while the APU plays.
+pub struct NsfPlayer {
+    pub nes: Nes,
+    pub nsf: Nsf,
+    track: u8,
+    cycles_per_play: f64,
+    frame_end: f64, // CPU cycle the current tick ends on
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> Self {
+        let region = nsf.region();
+        let mut nes = Nes::new();
+        nes.memory.set_region(region);
+        nes.memory.insert_cartridge(Box::new(NsfMapper::new(&nsf)));
+        let cycles_per_play = region.cpu_clock_hz() / nsf.play_rate(region);
+        let mut player = NsfPlayer { nes, nsf, track: 0, cycles_per_play, frame_end: 0.0 };
+        player.start_track(player.nsf.starting_song);
+        player
+    }
+
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    // Ticks per second, one play call each
+    pub fn play_rate(&self) -> f64 {
+        self.nsf.play_rate(self.nes.memory.region())
+    }
+
+    // Resets the machine the way the NSF spec asks and runs the track's init routine.
+    // Tracks are counted from 0.
+    pub fn start_track(&mut self, track: u8) {
+        self.track = track;
+        let memory = &mut self.nes.memory;
+        for address in (0x0000..0x0800).chain(0x6000..0x8000) {
+            memory.write(address, 0);
+        }
+        for (register, bank) in self.nsf.bank_writes() {
+            memory.write(register, bank);
+        }
+        memory.write(0x4015, 0x00);
+        for address in 0x4000..=0x4013 {
+            memory.write(address, 0);
+        }
+        memory.write(0x4015, 0x0F);
+        memory.write(0x4017, 0x40);
+
+        let cpu = &mut self.nes.cpu;
+        cpu.a = track;
+        cpu.x = (self.nes.memory.region() == Region::Pal) as u8;
+        cpu.y = 0;
+        cpu.sp = 0xFF;
+        cpu.p = 0x24;
+        self.call(self.nsf.init_address);
+
+        // Some rips never return from init and play from there; give up waiting after a second
+        let limit = self.nes.cpu.cycles + self.nes.memory.region().cpu_clock_hz() as u64;
+        while !self.idle() && self.nes.cpu.cycles < limit {
+            self.nes.step();
+        }
+        self.frame_end = self.nes.cpu.cycles as f64;
+        self.nes.memory.end_audio_frame();
+    }
+
+    // The CPU is waiting in the idle loop rather than running the rip's code
+    pub fn idle(&self) -> bool {
+        self.nes.cpu.pc == IDLE_ADDRESS
+    }
+
+    // JSR from the idle loop
+    fn call(&mut self, address: u16) {
+        let return_address = IDLE_ADDRESS - 1;
+        for byte in [(return_address >> 8) as u8, return_address as u8] {
+            self.nes.memory.write(0x0100 | self.nes.cpu.sp as u16, byte);
+            self.nes.cpu.sp = self.nes.cpu.sp.wrapping_sub(1);
+        }
+        self.nes.cpu.pc = address;
+    }
+
+    // One tick: calls play, unless the last call still hasn't returned, and runs
+    // until the next one is due. Finishes the audio for the tick.
+    pub fn run_frame(&mut self) {
+        if self.idle() {
+            self.call(self.nsf.play_address);
+        }
+        self.frame_end += self.cycles_per_play;
+        while (self.nes.cpu.cycles as f64) < self.frame_end {
+            self.nes.step();
+        }
+        self.nes.memory.end_audio_frame();
+    }
+
+    // Plays for the given time, in ticks
+    pub fn run_seconds(&mut self, seconds: f64) {
+        let frames = (seconds * self.play_rate()).round() as u64;
+        for _ in 0..frames {
+            self.run_frame();
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::palette::{composite_signal, yiq_to_rgb, BLACK, DEFAULT_HUE, DEFAULT_SATURATION, WHITE};
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const SAMPLES_PER_PIXEL: usize = 8; // Two samples per master clock, four master clocks per dot
+const SAMPLES_PER_LINE: usize = SCREEN_WIDTH * SAMPLES_PER_PIXEL;
+const PHASES: usize = 12; // Samples per colour subcarrier cycle
+
+// Twice the 8:7 pixel aspect ratio, so each NES pixel spans ~2.29 output pixels
+pub const NTSC_OUTPUT_WIDTH: usize = 585;
+pub const NTSC_OUTPUT_HEIGHT: usize = SCREEN_HEIGHT;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NtscSettings {
+    pub hue: f32,        // Degrees of rotation, 0 is the standard decoder
+    pub saturation: f32, // 1.0 is the standard decoder
+    pub sharpness: f32,  // -1.0 (soft) to 1.0 (sharp)
+    pub artifacts: f32,  // 0.0 (no colour bleed or dot crawl) to 1.0 (full composite)
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings {
+            hue: 0.0,
+            saturation: 1.0,
+            sharpness: 0.0,
+            artifacts: 1.0,
+        }
+    }
+}
+
+// Encodes PPU pixels into a composite signal one scanline at a time and decodes
+// it back to RGB the way a TV would
+pub struct NtscFilter {
+    pub settings: NtscSettings,
+    signal_table: Vec<[f32; PHASES]>, // Normalized signal per 9-bit pixel and phase
+    luma_table: Vec<f32>,             // Average signal per 9-bit pixel
+}
+
+impl Default for NtscFilter {
+    fn default() -> Self {
+        Self::new(NtscSettings::default())
+    }
+}
+
+impl NtscFilter {
+    pub fn new(settings: NtscSettings) -> Self {
+        let signal_table: Vec<[f32; PHASES]> = (0..512)
+            .map(|pixel| {
+                let mut signals = [0.0; PHASES];
+                for (phase, signal) in signals.iter_mut().enumerate() {
+                    *signal = (composite_signal(pixel as u16, phase) - BLACK) / (WHITE - BLACK);
+                }
+                signals
+            })
+            .collect();
+        let luma_table = signal_table
+            .iter()
+            .map(|signals| signals.iter().sum::<f32>() / PHASES as f32)
+            .collect();
+        NtscFilter { settings, signal_table, luma_table }
+    }
+
+    // Filters a PPU frame into NTSC_OUTPUT_WIDTH x NTSC_OUTPUT_HEIGHT packed RGB.
+    // The subcarrier phase moves from frame to frame, which is what makes dots crawl.
+    pub fn filter(&self, frame: &[u16], frame_number: u64) -> Vec<u8> {
+        let mut output = vec![0; NTSC_OUTPUT_WIDTH * NTSC_OUTPUT_HEIGHT * 3];
+        let frame_phase = (frame_number % 3) as usize * 4;
+        for y in 0..SCREEN_HEIGHT {
+            // A scanline is 341 dots of 8 samples, which advances the phase by 4
+            let line_phase = (frame_phase + y * 4) % PHASES;
+            let pixels = &frame[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH];
+            let row = &mut output[y * NTSC_OUTPUT_WIDTH * 3..(y + 1) * NTSC_OUTPUT_WIDTH * 3];
+            self.filter_line(pixels, line_phase, row);
+        }
+        output
+    }
+
+    fn filter_line(&self, pixels: &[u16], line_phase: usize, row: &mut [u8]) {
+        let artifacts = self.settings.artifacts.clamp(0.0, 1.0);
+        let hue = DEFAULT_HUE + self.settings.hue / 30.0; // 30 degrees per phase step
+        let saturation = DEFAULT_SATURATION * self.settings.saturation;
+
+        // Prefix sums let every sample be box-filtered in constant time
+        let mut luma_sum = vec![0.0f32; SAMPLES_PER_LINE + 1];
+        let mut i_sum = vec![0.0f32; SAMPLES_PER_LINE + 1];
+        let mut q_sum = vec![0.0f32; SAMPLES_PER_LINE + 1];
+        for sample in 0..SAMPLES_PER_LINE {
+            let pixel = (pixels[sample / SAMPLES_PER_PIXEL] & 0x1FF) as usize;
+            let phase = (line_phase + sample) % PHASES;
+            let signal = self.signal_table[pixel][phase];
+            let luma = self.luma_table[pixel];
+
+            // Without artifacts the decoder sees luma and chroma perfectly separated
+            let composite_luma = luma + artifacts * (signal - luma);
+            let chroma = signal - (1.0 - artifacts) * luma;
+            let angle = std::f32::consts::PI * (phase as f32 + hue) / 6.0;
+
+            luma_sum[sample + 1] = luma_sum[sample] + composite_luma;
+            i_sum[sample + 1] = i_sum[sample] + chroma * angle.cos();
+            q_sum[sample + 1] = q_sum[sample] + chroma * angle.sin();
+        }
+
+        let window = |sums: &[f32], center: usize, width: usize| {
+            let start = center.saturating_sub(width / 2);
+            let end = (center + width / 2).min(SAMPLES_PER_LINE);
+            (sums[end] - sums[start]) / (end - start) as f32
+        };
+
+        for x in 0..NTSC_OUTPUT_WIDTH {
+            let center = ((x as f32 + 0.5) * SAMPLES_PER_LINE as f32 / NTSC_OUTPUT_WIDTH as f32) as usize;
+            let luma = window(&luma_sum, center, PHASES);
+            let wide_luma = window(&luma_sum, center, PHASES * 2);
+            let y = luma + self.settings.sharpness * (luma - wide_luma);
+            let i = window(&i_sum, center, PHASES) * saturation;
+            let q = window(&q_sum, center, PHASES) * saturation;
+            row[x * 3..x * 3 + 3].copy_from_slice(&yiq_to_rgb(y, i, q));
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::OnceLock;
+
 #[derive(Clone)]
 pub struct Opcode {
     pub name: &'static str,
@@ -23,6 +25,20 @@ pub enum AddressingMode {
     // Add any additional addressing modes if needed
 }
 
+impl AddressingMode {
+    // Number of operand bytes that follow the opcode
+    pub fn operand_length(&self) -> u16 {
+        match self {
+            AddressingMode::Accumulator | AddressingMode::Implied => 0,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 2,
+            _ => 1,
+        }
+    }
+}
+
 pub fn build_opcode_table() -> [Option<Opcode>; 256] {
     let mut table: [Option<Opcode>; 256] = [(); 256].map(|_| None);
 
@@ -284,10 +300,12 @@ pub fn build_opcode_table() -> [Option<Opcode>; 256] {
     table[0x98] = Some(Opcode { name: "TYA", cycles: 2, addressing_mode: AddressingMode::Implied });
 
     // STP - Stop (Unofficial)
-    table[0xDB] = Some(Opcode { name: "STP", cycles: 0, addressing_mode: AddressingMode::Implied });
+    for opcode in [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2] {
+        table[opcode] = Some(Opcode { name: "STP", cycles: 2, addressing_mode: AddressingMode::Implied });
+    }
 
     // SHX - Store X Indexed with Shift (Unofficial)
-    table[0x9E] = Some(Opcode { name: "SHX", cycles: 0, addressing_mode: AddressingMode::AbsoluteY });
+    table[0x9E] = Some(Opcode { name: "SHX", cycles: 5, addressing_mode: AddressingMode::AbsoluteY });
     
     //OFICIAL OPCODES COMPLETED
 
@@ -325,5 +343,69 @@ pub fn build_opcode_table() -> [Option<Opcode>; 256] {
     table[0xF3] = Some(Opcode { name: "ISC", cycles: 8, addressing_mode: AddressingMode::IndirectIndexed });
     table[0xE3] = Some(Opcode { name: "ISC", cycles: 8, addressing_mode: AddressingMode::IndexedIndirect });
 
+
+    // SLO - Shift Left then OR with Accumulator
+    table[0x07] = Some(Opcode { name: "SLO", cycles: 5, addressing_mode: AddressingMode::ZeroPage });
+    table[0x17] = Some(Opcode { name: "SLO", cycles: 6, addressing_mode: AddressingMode::ZeroPageX });
+    table[0x0F] = Some(Opcode { name: "SLO", cycles: 6, addressing_mode: AddressingMode::Absolute });
+    table[0x1F] = Some(Opcode { name: "SLO", cycles: 7, addressing_mode: AddressingMode::AbsoluteX });
+    table[0x1B] = Some(Opcode { name: "SLO", cycles: 7, addressing_mode: AddressingMode::AbsoluteY });
+    table[0x03] = Some(Opcode { name: "SLO", cycles: 8, addressing_mode: AddressingMode::IndexedIndirect });
+    table[0x13] = Some(Opcode { name: "SLO", cycles: 8, addressing_mode: AddressingMode::IndirectIndexed });
+
+    // RLA - Rotate Left then AND with Accumulator
+    table[0x27] = Some(Opcode { name: "RLA", cycles: 5, addressing_mode: AddressingMode::ZeroPage });
+    table[0x37] = Some(Opcode { name: "RLA", cycles: 6, addressing_mode: AddressingMode::ZeroPageX });
+    table[0x2F] = Some(Opcode { name: "RLA", cycles: 6, addressing_mode: AddressingMode::Absolute });
+    table[0x3F] = Some(Opcode { name: "RLA", cycles: 7, addressing_mode: AddressingMode::AbsoluteX });
+    table[0x3B] = Some(Opcode { name: "RLA", cycles: 7, addressing_mode: AddressingMode::AbsoluteY });
+    table[0x23] = Some(Opcode { name: "RLA", cycles: 8, addressing_mode: AddressingMode::IndexedIndirect });
+    table[0x33] = Some(Opcode { name: "RLA", cycles: 8, addressing_mode: AddressingMode::IndirectIndexed });
+
+    // SRE - Shift Right then EOR with Accumulator
+    table[0x47] = Some(Opcode { name: "SRE", cycles: 5, addressing_mode: AddressingMode::ZeroPage });
+    table[0x57] = Some(Opcode { name: "SRE", cycles: 6, addressing_mode: AddressingMode::ZeroPageX });
+    table[0x4F] = Some(Opcode { name: "SRE", cycles: 6, addressing_mode: AddressingMode::Absolute });
+    table[0x5F] = Some(Opcode { name: "SRE", cycles: 7, addressing_mode: AddressingMode::AbsoluteX });
+    table[0x5B] = Some(Opcode { name: "SRE", cycles: 7, addressing_mode: AddressingMode::AbsoluteY });
+    table[0x43] = Some(Opcode { name: "SRE", cycles: 8, addressing_mode: AddressingMode::IndexedIndirect });
+    table[0x53] = Some(Opcode { name: "SRE", cycles: 8, addressing_mode: AddressingMode::IndirectIndexed });
+
+    // RRA - Rotate Right then Add with Carry
+    table[0x67] = Some(Opcode { name: "RRA", cycles: 5, addressing_mode: AddressingMode::ZeroPage });
+    table[0x77] = Some(Opcode { name: "RRA", cycles: 6, addressing_mode: AddressingMode::ZeroPageX });
+    table[0x6F] = Some(Opcode { name: "RRA", cycles: 6, addressing_mode: AddressingMode::Absolute });
+    table[0x7F] = Some(Opcode { name: "RRA", cycles: 7, addressing_mode: AddressingMode::AbsoluteX });
+    table[0x7B] = Some(Opcode { name: "RRA", cycles: 7, addressing_mode: AddressingMode::AbsoluteY });
+    table[0x63] = Some(Opcode { name: "RRA", cycles: 8, addressing_mode: AddressingMode::IndexedIndirect });
+    table[0x73] = Some(Opcode { name: "RRA", cycles: 8, addressing_mode: AddressingMode::IndirectIndexed });
+
+    // SBC - Unofficial duplicate of $E9
+    table[0xEB] = Some(Opcode { name: "SBC", cycles: 2, addressing_mode: AddressingMode::Immediate });
+
+    // NOP - Unofficial variants, some of which read an operand
+    for opcode in [0x1A, 0x3A, 0x5A, 0x7A, 0xDA, 0xFA] {
+        table[opcode] = Some(Opcode { name: "NOP", cycles: 2, addressing_mode: AddressingMode::Implied });
+    }
+    for opcode in [0x80, 0x82, 0x89, 0xC2, 0xE2] {
+        table[opcode] = Some(Opcode { name: "NOP", cycles: 2, addressing_mode: AddressingMode::Immediate });
+    }
+    for opcode in [0x04, 0x44, 0x64] {
+        table[opcode] = Some(Opcode { name: "NOP", cycles: 3, addressing_mode: AddressingMode::ZeroPage });
+    }
+    for opcode in [0x14, 0x34, 0x54, 0x74, 0xD4, 0xF4] {
+        table[opcode] = Some(Opcode { name: "NOP", cycles: 4, addressing_mode: AddressingMode::ZeroPageX });
+    }
+    table[0x0C] = Some(Opcode { name: "NOP", cycles: 4, addressing_mode: AddressingMode::Absolute });
+    for opcode in [0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC] {
+        table[opcode] = Some(Opcode { name: "NOP", cycles: 4, addressing_mode: AddressingMode::AbsoluteX });
+    }
+
     table
-}
\ No newline at end of file
+}
+
+// The table is built once and shared by every CPU
+pub fn opcode_table() -> &'static [Option<Opcode>; 256] {
+    static TABLE: OnceLock<[Option<Opcode>; 256]> = OnceLock::new();
+    TABLE.get_or_init(build_opcode_table)
+}
@@ -0,0 +1,180 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Composite voltage levels of the 2C02, relative to sync: four "low" then four "high"
+const SIGNAL_LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+pub(crate) const BLACK: f32 = 0.518;
+pub(crate) const WHITE: f32 = 1.962;
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+pub(crate) const DEFAULT_HUE: f32 = 3.9; // In twelfths of a colour cycle
+pub(crate) const DEFAULT_SATURATION: f32 = 1.6;
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(io::Error),
+    InvalidSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::Io(err) => write!(f, "could not read palette: {}", err),
+            PaletteError::InvalidSize(size) => {
+                write!(f, "palette must be 192 or 1536 bytes, got {}", size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<io::Error> for PaletteError {
+    fn from(err: io::Error) -> Self {
+        PaletteError::Io(err)
+    }
+}
+
+// Maps the PPU's 9-bit pixels (colour index in bits 0-5, emphasis in bits 6-8) to RGB
+pub struct Palette {
+    colours: Vec<[u8; 3]>, // 512 entries, one per colour/emphasis combination
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Palette {
+    // The standard 2C02 palette, decoded from its composite signal
+    pub fn new() -> Self {
+        let colours = (0..512).map(|pixel| decode_pixel(pixel as u16)).collect();
+        Palette { colours }
+    }
+
+    // Accepts a 64-colour .pal file (emphasis is synthesized) or a 512-colour one
+    pub fn from_bytes(data: &[u8]) -> Result<Self, PaletteError> {
+        let entries: Vec<[u8; 3]> = data.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+        match data.len() {
+            1536 => Ok(Palette { colours: entries }),
+            192 => {
+                let colours = (0..512)
+                    .map(|pixel| apply_emphasis(entries[pixel & 0x3F], pixel as u16))
+                    .collect();
+                Ok(Palette { colours })
+            }
+            size => Err(PaletteError::InvalidSize(size)),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PaletteError> {
+        let data = fs::read(path)?;
+        Self::from_bytes(&data)
+    }
+
+    pub fn rgb(&self, pixel: u16) -> [u8; 3] {
+        self.colours[(pixel & 0x1FF) as usize]
+    }
+
+    // Colour of a palette index as displayed under the given PPUMASK value
+    pub fn rgb_with_mask(&self, index: u8, mask: u8) -> [u8; 3] {
+        let mut index = index & 0x3F;
+        if mask & 0x01 != 0 {
+            index &= 0x30; // Greyscale
+        }
+        self.rgb(index as u16 | (mask as u16 & 0xE0) << 1)
+    }
+
+    // Converts a PPU frame into packed 24-bit RGB
+    pub fn to_rgb(&self, frame: &[u16]) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(frame.len() * 3);
+        for &pixel in frame {
+            rgb.extend_from_slice(&self.rgb(pixel));
+        }
+        rgb
+    }
+}
+
+// Voltage the PPU outputs for a pixel at one of the 12 phases of the colour subcarrier
+pub(crate) fn composite_signal(pixel: u16, phase: usize) -> f32 {
+    let colour = (pixel & 0x0F) as usize;
+    let mut level = ((pixel >> 4) & 0x03) as usize;
+    let emphasis = (pixel >> 6) & 0x07;
+    if colour > 13 {
+        level = 1; // Columns $E/$F are forced to black
+    }
+
+    let mut low = SIGNAL_LEVELS[level];
+    let mut high = SIGNAL_LEVELS[4 + level];
+    if colour == 0 {
+        low = high;
+    }
+    if colour > 12 {
+        high = low;
+    }
+
+    let in_phase = |colour: usize| (colour + phase) % 12 < 6;
+    let mut signal = if in_phase(colour) { high } else { low };
+    if (emphasis & 0x01 != 0 && in_phase(0))
+        || (emphasis & 0x02 != 0 && in_phase(4))
+        || (emphasis & 0x04 != 0 && in_phase(8))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    signal
+}
+
+// Brightness of a pixel from 0 (black) to 1 (white), averaged over a colour cycle
+pub(crate) fn luma(pixel: u16) -> f32 {
+    let signal: f32 = (0..12).map(|phase| composite_signal(pixel, phase)).sum::<f32>() / 12.0;
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+// Demodulates one full colour cycle of a pixel into RGB
+fn decode_pixel(pixel: u16) -> [u8; 3] {
+    let (mut y, mut i, mut q) = (0.0f32, 0.0f32, 0.0f32);
+    for phase in 0..12 {
+        let signal = (composite_signal(pixel, phase) - BLACK) / (WHITE - BLACK);
+        let angle = std::f32::consts::PI * (phase as f32 + DEFAULT_HUE) / 6.0;
+        y += signal;
+        i += signal * angle.cos();
+        q += signal * angle.sin();
+    }
+    y /= 12.0;
+    i = i / 12.0 * DEFAULT_SATURATION;
+    q = q / 12.0 * DEFAULT_SATURATION;
+    yiq_to_rgb(y, i, q)
+}
+
+pub(crate) fn yiq_to_rgb(y: f32, i: f32, q: f32) -> [u8; 3] {
+    let r = y + 0.946882 * i + 0.623557 * q;
+    let g = y - 0.274788 * i - 0.635691 * q;
+    let b = y - 1.108545 * i + 1.709007 * q;
+    [gamma(r), gamma(g), gamma(b)]
+}
+
+// The signal is linear but displays expect a gamma-corrected value
+fn gamma(value: f32) -> u8 {
+    if value <= 0.0 {
+        return 0;
+    }
+    (value.powf(2.2 / 1.8) * 255.0).round().min(255.0) as u8
+}
+
+// Emphasis darkens the channels that are not emphasized. Black columns are unaffected.
+fn apply_emphasis(colour: [u8; 3], pixel: u16) -> [u8; 3] {
+    let emphasis = (pixel >> 6) & 0x07;
+    if emphasis == 0 || pixel & 0x0E == 0x0E {
+        return colour;
+    }
+    let mut result = colour;
+    for (channel, value) in result.iter_mut().enumerate() {
+        if emphasis & (1 << channel) == 0 {
+            *value = (*value as f32 * EMPHASIS_ATTENUATION).round() as u8;
+        }
+    }
+    result
+}
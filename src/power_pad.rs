@@ -0,0 +1,66 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Buttons in the order the NES Power Pad shifts them out on D3 and D4
+const D3_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_ORDER: [u8; 4] = [4, 3, 12, 8];
+
+// The twelve-button floor mat. The NES version is a pair of shift registers on a
+// controller port; the Famicom's Family Trainer is a matrix on the expansion port.
+#[derive(Clone, Debug, Default)]
+pub struct PowerPad {
+    pub buttons: u16, // Bit 0 is button 1, bit 11 button 12, numbered as on side B
+    shift: [u8; 2],
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.shift[0]);
+        state.u8(self.shift[1]);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift = [state.u8()?, state.u8()?];
+        Ok(())
+    }
+
+    // Buttons are numbered from 1; anything outside the 16 bits reads as released
+    pub fn pressed(&self, button: u8) -> bool {
+        (1..=16).contains(&button) && self.buttons & (1 << (button - 1)) != 0
+    }
+
+    pub fn latch(&mut self) {
+        let bits = |order: &[u8]| {
+            order.iter().enumerate().fold(0u8, |bits, (bit, &button)| bits | (self.pressed(button) as u8) << bit)
+        };
+        self.shift = [bits(&D3_ORDER), bits(&D4_ORDER) | 0xF0];
+    }
+
+    // D3 and D4 of the port, 1 for a pressed button. Past the last button both read 1.
+    pub fn data(&self) -> u8 {
+        (self.shift[0] & 0x01) << 3 | (self.shift[1] & 0x01) << 4
+    }
+
+    pub fn clock(&mut self) {
+        for shift in self.shift.iter_mut() {
+            *shift = *shift >> 1 | 0x80;
+        }
+    }
+
+    // Family Trainer: a clear bit in the $4016 write selects a row of four buttons
+    // (bit 2 buttons 1-4, bit 1 5-8, bit 0 9-12), which read back on D4-D1 as 0 when pressed.
+    pub fn matrix(&self, select: u8) -> u8 {
+        let mut pressed = 0;
+        for (row, first) in [(0x04, 1), (0x02, 5), (0x01, 9)] {
+            if select & row == 0 {
+                for column in 0..4 {
+                    pressed |= (self.pressed(first + column) as u8) << (4 - column);
+                }
+            }
+        }
+        !pressed & 0x1E
+    }
+}
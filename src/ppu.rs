@@ -0,0 +1,611 @@
+use crate::mapper::Mapper;
+use crate::ppu_bus::PpuBus;
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+
+pub struct PPU {
+    pub ctrl: u8,       //PPUCTRL ($2000)
+    pub mask: u8,       //PPUMASK ($2001)
+    pub status: u8,     //PPUSTATUS ($2002)
+    pub oam: [u8; 256], //Object Attribute Memory (sprite data)
+    pub oam_addr: u8,   //OAMADDR ($2003)
+    pub bus: PpuBus,
+    pub region: Region,
+
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame_count: u64,
+    // One entry per pixel: palette index in bits 0-5, PPUMASK emphasis in bits 6-8
+    pub frame: Vec<u16>,
+
+    v: u16,      // Current VRAM address
+    t: u16,      // Temporary VRAM address (top-left of the screen)
+    fine_x: u8,  // Fine X scroll
+    w: bool,     // First/second write toggle for $2005/$2006
+    read_buffer: u8,
+    io_latch: u8, // Last value driven on the CPU-PPU data bus
+    nmi_pending: bool,
+    odd_frame: bool,
+
+    // Background fetch latches and shift registers
+    nametable_latch: u8,
+    attribute_latch: u8,
+    pattern_lo_latch: u8,
+    pattern_hi_latch: u8,
+    bg_pattern_lo: u16,
+    bg_pattern_hi: u16,
+    bg_attribute_lo: u16,
+    bg_attribute_hi: u16,
+
+    // Sprites found during evaluation: (y, tile, attributes, x) from OAM
+    secondary_oam: [[u8; 4]; 8],
+    secondary_count: usize,
+    secondary_has_sprite_zero: bool,
+
+    // Sprites being drawn on the current scanline
+    sprite_count: usize,
+    sprite_pattern_lo: [u8; 8],
+    sprite_pattern_hi: [u8; 8],
+    sprite_attributes: [u8; 8],
+    sprite_x: [u8; 8],
+    sprite_zero_on_line: bool,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PPU {
+    pub fn new() -> Self {
+        PPU {
+            ctrl: 0x00,
+            mask: 0x00,
+            status: 0x00,
+            oam: [0; 256],
+            oam_addr: 0x00,
+            bus: PpuBus::new(),
+            region: Region::Ntsc,
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            nmi_pending: false,
+            odd_frame: false,
+            nametable_latch: 0,
+            attribute_latch: 0,
+            pattern_lo_latch: 0,
+            pattern_hi_latch: 0,
+            bg_pattern_lo: 0,
+            bg_pattern_hi: 0,
+            bg_attribute_lo: 0,
+            bg_attribute_hi: 0,
+            secondary_oam: [[0xFF; 4]; 8],
+            secondary_count: 0,
+            secondary_has_sprite_zero: false,
+            sprite_count: 0,
+            sprite_pattern_lo: [0; 8],
+            sprite_pattern_hi: [0; 8],
+            sprite_attributes: [0; 8],
+            sprite_x: [0; 8],
+            sprite_zero_on_line: false,
+        }
+    }
+
+    // Everything but the finished frame, which the next frame redraws
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.ctrl);
+        state.u8(self.mask);
+        state.u8(self.status);
+        state.bytes(&self.oam);
+        state.u8(self.oam_addr);
+        self.bus.save_state(state);
+        state.u16(self.scanline);
+        state.u16(self.dot);
+        state.u64(self.frame_count);
+
+        state.u16(self.v);
+        state.u16(self.t);
+        state.u8(self.fine_x);
+        state.bool(self.w);
+        state.u8(self.read_buffer);
+        state.u8(self.io_latch);
+        state.bool(self.nmi_pending);
+        state.bool(self.odd_frame);
+
+        state.u8(self.nametable_latch);
+        state.u8(self.attribute_latch);
+        state.u8(self.pattern_lo_latch);
+        state.u8(self.pattern_hi_latch);
+        state.u16(self.bg_pattern_lo);
+        state.u16(self.bg_pattern_hi);
+        state.u16(self.bg_attribute_lo);
+        state.u16(self.bg_attribute_hi);
+
+        state.bytes(self.secondary_oam.as_flattened());
+        state.usize(self.secondary_count);
+        state.bool(self.secondary_has_sprite_zero);
+        state.usize(self.sprite_count);
+        state.bytes(&self.sprite_pattern_lo);
+        state.bytes(&self.sprite_pattern_hi);
+        state.bytes(&self.sprite_attributes);
+        state.bytes(&self.sprite_x);
+        state.bool(self.sprite_zero_on_line);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ctrl = state.u8()?;
+        self.mask = state.u8()?;
+        self.status = state.u8()?;
+        state.fill(&mut self.oam, "OAM size")?;
+        self.oam_addr = state.u8()?;
+        self.bus.load_state(state)?;
+        self.scanline = state.u16()?;
+        self.dot = state.u16()?;
+        if self.scanline >= self.region.scanlines_per_frame() || self.dot >= DOTS_PER_SCANLINE {
+            return Err(StateError::Invalid("PPU position"));
+        }
+        self.frame_count = state.u64()?;
+
+        self.v = state.u16()?;
+        self.t = state.u16()?;
+        self.fine_x = state.u8()? & 0x07;
+        self.w = state.bool()?;
+        self.read_buffer = state.u8()?;
+        self.io_latch = state.u8()?;
+        self.nmi_pending = state.bool()?;
+        self.odd_frame = state.bool()?;
+
+        self.nametable_latch = state.u8()?;
+        self.attribute_latch = state.u8()?;
+        self.pattern_lo_latch = state.u8()?;
+        self.pattern_hi_latch = state.u8()?;
+        self.bg_pattern_lo = state.u16()?;
+        self.bg_pattern_hi = state.u16()?;
+        self.bg_attribute_lo = state.u16()?;
+        self.bg_attribute_hi = state.u16()?;
+
+        state.fill(self.secondary_oam.as_flattened_mut(), "secondary OAM size")?;
+        self.secondary_count = state.usize()?.min(8);
+        self.secondary_has_sprite_zero = state.bool()?;
+        self.sprite_count = state.usize()?.min(8);
+        state.fill(&mut self.sprite_pattern_lo, "sprite count")?;
+        state.fill(&mut self.sprite_pattern_hi, "sprite count")?;
+        state.fill(&mut self.sprite_attributes, "sprite count")?;
+        state.fill(&mut self.sprite_x, "sprite count")?;
+        self.sprite_zero_on_line = state.bool()?;
+        Ok(())
+    }
+
+    // CPU-side register access, $2000-$2007 mirrored every 8 bytes up to $3FFF
+    pub fn read_register(&mut self, address: u16, cartridge: &mut Option<Box<dyn Mapper>>) -> u8 {
+        let value = match address & 0x0007 {
+            0x0002 => {
+                // PPUSTATUS: reading clears vblank and the write toggle
+                let value = (self.status & 0xE0) | (self.io_latch & 0x1F);
+                self.status &= !0x80;
+                self.w = false;
+                value
+            }
+            0x0004 => self.oam[self.oam_addr as usize], // OAMDATA
+            0x0007 => {
+                // PPUDATA: reads below the palette go through a one-byte buffer
+                let address = self.v & 0x3FFF;
+                let value = if address >= 0x3F00 {
+                    self.read_buffer = self.bus.read(address - 0x1000, cartridge);
+                    self.bus.peek_palette(address) | (self.io_latch & 0xC0)
+                } else {
+                    let buffered = self.read_buffer;
+                    self.read_buffer = self.bus.read(address, cartridge);
+                    buffered
+                };
+                self.increment_vram_address();
+                value
+            }
+            _ => self.io_latch, // Write-only registers return the open bus latch
+        };
+        self.io_latch = value;
+        value
+    }
+
+    pub fn peek_register(&self, address: u16) -> u8 {
+        match address & 0x0007 {
+            0x0002 => (self.status & 0xE0) | (self.io_latch & 0x1F),
+            0x0004 => self.oam[self.oam_addr as usize],
+            0x0007 => {
+                let address = self.v & 0x3FFF;
+                if address >= 0x3F00 {
+                    self.bus.peek_palette(address) | (self.io_latch & 0xC0)
+                } else {
+                    self.read_buffer
+                }
+            }
+            _ => self.io_latch,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8, cartridge: &mut Option<Box<dyn Mapper>>) {
+        self.io_latch = value;
+        match address & 0x0007 {
+            0x0000 => {
+                // PPUCTRL: enabling NMI during vblank fires one immediately
+                if self.ctrl & 0x80 == 0 && value & 0x80 != 0 && self.status & 0x80 != 0 {
+                    self.nmi_pending = true;
+                }
+                self.ctrl = value;
+                self.t = (self.t & !0x0C00) | ((value as u16 & 0x03) << 10);
+            }
+            0x0001 => self.mask = value, // PPUMASK
+            0x0003 => self.oam_addr = value, // OAMADDR
+            0x0004 => {
+                // OAMDATA
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            0x0005 => {
+                // PPUSCROLL
+                if !self.w {
+                    self.t = (self.t & !0x001F) | (value as u16 >> 3);
+                    self.fine_x = value & 0x07;
+                } else {
+                    self.t = (self.t & !0x73E0)
+                        | ((value as u16 & 0x07) << 12)
+                        | ((value as u16 & 0xF8) << 2);
+                }
+                self.w = !self.w;
+            }
+            0x0006 => {
+                // PPUADDR: high byte first, the second write copies t into v
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                    self.bus.address = self.v & 0x3FFF;
+                }
+                self.w = !self.w;
+            }
+            0x0007 => {
+                // PPUDATA
+                self.bus.write(self.v & 0x3FFF, value, cartridge);
+                self.increment_vram_address();
+            }
+            _ => {}
+        }
+    }
+
+    // Returns true once for every NMI the PPU has raised
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        if self.scanline >= region.scanlines_per_frame() {
+            self.scanline = 0;
+        }
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask & 0x18 != 0
+    }
+
+    fn increment_vram_address(&mut self) {
+        let step = if self.ctrl & 0x04 != 0 { 32 } else { 1 };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+        self.bus.address = self.v & 0x3FFF;
+    }
+
+    // Advances the PPU by one dot
+    pub fn tick(&mut self, cartridge: &mut Option<Box<dyn Mapper>>) {
+        let visible_line = self.scanline < SCREEN_HEIGHT as u16;
+        let pre_render_line = self.scanline == self.region.scanlines_per_frame() - 1;
+
+        if self.rendering_enabled() && (visible_line || pre_render_line) {
+            self.render_dot(pre_render_line, cartridge);
+        }
+
+        if visible_line && (1..=256).contains(&self.dot) {
+            self.output_pixel();
+        }
+
+        if let Some(mapper) = cartridge {
+            mapper.ppu_clock(self.bus.address);
+        }
+
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            self.status |= 0x80;
+            self.frame_count += 1;
+            if self.ctrl & 0x80 != 0 {
+                self.nmi_pending = true;
+            }
+        }
+
+        if pre_render_line && self.dot == 1 {
+            self.status &= !0xE0; // Clear vblank, sprite 0 hit and overflow
+        }
+
+        // NTSC odd frames skip the last dot of the pre-render line while rendering
+        if pre_render_line
+            && self.dot == 339
+            && self.odd_frame
+            && self.rendering_enabled()
+            && self.region.skips_odd_frame_dot()
+        {
+            self.dot = 340;
+        }
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    fn render_dot(&mut self, pre_render_line: bool, cartridge: &mut Option<Box<dyn Mapper>>) {
+        let dot = self.dot;
+
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.nametable_latch = self.bus.read(0x2000 | (self.v & 0x0FFF), cartridge);
+                }
+                2 => {
+                    let address = 0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07);
+                    let shift = ((self.v >> 4) & 0x04) | (self.v & 0x02);
+                    self.attribute_latch = (self.bus.read(address, cartridge) >> shift) & 0x03;
+                }
+                4 => self.pattern_lo_latch = self.bus.read(self.background_pattern_address(), cartridge),
+                6 => self.pattern_hi_latch = self.bus.read(self.background_pattern_address() + 8, cartridge),
+                7 => self.increment_coarse_x(),
+                _ => {}
+            }
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            257 => {
+                self.load_background_shifters();
+                self.v = (self.v & !0x041F) | (self.t & 0x041F);
+                self.evaluate_sprites(pre_render_line);
+            }
+            280..=304 if pre_render_line => {
+                self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+            }
+            337 | 339 => {
+                self.bus.read(0x2000 | (self.v & 0x0FFF), cartridge);
+            }
+            _ => {}
+        }
+
+        // Sprite pattern fetches for the next scanline, eight dots per slot
+        if (257..=320).contains(&dot) {
+            let slot = ((dot - 257) / 8) as usize;
+            match (dot - 257) % 8 {
+                4 => {
+                    let address = self.sprite_pattern_address(slot);
+                    self.sprite_pattern_lo[slot] = self.fetch_sprite_pattern(slot, address, cartridge);
+                }
+                6 => {
+                    let address = self.sprite_pattern_address(slot) + 8;
+                    self.sprite_pattern_hi[slot] = self.fetch_sprite_pattern(slot, address, cartridge);
+                }
+                7 if slot == 7 => {
+                    self.sprite_count = self.secondary_count;
+                    self.sprite_zero_on_line = self.secondary_has_sprite_zero;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn background_pattern_address(&self) -> u16 {
+        let table = (self.ctrl as u16 & 0x10) << 8;
+        let fine_y = (self.v >> 12) & 0x07;
+        table | (self.nametable_latch as u16) << 4 | fine_y
+    }
+
+    fn shift_background(&mut self) {
+        self.bg_pattern_lo <<= 1;
+        self.bg_pattern_hi <<= 1;
+        self.bg_attribute_lo <<= 1;
+        self.bg_attribute_hi <<= 1;
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.bg_pattern_lo = (self.bg_pattern_lo & 0xFF00) | self.pattern_lo_latch as u16;
+        self.bg_pattern_hi = (self.bg_pattern_hi & 0xFF00) | self.pattern_hi_latch as u16;
+        let attribute_lo = if self.attribute_latch & 0x01 != 0 { 0xFF } else { 0x00 };
+        let attribute_hi = if self.attribute_latch & 0x02 != 0 { 0xFF } else { 0x00 };
+        self.bg_attribute_lo = (self.bg_attribute_lo & 0xFF00) | attribute_lo;
+        self.bg_attribute_hi = (self.bg_attribute_hi & 0xFF00) | attribute_hi;
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400; // Switch horizontal nametable
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000; // Fine Y
+        } else {
+            self.v &= !0x7000;
+            let mut coarse_y = (self.v & 0x03E0) >> 5;
+            if coarse_y == 29 {
+                coarse_y = 0;
+                self.v ^= 0x0800; // Switch vertical nametable
+            } else if coarse_y == 31 {
+                coarse_y = 0;
+            } else {
+                coarse_y += 1;
+            }
+            self.v = (self.v & !0x03E0) | (coarse_y << 5);
+        }
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & 0x20 != 0 { 16 } else { 8 }
+    }
+
+    // Finds the first eight sprites on the next scanline
+    fn evaluate_sprites(&mut self, pre_render_line: bool) {
+        self.secondary_count = 0;
+        self.secondary_has_sprite_zero = false;
+        self.secondary_oam = [[0xFF; 4]; 8];
+        if pre_render_line {
+            return;
+        }
+
+        let height = self.sprite_height();
+        for index in 0..64 {
+            let entry = &self.oam[index * 4..index * 4 + 4];
+            let row = self.scanline.wrapping_sub(entry[0] as u16);
+            if row >= height {
+                continue;
+            }
+            if self.secondary_count == 8 {
+                self.status |= 0x20; // Sprite overflow
+                break;
+            }
+            self.secondary_oam[self.secondary_count].copy_from_slice(entry);
+            if index == 0 {
+                self.secondary_has_sprite_zero = true;
+            }
+            self.secondary_count += 1;
+        }
+    }
+
+    fn sprite_pattern_address(&self, slot: usize) -> u16 {
+        let [y, tile, attributes, _] = self.secondary_oam[slot];
+        let height = self.sprite_height();
+        // Empty slots still fetch tile $FF, which mappers watching A12 rely on
+        let mut row = if slot < self.secondary_count {
+            self.scanline.wrapping_sub(y as u16) & (height - 1)
+        } else {
+            0
+        };
+        if attributes & 0x80 != 0 && slot < self.secondary_count {
+            row = height - 1 - row; // Vertical flip
+        }
+
+        if height == 16 {
+            let table = (tile as u16 & 0x01) << 12;
+            let tile = (tile & 0xFE) as u16 + (row >> 3);
+            table | tile << 4 | (row & 0x07)
+        } else {
+            let table = (self.ctrl as u16 & 0x08) << 9;
+            table | (tile as u16) << 4 | row
+        }
+    }
+
+    fn fetch_sprite_pattern(&mut self, slot: usize, address: u16, cartridge: &mut Option<Box<dyn Mapper>>) -> u8 {
+        let value = self.bus.read(address, cartridge);
+        if slot >= self.secondary_count {
+            return 0;
+        }
+        let [_, _, attributes, x] = self.secondary_oam[slot];
+        self.sprite_attributes[slot] = attributes;
+        self.sprite_x[slot] = x;
+        if attributes & 0x40 != 0 {
+            value.reverse_bits() // Horizontal flip
+        } else {
+            value
+        }
+    }
+
+    fn background_pixel(&self, x: usize) -> (u8, u8) {
+        if self.mask & 0x08 == 0 || (x < 8 && self.mask & 0x02 == 0) {
+            return (0, 0);
+        }
+        let bit = 0x8000 >> self.fine_x;
+        let pixel = ((self.bg_pattern_hi & bit != 0) as u8) << 1 | (self.bg_pattern_lo & bit != 0) as u8;
+        let palette = ((self.bg_attribute_hi & bit != 0) as u8) << 1 | (self.bg_attribute_lo & bit != 0) as u8;
+        (pixel, palette)
+    }
+
+    // Returns (pixel, palette, behind background, is sprite 0) for the first opaque sprite
+    fn sprite_pixel(&self, x: usize) -> Option<(u8, u8, bool, bool)> {
+        if self.mask & 0x10 == 0 || (x < 8 && self.mask & 0x04 == 0) {
+            return None;
+        }
+        for slot in 0..self.sprite_count {
+            let offset = x as i16 - self.sprite_x[slot] as i16;
+            if !(0..8).contains(&offset) {
+                continue;
+            }
+            let shift = 7 - offset;
+            let pixel = ((self.sprite_pattern_hi[slot] >> shift) & 0x01) << 1
+                | ((self.sprite_pattern_lo[slot] >> shift) & 0x01);
+            if pixel == 0 {
+                continue;
+            }
+            let attributes = self.sprite_attributes[slot];
+            return Some((pixel, attributes & 0x03, attributes & 0x20 != 0, slot == 0 && self.sprite_zero_on_line));
+        }
+        None
+    }
+
+    fn output_pixel(&mut self) {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+
+        let palette_address = if self.rendering_enabled() {
+            let (bg_pixel, bg_palette) = self.background_pixel(x);
+            match self.sprite_pixel(x) {
+                Some((sprite_pixel, sprite_palette, behind, sprite_zero)) => {
+                    if bg_pixel != 0 && sprite_zero && x != 255 {
+                        self.status |= 0x40; // Sprite 0 hit
+                    }
+                    if bg_pixel != 0 && behind {
+                        0x3F00 | (bg_palette as u16) << 2 | bg_pixel as u16
+                    } else {
+                        0x3F10 | (sprite_palette as u16) << 2 | sprite_pixel as u16
+                    }
+                }
+                None if bg_pixel != 0 => 0x3F00 | (bg_palette as u16) << 2 | bg_pixel as u16,
+                None => 0x3F00,
+            }
+        } else if self.v & 0x3F00 == 0x3F00 {
+            self.v & 0x3F1F // With rendering off, a palette address in v shows that colour
+        } else {
+            0x3F00
+        };
+
+        let mut colour = self.bus.peek_palette(palette_address);
+        if self.mask & 0x01 != 0 {
+            colour &= 0x30; // Greyscale
+        }
+        let mut emphasis = (self.mask as u16 & 0xE0) << 1;
+        if self.region.swaps_emphasis() {
+            // Store emphasis in NTSC order (red, green, blue) for the palette
+            emphasis = (emphasis & 0x100) | (emphasis & 0x40) << 1 | (emphasis & 0x80) >> 1;
+        }
+        self.frame[y * SCREEN_WIDTH + x] = colour as u16 | emphasis;
+    }
+}
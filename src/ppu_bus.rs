@@ -0,0 +1,132 @@
+use crate::mapper::Mapper;
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,    // $2000 = $2400, $2800 = $2C00
+    Vertical,      // $2000 = $2800, $2400 = $2C00
+    SingleScreenA, // Every nametable maps to the first 1 KiB page
+    SingleScreenB, // Every nametable maps to the second 1 KiB page
+    FourScreen,    // Four distinct nametables, the extra 2 KiB lives on the cartridge
+}
+
+impl Mirroring {
+    // Physical 1 KiB page used for each of the four logical nametables
+    fn pages(&self) -> [usize; 4] {
+        match self {
+            Mirroring::Horizontal => [0, 0, 1, 1],
+            Mirroring::Vertical => [0, 1, 0, 1],
+            Mirroring::SingleScreenA => [0, 0, 0, 0],
+            Mirroring::SingleScreenB => [1, 1, 1, 1],
+            Mirroring::FourScreen => [0, 1, 2, 3],
+        }
+    }
+
+    // Maps $2000-$3EFF to an offset into the 4 KiB of nametable VRAM
+    pub fn nametable_index(&self, address: u16) -> usize {
+        let address = (address as usize - 0x2000) & 0x0FFF;
+        let table = address / 0x0400;
+        self.pages()[table] * 0x0400 + (address & 0x03FF)
+    }
+}
+
+// The PPU's 14-bit address space:
+// $0000-$1FFF pattern tables, $2000-$2FFF nametables (mirrored at $3000-$3EFF),
+// $3F00-$3FFF palette RAM (32 bytes mirrored).
+// Pattern tables and mirroring come from the cartridge when one is inserted.
+pub struct PpuBus {
+    pub chr: Vec<u8>,     // Pattern tables used without a cartridge, 8 KiB of CHR-RAM
+    pub vram: [u8; 4096], // 2 KiB console VRAM plus 2 KiB for four-screen boards
+    pub palette: [u8; 32],
+    pub mirroring: Mirroring,
+    pub address: u16, // Last address driven onto the bus, mappers watch its A12 line
+}
+
+impl Default for PpuBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PpuBus {
+    pub fn new() -> Self {
+        PpuBus {
+            chr: vec![0; 0x2000],
+            vram: [0; 4096],
+            palette: [0; 32],
+            mirroring: Mirroring::Horizontal,
+            address: 0,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.chr);
+        state.bytes(&self.vram);
+        state.bytes(&self.palette);
+        state.mirroring(self.mirroring);
+        state.u16(self.address);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.fill(&mut self.chr, "CHR-RAM size")?;
+        state.fill(&mut self.vram, "VRAM size")?;
+        state.fill(&mut self.palette, "palette size")?;
+        self.mirroring = state.mirroring()?;
+        self.address = state.u16()?;
+        Ok(())
+    }
+
+    // Mirroring used without a cartridge. Mappers report their own at runtime.
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
+    pub fn read(&mut self, address: u16, cartridge: &mut Option<Box<dyn Mapper>>) -> u8 {
+        let address = address & 0x3FFF;
+        self.address = address;
+        match address {
+            0x0000..=0x1FFF => match cartridge {
+                Some(mapper) => mapper.read_chr(address),
+                None => self.chr[address as usize % self.chr.len()],
+            },
+            0x2000..=0x3EFF => match cartridge {
+                Some(mapper) => mapper.read_nametable(address, &self.vram),
+                None => self.vram[self.mirroring.nametable_index(address)],
+            },
+            _ => self.peek_palette(address),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, cartridge: &mut Option<Box<dyn Mapper>>) {
+        let address = address & 0x3FFF;
+        self.address = address;
+        match address {
+            0x0000..=0x1FFF => match cartridge {
+                Some(mapper) => mapper.write_chr(address, value),
+                None => {
+                    let len = self.chr.len();
+                    self.chr[address as usize % len] = value;
+                }
+            },
+            0x2000..=0x3EFF => match cartridge {
+                Some(mapper) => mapper.write_nametable(address, value, &mut self.vram),
+                None => self.vram[self.mirroring.nametable_index(address)] = value,
+            },
+            _ => self.palette[Self::palette_index(address)] = value & 0x3F,
+        }
+    }
+
+    pub fn peek_palette(&self, address: u16) -> u8 {
+        self.palette[Self::palette_index(address)]
+    }
+
+    // $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
+    pub fn palette_index(address: u16) -> usize {
+        let index = (address & 0x001F) as usize;
+        if index & 0x13 == 0x10 {
+            index & !0x10
+        } else {
+            index
+        }
+    }
+}
@@ -0,0 +1,124 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy, // Famiclone timing: PAL clocks with an NTSC-like frame layout
+}
+
+const NTSC_NOISE_PERIODS: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const PAL_NOISE_PERIODS: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+const NTSC_DMC_RATES: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+const PAL_DMC_RATES: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+impl Region {
+    // NES 2.0 header byte 12: 0 = NTSC, 1 = PAL, 2 = multi-region, 3 = Dendy
+    pub fn from_nes2_timing(value: u8) -> Region {
+        match value & 0x03 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            _ => Region::Ntsc,
+        }
+    }
+
+    pub fn master_clock_hz(&self) -> f64 {
+        match self {
+            Region::Ntsc => 21_477_272.0,
+            Region::Pal | Region::Dendy => 26_601_712.0,
+        }
+    }
+
+    // Master clock cycles per CPU cycle
+    pub fn cpu_divider(&self) -> u32 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    // Master clock cycles per PPU dot
+    pub fn ppu_divider(&self) -> u32 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    pub fn cpu_clock_hz(&self) -> f64 {
+        self.master_clock_hz() / self.cpu_divider() as f64
+    }
+
+    // PPU dots per CPU cycle: 3 on NTSC and Dendy, 3.2 on PAL
+    pub fn ppu_cpu_ratio(&self) -> f64 {
+        self.cpu_divider() as f64 / self.ppu_divider() as f64
+    }
+
+    pub fn scanlines_per_frame(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Scanline on which the vblank flag is raised
+    pub fn vblank_scanline(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291, // 51 post-render lines keep vblank as short as NTSC's
+        }
+    }
+
+    // Scanlines between the vblank flag being set and the pre-render line
+    pub fn vblank_length(&self) -> u16 {
+        self.scanlines_per_frame() - 1 - self.vblank_scanline()
+    }
+
+    // Only the NTSC PPU drops a dot on odd frames
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    // The 2C07 swaps the red and green emphasis bits of PPUMASK
+    pub fn swaps_emphasis(&self) -> bool {
+        *self == Region::Pal
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        let dots_per_frame = self.scanlines_per_frame() as f64 * 341.0;
+        self.master_clock_hz() / self.ppu_divider() as f64 / dots_per_frame
+    }
+
+    // CPU cycles at which the APU frame counter's steps fall in 4-step mode
+    pub fn frame_counter_four_step(&self) -> [u32; 4] {
+        match self {
+            Region::Pal => [8313, 16627, 24939, 33253],
+            Region::Ntsc | Region::Dendy => [7457, 14913, 22371, 29829],
+        }
+    }
+
+    // CPU cycles at which the APU frame counter's steps fall in 5-step mode
+    pub fn frame_counter_five_step(&self) -> [u32; 5] {
+        match self {
+            Region::Pal => [8313, 16627, 24939, 33253, 41565],
+            Region::Ntsc | Region::Dendy => [7457, 14913, 22371, 29829, 37281],
+        }
+    }
+
+    // Timer periods for the noise channel, in CPU cycles
+    pub fn noise_periods(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Pal => &PAL_NOISE_PERIODS,
+            Region::Ntsc | Region::Dendy => &NTSC_NOISE_PERIODS,
+        }
+    }
+
+    // Timer periods for the DMC, in CPU cycles
+    pub fn dmc_rates(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Pal => &PAL_DMC_RATES,
+            Region::Ntsc | Region::Dendy => &NTSC_DMC_RATES,
+        }
+    }
+}
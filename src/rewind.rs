@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+
+use crate::nes::Nes;
+use crate::state::StateError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RewindConfig {
+    pub seconds: f64,          // How far back rewinding can go
+    pub interval: u32,         // Frames between snapshots, 1 to rewind frame by frame
+    pub keyframe_every: usize, // Snapshots per keyframe; the rest are stored as deltas against it
+    pub memory_budget: usize,  // Upper bound on the bytes held by snapshots
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        RewindConfig {
+            seconds: 30.0,
+            interval: 1,
+            keyframe_every: 60,
+            memory_budget: 64 * 1024 * 1024,
+        }
+    }
+}
+
+// A keyframe and the snapshots taken after it, each XORed against the keyframe
+// and run-length encoded. Most of the machine doesn't change from frame to frame,
+// so the deltas are mostly runs of zeros.
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(|delta| delta.len()).sum::<usize>()
+    }
+
+    fn len(&self) -> usize {
+        1 + self.deltas.len()
+    }
+}
+
+// Rewind history for one console. Call end_frame after every frame; step_back
+// restores the snapshot before the current one.
+pub struct Rewind {
+    config: RewindConfig,
+    groups: VecDeque<Group>,
+    size: usize,
+    frames: u32,
+    at_snapshot: bool,        // The newest snapshot is the machine's current state
+    frame_rate: f64,          // Of the console being recorded, to turn seconds into snapshots
+    newest_keyframe: Vec<u8>, // The newest group's keyframe, decoded
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(RewindConfig::default())
+    }
+}
+
+impl Rewind {
+    pub fn new(config: RewindConfig) -> Self {
+        Rewind {
+            config,
+            groups: VecDeque::new(),
+            size: 0,
+            frames: 0,
+            at_snapshot: false,
+            frame_rate: 60.0,
+            newest_keyframe: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> RewindConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: RewindConfig) {
+        self.config = config;
+        self.trim();
+    }
+
+    // Number of snapshots held
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(Group::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    // Bytes held by snapshots
+    pub fn memory_used(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.size = 0;
+        self.frames = 0;
+        self.at_snapshot = false;
+        self.newest_keyframe.clear();
+    }
+
+    // Called once per frame; takes a snapshot every `interval` frames
+    pub fn end_frame(&mut self, nes: &Nes) {
+        self.frames += 1;
+        if self.frames >= self.config.interval.max(1) {
+            self.capture(nes);
+        } else {
+            self.at_snapshot = false;
+        }
+    }
+
+    pub fn capture(&mut self, nes: &Nes) {
+        self.frames = 0;
+        let state = nes.save_state();
+        let group = self.groups.back_mut().filter(|group| {
+            group.len() < self.config.keyframe_every.max(1) && self.newest_keyframe.len() == state.len()
+        });
+
+        if let Some(group) = group {
+            let delta = encode(&state, Some(&self.newest_keyframe));
+            self.size += delta.len();
+            group.deltas.push(delta);
+        } else {
+            let group = Group { keyframe: encode(&state, None), deltas: Vec::new() };
+            self.size += group.size();
+            self.groups.push_back(group);
+            self.newest_keyframe = state;
+        }
+        self.at_snapshot = true;
+        self.frame_rate = nes.memory.region().frame_rate();
+        self.trim();
+    }
+
+    // Snapshots needed to cover the configured number of seconds
+    fn capacity(&self) -> usize {
+        let snapshots = self.config.seconds * self.frame_rate / self.config.interval.max(1) as f64;
+        (snapshots.ceil() as usize).max(1)
+    }
+
+    // Goes back one snapshot. Returns false when there is nothing older to go back to.
+    pub fn step_back(&mut self, nes: &mut Nes) -> Result<bool, StateError> {
+        if self.at_snapshot {
+            if self.len() < 2 {
+                return Ok(false);
+            }
+            self.pop();
+        }
+        let Some(group) = self.groups.back() else {
+            return Ok(false);
+        };
+        let state = match group.deltas.last() {
+            Some(delta) => decode(delta, Some(&self.newest_keyframe)),
+            None => self.newest_keyframe.clone(),
+        };
+        nes.load_state(&state)?;
+        self.frames = 0;
+        self.at_snapshot = true;
+        Ok(true)
+    }
+
+    fn pop(&mut self) {
+        let Some(group) = self.groups.back_mut() else {
+            return;
+        };
+        if let Some(delta) = group.deltas.pop() {
+            self.size -= delta.len();
+        } else {
+            self.size -= group.keyframe.len();
+            self.groups.pop_back();
+            self.newest_keyframe = self.groups.back().map(|group| decode(&group.keyframe, None)).unwrap_or_default();
+        }
+    }
+
+    // Drops the oldest groups until the history fits. The newest group always stays.
+    fn trim(&mut self) {
+        let capacity = self.capacity();
+        while self.groups.len() > 1 {
+            let oldest = &self.groups[0];
+            let over_depth = self.len() - oldest.len() >= capacity;
+            let over_budget = self.size > self.config.memory_budget;
+            if !over_depth && !over_budget {
+                break;
+            }
+            self.size -= oldest.size();
+            self.groups.pop_front();
+        }
+    }
+}
+
+// XORs data against a base (or nothing) and run-length encodes the zeros:
+// pairs of a zero run and a literal run, each length as a LEB128 varint,
+// followed by the literal bytes.
+fn encode(data: &[u8], base: Option<&[u8]>) -> Vec<u8> {
+    let xored: Vec<u8> = match base {
+        Some(base) => data.iter().zip(base).map(|(a, b)| a ^ b).collect(),
+        None => data.to_vec(),
+    };
+
+    let mut out = Vec::new();
+    write_varint(&mut out, xored.len());
+    let mut position = 0;
+    while position < xored.len() {
+        let zeros = xored[position..].iter().take_while(|&&byte| byte == 0).count();
+        position += zeros;
+        // A literal run ends at the first pair of zeros, single zeros are cheaper inline
+        let mut end = position;
+        while end < xored.len() && !(xored[end] == 0 && xored.get(end + 1).is_none_or(|&next| next == 0)) {
+            end += 1;
+        }
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, end - position);
+        out.extend_from_slice(&xored[position..end]);
+        position = end;
+    }
+    out
+}
+
+fn decode(encoded: &[u8], base: Option<&[u8]>) -> Vec<u8> {
+    let mut position = 0;
+    let len = read_varint(encoded, &mut position);
+    let mut out = vec![0; len];
+    let mut offset = 0;
+    while position < encoded.len() {
+        offset += read_varint(encoded, &mut position);
+        let literals = read_varint(encoded, &mut position);
+        out[offset..offset + literals].copy_from_slice(&encoded[position..position + literals]);
+        position += literals;
+        offset += literals;
+    }
+    if let Some(base) = base {
+        for (byte, base) in out.iter_mut().zip(base) {
+            *byte ^= base;
+        }
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+
+use crate::ppu_bus::Mirroring;
+use crate::region::Region;
+
+// Save state layout: a header (magic, format version, region and the CRC32 of the
+// ROM the state belongs to) followed by tagged sections, one per component.
+// Readers skip sections they don't know and leave components whose section is
+// missing untouched, so a section can be added without breaking older states;
+// changes to an existing section's layout bump STATE_VERSION and are handled by
+// checking `StateReader::version` where the field is read.
+pub const STATE_VERSION: u16 = 4;
+const MAGIC: &[u8; 4] = b"RNES";
+const HEADER_SIZE: usize = 4 + 2 + 1 + 4;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    InvalidHeader,
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u32, actual: u32 },
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::InvalidHeader => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "save state version {} is newer than this emulator ({})", version, STATE_VERSION)
+            }
+            StateError::RomMismatch { expected, actual } => {
+                write!(f, "save state is for ROM {:08X}, but {:08X} is inserted", expected, actual)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StateHeader {
+    pub version: u16,
+    pub region: Region,
+    pub rom_hash: u32,
+}
+
+impl StateHeader {
+    pub fn parse(data: &[u8]) -> Result<StateHeader, StateError> {
+        if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+            return Err(StateError::InvalidHeader);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version == 0 || version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let region = region_from_index(data[6]).ok_or(StateError::Invalid("region"))?;
+        let rom_hash = u32::from_le_bytes([data[7], data[8], data[9], data[10]]);
+        Ok(StateHeader { version, region, rom_hash })
+    }
+}
+
+fn region_from_index(index: u8) -> Option<Region> {
+    match index {
+        0 => Some(Region::Ntsc),
+        1 => Some(Region::Pal),
+        2 => Some(Region::Dendy),
+        _ => None,
+    }
+}
+
+fn region_index(region: Region) -> u8 {
+    match region {
+        Region::Ntsc => 0,
+        Region::Pal => 1,
+        Region::Dendy => 2,
+    }
+}
+
+// Little-endian serializer for one state
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(region: Region, rom_hash: u32) -> Self {
+        let mut data = Vec::with_capacity(HEADER_SIZE);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        data.push(region_index(region));
+        data.extend_from_slice(&rom_hash.to_le_bytes());
+        StateWriter { data }
+    }
+
+    // A section is its tag, its length and whatever `write` puts in it
+    pub fn section<F: FnOnce(&mut StateWriter)>(&mut self, tag: &[u8; 4], write: F) {
+        self.data.extend_from_slice(tag);
+        let length_at = self.data.len();
+        self.u32(0);
+        write(self);
+        let length = (self.data.len() - length_at - 4) as u32;
+        self.data[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    // Length-prefixed
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn mirroring(&mut self, value: Mirroring) {
+        self.u8(match value {
+            Mirroring::Horizontal => 0,
+            Mirroring::Vertical => 1,
+            Mirroring::SingleScreenA => 2,
+            Mirroring::SingleScreenB => 3,
+            Mirroring::FourScreen => 4,
+        });
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+// A section's tag and a reader over its body
+pub type Section<'a> = ([u8; 4], StateReader<'a>);
+
+// Reads back what StateWriter wrote
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    pub version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    // Splits a whole state into its header and sections
+    pub fn parse(data: &'a [u8]) -> Result<(StateHeader, Vec<Section<'a>>), StateError> {
+        let header = StateHeader::parse(data)?;
+        let mut reader = StateReader { data, position: HEADER_SIZE, version: header.version };
+        let mut sections = Vec::new();
+        while reader.position < data.len() {
+            let tag = reader.take(4)?;
+            let length = reader.u32()? as usize;
+            let body = reader.take(length)?;
+            let section = StateReader { data: body, position: 0, version: header.version };
+            sections.push(([tag[0], tag[1], tag[2], tag[3]], section));
+        }
+        Ok((header, sections))
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        let end = self.position.checked_add(length).ok_or(StateError::Truncated)?;
+        let slice = self.data.get(self.position..end).ok_or(StateError::Truncated)?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, StateError> {
+        usize::try_from(self.u64()?).map_err(|_| StateError::Invalid("size"))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    // Reads length-prefixed bytes into memory that must be exactly as large
+    pub fn fill(&mut self, target: &mut [u8], what: &'static str) -> Result<(), StateError> {
+        let bytes = self.bytes()?;
+        if bytes.len() != target.len() {
+            return Err(StateError::Invalid(what));
+        }
+        target.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn mirroring(&mut self) -> Result<Mirroring, StateError> {
+        match self.u8()? {
+            0 => Ok(Mirroring::Horizontal),
+            1 => Ok(Mirroring::Vertical),
+            2 => Ok(Mirroring::SingleScreenA),
+            3 => Ok(Mirroring::SingleScreenB),
+            4 => Ok(Mirroring::FourScreen),
+            _ => Err(StateError::Invalid("mirroring")),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// The Arkanoid paddle: a fire button and a knob whose position is latched by the
+// strobe and read back serially, most significant bit first and inverted.
+#[derive(Clone, Debug, Default)]
+pub struct Vaus {
+    pub position: u8, // Raw potentiometer value, roughly $62 (left) to $F2 (right) on real units
+    pub button: bool,
+    shift: u8,
+}
+
+impl Vaus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.shift);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift = state.u8()?;
+        Ok(())
+    }
+
+    pub fn latch(&mut self) {
+        self.shift = self.position;
+    }
+
+    // The next bit of the knob position; reads 1 once all eight are out
+    pub fn data(&self) -> u8 {
+        !self.shift >> 7 & 0x01
+    }
+
+    pub fn button(&self) -> u8 {
+        self.button as u8
+    }
+
+    pub fn clock(&mut self) {
+        self.shift <<= 1;
+    }
+}
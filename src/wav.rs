@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::audio::{AudioConfig, Channel, Resampler};
+use crate::input_log::InputLog;
+use crate::nes::Nes;
+
+const HEADER_SIZE: u32 = 44;
+
+// 16-bit PCM .wav writer. The sizes in the header are filled in by finish.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_bytes: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate, channels)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let block_align = channels * 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { out, data_bytes: 0 })
+    }
+
+    // Interleaved, as Resampler::read_i16 produces them
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples {
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_bytes += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    // Moves every finished sample from the resampler to the file
+    pub fn write_resampler(&mut self, resampler: &mut Resampler) -> io::Result<()> {
+        let mut samples = vec![0; resampler.available() * resampler.config().channels.count()];
+        resampler.read_i16(&mut samples);
+        self.write_samples(&samples)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(HEADER_SIZE - 8 + self.data_bytes).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_bytes.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WavExport {
+    pub frames: u64,
+    pub config: AudioConfig,
+    pub stems: bool, // Also write each Channel alone, beside the mix
+}
+
+impl Default for WavExport {
+    fn default() -> Self {
+        WavExport {
+            frames: 60,
+            config: AudioConfig::default(),
+            stems: false,
+        }
+    }
+}
+
+// out.wav's pulse 1 stem is out.pulse1.wav
+pub fn stem_path<P: AsRef<Path>>(path: P, channel: Channel) -> PathBuf {
+    path.as_ref().with_extension(format!("{}.wav", channel.name()))
+}
+
+// Runs the console for the given number of frames and writes what it played,
+// with no audio device involved. Input comes from the log when there is one,
+// otherwise nothing is pressed.
+pub fn export_wav(nes: &mut Nes, path: &Path, export: &WavExport, input: Option<&InputLog>) -> io::Result<()> {
+    let previous = nes.memory.audio.config();
+    nes.memory.audio.set_config(export.config);
+    if export.stems {
+        nes.memory.enable_stems(export.config);
+    }
+
+    // Put the audio back the way the host had it, even when a write fails
+    let result = write_export(nes, path, export, input);
+    nes.memory.disable_stems();
+    nes.memory.audio.set_config(previous);
+    result
+}
+
+fn write_export(nes: &mut Nes, path: &Path, export: &WavExport, input: Option<&InputLog>) -> io::Result<()> {
+    // The resampler's own config, so the header carries the rate it actually runs at
+    let config = nes.memory.audio.config();
+    let channels = config.channels.count() as u16;
+    let mut mix = WavWriter::create(path, config.sample_rate, channels)?;
+    let mut stems = Vec::new();
+    if export.stems {
+        for channel in Channel::ALL {
+            stems.push((channel, WavWriter::create(stem_path(path, channel), config.sample_rate, channels)?));
+        }
+    }
+
+    for frame in 0..export.frames {
+        if let Some(input) = input {
+            let frame = input.frame(frame as usize);
+            if frame.reset {
+                nes.reset();
+            }
+            for (port, buttons) in frame.buttons.into_iter().enumerate() {
+                nes.set_buttons(port, buttons);
+            }
+        }
+        nes.run_frame();
+
+        mix.write_resampler(&mut nes.memory.audio)?;
+        for (channel, writer) in stems.iter_mut() {
+            if let Some(stem) = nes.memory.stem_mut(*channel) {
+                writer.write_resampler(stem)?;
+            }
+        }
+    }
+
+    mix.finish()?;
+    for (_, writer) in stems {
+        writer.finish()?;
+    }
+    Ok(())
+}
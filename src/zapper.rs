@@ -0,0 +1,52 @@
+use crate::palette::luma;
+use crate::ppu::{DOTS_PER_SCANLINE, PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const SENSE_RADIUS: usize = 2; // Pixels around the aim point the photodiode sees
+const SENSE_LINES: u32 = 20; // Scanlines a lit pixel keeps the diode on after the beam passes
+const BRIGHTNESS_THRESHOLD: f32 = 0.5;
+
+// The light gun, read through $4017. It only looks at the screen while the beam is
+// lighting up the area it's aimed at, so games flash targets and poll during the frame.
+#[derive(Clone, Debug, Default)]
+pub struct Zapper {
+    pub aim: Option<(usize, usize)>, // Screen coordinates, None when pointed away from the screen
+    pub trigger: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Bit 3 clear when light is sensed, bit 4 set while the trigger is pulled
+    pub fn read(&self, ppu: &PPU) -> u8 {
+        let light = if self.senses_light(ppu) { 0x00 } else { 0x08 };
+        let trigger = if self.trigger { 0x10 } else { 0x00 };
+        light | trigger
+    }
+
+    // Whether a bright pixel near the aim point was drawn within the last few scanlines
+    pub fn senses_light(&self, ppu: &PPU) -> bool {
+        let Some((aim_x, aim_y)) = self.aim else {
+            return false;
+        };
+        if aim_x >= SCREEN_WIDTH || aim_y >= SCREEN_HEIGHT {
+            return false;
+        }
+
+        let dots_per_line = DOTS_PER_SCANLINE as u32;
+        let frame_dots = ppu.region.scanlines_per_frame() as u32 * dots_per_line;
+        let beam = ppu.scanline as u32 * dots_per_line + ppu.dot as u32;
+        let rows = aim_y.saturating_sub(SENSE_RADIUS)..=(aim_y + SENSE_RADIUS).min(SCREEN_HEIGHT - 1);
+        let columns = aim_x.saturating_sub(SENSE_RADIUS)..=(aim_x + SENSE_RADIUS).min(SCREEN_WIDTH - 1);
+        rows.into_iter().any(|y| {
+            columns.clone().any(|x| {
+                // Pixel x of a line is output on dot x + 1
+                let drawn = y as u32 * dots_per_line + x as u32 + 1;
+                let elapsed = (beam + frame_dots - drawn) % frame_dots;
+                elapsed < SENSE_LINES * dots_per_line
+                    && luma(ppu.frame[y * SCREEN_WIDTH + x]) >= BRIGHTNESS_THRESHOLD
+            })
+        })
+    }
+}
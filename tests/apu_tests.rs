@@ -0,0 +1,246 @@
+mod common;
+
+use common::{build_ines, nop_prg};
+use rusty_nes::apu::APU;
+use rusty_nes::cartridge::Rom;
+use rusty_nes::memory::Memory;
+use rusty_nes::nes::Nes;
+
+// One NTSC frame's worth of frame counter, in CPU cycles
+const FRAME_CYCLES: u32 = 29830;
+
+fn clock(apu: &mut APU, cycles: u32) {
+    for _ in 0..cycles {
+        apu.clock();
+    }
+}
+
+#[test]
+fn test_length_counters_and_status() {
+    let mut memory = Memory::new();
+    memory.write(0x4015, 0x0F);
+    memory.write(0x4000, 0x10); // Counter not halted, constant volume 0
+    memory.write(0x4003, 0x00); // Length index 0: 10 half frames
+    memory.write(0x400B, 0x08); // Triangle, length index 1: 254 half frames
+    assert_eq!(memory.read(0x4015) & 0x0F, 0x05);
+
+    memory.tick(FRAME_CYCLES * 4);
+    assert_eq!(memory.read(0x4015) & 0x0F, 0x05, "Eight half frames shouldn't empty a count of 10");
+    memory.tick(FRAME_CYCLES);
+    assert_eq!(memory.read(0x4015) & 0x0F, 0x04, "The pulse's length counter should have run out");
+
+    memory.write(0x4015, 0x00);
+    assert_eq!(memory.read(0x4015) & 0x0F, 0x00, "Disabling a channel clears its length counter");
+    memory.write(0x400B, 0x08);
+    assert_eq!(memory.read(0x4015) & 0x0F, 0x00, "Disabled channels ignore length loads");
+}
+
+#[test]
+fn test_write_only_registers_read_open_bus() {
+    let mut memory = Memory::new();
+    memory.write(0x4000, 0x10);
+    memory.write(0x4018, 0x22);
+    memory.write(0x400B, 0x08);
+    assert_eq!(memory.read(0x4000), 0x08, "$4000 reads back the last value on the bus, not the write");
+    assert_eq!(memory.read(0x4018), 0x08, "The disabled test registers are open bus too");
+    assert_eq!(memory.peek(0x4014), 0x08);
+}
+
+#[test]
+fn test_pulse_duty_and_sweep_mute() {
+    let mut apu = APU::new();
+    apu.write_register(0x4015, 0x01);
+    apu.write_register(0x4000, 0xBF); // 50% duty, halted, constant volume 15
+    apu.write_register(0x4002, 0x40);
+    apu.write_register(0x4003, 0x00);
+
+    // Each duty step lasts (period + 1) APU cycles, two CPU cycles each
+    let mut high = 0;
+    for _ in 0..(0x41 * 2 * 8) {
+        apu.clock();
+        if apu.pulse1.output() == 15 {
+            high += 1;
+        }
+    }
+    assert_eq!(high, 0x41 * 2 * 4, "A 50% duty cycle should be high half of the time");
+
+    apu.write_register(0x4002, 0x07);
+    clock(&mut apu, 64);
+    assert!((0..64).all(|_| {
+        apu.clock();
+        apu.pulse1.output() == 0
+    }), "Periods below 8 are muted by the sweep unit");
+}
+
+#[test]
+fn test_triangle_needs_linear_counter() {
+    let mut apu = APU::new();
+    apu.write_register(0x4015, 0x04);
+    apu.write_register(0x4008, 0x00); // Linear counter reload value 0
+    apu.write_register(0x400A, 0x10);
+    apu.write_register(0x400B, 0x08);
+    clock(&mut apu, FRAME_CYCLES);
+    let level = apu.triangle.output();
+    clock(&mut apu, 1000);
+    assert_eq!(apu.triangle.output(), level, "A zero linear counter should freeze the triangle");
+
+    apu.write_register(0x4008, 0xFF); // Control set, reload 127
+    apu.write_register(0x400B, 0x08);
+    clock(&mut apu, 7460); // Past the first quarter frame, which reloads the linear counter
+    let mut levels = std::collections::HashSet::new();
+    for _ in 0..(0x11 * 32) {
+        apu.clock();
+        levels.insert(apu.triangle.output());
+    }
+    assert_eq!(levels.len(), 16, "The triangle should step through all 16 levels");
+}
+
+#[test]
+fn test_noise_short_mode_repeats_every_93_steps() {
+    let mut apu = APU::new();
+    apu.write_register(0x4015, 0x08);
+    apu.write_register(0x400C, 0x3F); // Halted, constant volume 15
+    apu.write_register(0x400E, 0x80); // Short mode, period 4
+    apu.write_register(0x400F, 0x00);
+    clock(&mut apu, 4 * 200);
+
+    let outputs: Vec<u8> = (0..4 * 93 * 2).map(|_| {
+        apu.clock();
+        apu.noise.output()
+    }).collect();
+    assert!(outputs.contains(&15) && outputs.contains(&0));
+    assert_eq!(outputs[..4 * 93], outputs[4 * 93..], "Short mode noise loops every 93 steps");
+}
+
+#[test]
+fn test_dmc_fetches_samples_through_cpu_bus() {
+    let mut prg = nop_prg(0x8000);
+    prg[0x7000..0x7011].fill(0xFF); // 17 bytes of rising deltas at $F000
+    let rom = Rom::parse(&build_ines(0, 0, &prg, &[0; 0x2000])).unwrap();
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+
+    nes.memory.write(0x4010, 0x8F); // IRQ enabled, fastest rate
+    nes.memory.write(0x4011, 0x00);
+    nes.memory.write(0x4012, 0xC0); // $F000
+    nes.memory.write(0x4013, 0x01); // 17 bytes
+    nes.memory.write(0x4015, 0x10);
+    assert_eq!(nes.memory.read(0x4015) & 0x10, 0x10, "The DMC should report bytes remaining");
+
+    let cycles = nes.cpu.cycles;
+    for _ in 0..5000 {
+        nes.step();
+    }
+    let status = nes.memory.read(0x4015);
+    assert_eq!(status & 0x10, 0, "The sample should have finished");
+    assert_eq!(status & 0x80, 0x80, "The DMC IRQ flag should be set at the end");
+    assert_eq!(nes.memory.apu.dmc.output(), 126, "Every bit of the sample raises the level, up to 126");
+    assert!(nes.memory.irq(), "The DMC should hold the IRQ line");
+    assert!(nes.cpu.cycles - cycles > 1000, "The CPU was stalled by the fetches");
+
+    nes.memory.write(0x4015, 0x00);
+    assert!(!nes.memory.irq(), "Writing $4015 acknowledges the DMC IRQ");
+}
+
+#[test]
+fn test_frame_irq_timing_and_acknowledge() {
+    let mut apu = APU::new();
+    apu.write_register(0x4017, 0x00); // Written on an APU cycle: the sequence restarts 3 cycles later
+    clock(&mut apu, 3 + 29827);
+    assert!(!apu.irq(), "The frame IRQ shouldn't be raised before cycle 29828");
+    clock(&mut apu, 1);
+    assert!(apu.irq(), "The frame IRQ should be raised on cycle 29828");
+
+    assert_eq!(apu.read_status() & 0x40, 0x40, "Bit 6 of $4015 reports the frame IRQ");
+    assert!(!apu.irq(), "Reading $4015 acknowledges the frame IRQ");
+    clock(&mut apu, 1);
+    assert!(apu.irq(), "The flag is set again on the cycle of the last step");
+    clock(&mut apu, 1);
+    apu.read_status();
+    clock(&mut apu, FRAME_CYCLES - 3);
+    assert!(!apu.irq(), "A read after the last set cycle clears it until the next frame");
+    clock(&mut apu, 1);
+    assert!(apu.irq(), "The next frame raises it again");
+
+    apu.write_register(0x4017, 0x40);
+    assert!(!apu.irq(), "Setting the inhibit flag clears the IRQ at once");
+    clock(&mut apu, FRAME_CYCLES * 2);
+    assert!(!apu.irq(), "Inhibited frame counters never raise the IRQ");
+}
+
+#[test]
+fn test_frame_counter_write_jitter_and_five_step_mode() {
+    // The same write lands a cycle later when it falls between APU cycles
+    for (offset, delay) in [(0, 3), (1, 4)] {
+        let mut apu = APU::new();
+        clock(&mut apu, offset);
+        apu.write_register(0x4017, 0x00);
+        clock(&mut apu, delay + 29827);
+        assert!(!apu.irq(), "Delay {} restarted the sequence too early", delay);
+        clock(&mut apu, 1);
+        assert!(apu.irq(), "Delay {} restarted the sequence too late", delay);
+    }
+
+    let mut apu = APU::new();
+    apu.write_register(0x4015, 0x01);
+    apu.write_register(0x4000, 0x10);
+    apu.write_register(0x4003, 0x18); // Length index 3: 2 half frames
+    apu.write_register(0x4017, 0x80);
+    clock(&mut apu, 2);
+    assert_eq!(apu.peek_status() & 0x01, 0x01, "The write doesn't take effect for 3 cycles");
+    clock(&mut apu, 1);
+    clock(&mut apu, 14913);
+    assert_eq!(apu.peek_status() & 0x01, 0x00, "5-step mode clocks a half frame as the sequence restarts");
+
+    clock(&mut apu, 37282 * 2);
+    assert!(!apu.irq(), "5-step mode never raises the frame IRQ");
+}
+
+#[test]
+fn test_frame_irq_reaches_cpu() {
+    let mut prg = nop_prg(0x8000);
+    let program = [
+        0x58, // CLI
+        0x4C, 0x01, 0xC0, // JMP $C001
+    ];
+    prg[0x4000..0x4000 + program.len()].copy_from_slice(&program);
+    // IRQ handler at $C020: INC $10; acknowledge by reading $4015; RTI
+    let handler = [0xE6, 0x10, 0xAD, 0x15, 0x40, 0x40];
+    prg[0x4020..0x4020 + handler.len()].copy_from_slice(&handler);
+    prg[0x7FFE] = 0x20;
+    let rom = Rom::parse(&build_ines(0, 0, &prg, &[0; 0x2000])).unwrap();
+
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+    let start = nes.cpu.cycles;
+    while nes.cpu.cycles - start < FRAME_CYCLES as u64 * 21 / 2 {
+        nes.step();
+    }
+    assert_eq!(nes.memory.read(0x0010), 10, "One frame IRQ every 29830 cycles");
+}
+
+#[test]
+fn test_mixer_levels() {
+    let mut apu = APU::new();
+    // The triangle powers up at the top of its ramp, the only thing on the output
+    let rest = apu.output();
+    assert!((rest - 0.246).abs() < 0.001, "Got {}", rest);
+
+    apu.write_register(0x4011, 0x7F);
+    let dmc = apu.output() - rest;
+    assert!(dmc > 0.4 && dmc < 0.45, "A full DMC level should add about 0.43, got {}", dmc);
+
+    apu.write_register(0x4015, 0x03);
+    for address in [0x4000, 0x4004] {
+        apu.write_register(address, 0xFF); // 75% duty, constant volume 15
+        apu.write_register(address + 2, 0xFF);
+        apu.write_register(address + 3, 0x00);
+    }
+    let loudest = (0..0x100 * 16).fold(0.0f32, |loudest, _| {
+        apu.clock();
+        loudest.max(apu.output())
+    });
+    let pulses = loudest - rest - dmc;
+    assert!((pulses - 0.2585).abs() < 0.01, "Two full pulses should add about 0.26, got {}", pulses);
+    assert!(loudest < 1.0);
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{build_ines, nop_prg};
+use rusty_nes::audio::{dynamic_rate, AudioConfig, Channels, Resampler};
+use rusty_nes::cartridge::Rom;
+use rusty_nes::nes::Nes;
+
+const CLOCK_RATE: f64 = 1_789_773.0;
+
+fn mono(sample_rate: u32, filters: bool) -> Resampler {
+    Resampler::new(AudioConfig { sample_rate, channels: Channels::Mono, filters }, CLOCK_RATE)
+}
+
+// Feeds a square wave of the given period, in clocks, for one second
+fn square(resampler: &mut Resampler, period: u32, amplitude: f32) {
+    for cycle in 0..CLOCK_RATE as u32 {
+        let level = if cycle % period < period / 2 { amplitude } else { -amplitude };
+        resampler.clock(level);
+        if cycle % 29780 == 0 {
+            resampler.end_frame();
+        }
+    }
+    resampler.end_frame();
+}
+
+fn read_all(resampler: &mut Resampler) -> Vec<f32> {
+    let mut samples = vec![0.0; resampler.available()];
+    resampler.read_f32(&mut samples);
+    samples
+}
+
+#[test]
+fn test_resampler_rate_and_band_limiting() {
+    let mut resampler = mono(48000, false);
+    square(&mut resampler, 1790, 0.5); // About 1 kHz
+    let samples = read_all(&mut resampler);
+    let count = samples.len();
+    assert!((47980..=48000).contains(&count), "A second of clocks makes a second of samples, got {}", count);
+
+    let peak = samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    assert!(peak > 0.5 && peak < 0.65, "Steps ring a little past the level but no further, peak {}", peak);
+    let settled = samples[200..].iter().filter(|sample| (sample.abs() - 0.5).abs() < 0.01).count();
+    assert!(settled > samples.len() / 2, "Most samples should sit on the square's levels");
+
+    // Far above Nyquist the square averages out instead of aliasing down
+    let mut resampler = mono(48000, false);
+    square(&mut resampler, 8, 0.5);
+    let samples = read_all(&mut resampler);
+    let loudest = samples[100..].iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    assert!(loudest < 0.05, "A 224 kHz square should be inaudible, got {}", loudest);
+}
+
+#[test]
+fn test_zero_rate_is_clamped() {
+    let mut resampler = mono(0, true);
+    assert_eq!(resampler.config().sample_rate, 1, "The resampler reports the rate it runs at");
+    square(&mut resampler, 1790, 0.5);
+    square(&mut resampler, 1790, 0.5);
+    assert!(resampler.available() >= 1, "Two seconds at 1 Hz should finish a sample");
+}
+
+#[test]
+fn test_output_filters_remove_dc() {
+    let mut resampler = mono(44100, true);
+    for cycle in 0..CLOCK_RATE as u32 / 2 {
+        resampler.clock(0.8);
+        if cycle % 29780 == 0 {
+            resampler.end_frame();
+        }
+    }
+    resampler.end_frame();
+    let samples = read_all(&mut resampler);
+    assert!(samples[..100].iter().any(|&sample| sample > 0.3), "The step should get through at first");
+    assert!(samples.last().unwrap().abs() < 0.001, "The high-pass filters settle back to zero");
+}
+
+#[test]
+fn test_formats_and_rate_control() {
+    let config = AudioConfig { sample_rate: 44100, channels: Channels::Stereo, filters: false };
+    let mut resampler = Resampler::new(config, CLOCK_RATE);
+    square(&mut resampler, 4000, 0.25);
+    let frames = resampler.available();
+    let mut out = vec![0i16; 64];
+    assert_eq!(resampler.read_i16(&mut out), 32, "64 slots hold 32 stereo frames");
+    assert!(out.chunks(2).all(|frame| frame[0] == frame[1]), "Both sides carry the same signal");
+    // Past the first step's ringing, half way into the square's first half
+    assert!((out[60] as i32 - 8191).abs() <= 2, "0.25 should come out as 8191, got {}", out[60]);
+    assert_eq!(resampler.available(), frames - 32);
+
+    resampler.clear();
+    resampler.set_rate_control(0.99);
+    square(&mut resampler, 4000, 0.25);
+    let slower = resampler.available();
+    assert!((43640..=43670).contains(&slower), "Rate control should make 1% fewer samples, got {}", slower);
+
+    assert_eq!(dynamic_rate(0.5, 0.005), 1.0);
+    assert_eq!(dynamic_rate(0.0, 0.005), 1.005, "An empty queue asks for more samples");
+    assert_eq!(dynamic_rate(1.0, 0.005), 0.995, "A full queue asks for fewer");
+}
+
+#[test]
+fn test_nes_produces_samples_every_frame() {
+    let rom = Rom::parse(&build_ines(0, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap();
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+    nes.memory.write(0x4015, 0x01);
+    nes.memory.write(0x4000, 0xBF); // Constant volume 15, 50% duty
+    nes.memory.write(0x4002, 0xFD);
+    nes.memory.write(0x4003, 0x00); // About 440 Hz
+
+    nes.run_frame();
+    nes.memory.audio.clear();
+    for _ in 0..60 {
+        nes.run_frame();
+    }
+    let frames = nes.memory.audio.available();
+    assert!((44000..=44200).contains(&frames), "60 frames are about a second of audio, got {}", frames);
+    let mut samples = vec![0.0; frames * 2];
+    nes.memory.audio.read_f32(&mut samples);
+    let peak = samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    assert!(peak > 0.05, "The pulse should be audible, peak {}", peak);
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+
+use common::{build_ines, nop_prg, scratch_dir};
+use rusty_nes::battery::{SaveFile, SaveStorage};
+use rusty_nes::cartridge::Rom;
+use rusty_nes::nes::Nes;
+
+// An NROM image with the battery bit set
+fn battery_rom() -> Vec<u8> {
+    build_ines(0, 0x02, &nop_prg(0x8000), &[0; 0x2000])
+}
+
+// Keeps the save where the test can see it
+#[derive(Clone, Default)]
+struct SharedStorage {
+    data: Rc<RefCell<Option<Vec<u8>>>>,
+    stores: Rc<RefCell<u32>>,
+}
+
+impl SaveStorage for SharedStorage {
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.data.borrow().clone())
+    }
+
+    fn store(&mut self, data: &[u8]) -> io::Result<()> {
+        *self.data.borrow_mut() = Some(data.to_vec());
+        *self.stores.borrow_mut() += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_sav_file_round_trip() {
+    let dir = scratch_dir("sav_round_trip");
+    let rom_path = dir.join("game.nes");
+    fs::write(&rom_path, battery_rom()).unwrap();
+
+    let mut nes = Nes::new();
+    nes.load_rom(&rom_path).unwrap();
+    assert_eq!(nes.save_data().map(|data| data.len()), Some(0x2000));
+    nes.memory.write(0x6000, 0x12);
+    nes.memory.write(0x7FFF, 0x34);
+    nes.flush_save().unwrap();
+
+    let save = fs::read(dir.join("game.sav")).unwrap();
+    assert_eq!(save.len(), 0x2000);
+    assert_eq!((save[0], save[0x1FFF]), (0x12, 0x34));
+    assert!(!dir.join("game.sav.tmp").exists(), "The temporary file should be renamed over the save");
+
+    let mut nes = Nes::new();
+    nes.load_rom(&rom_path).unwrap();
+    assert_eq!(nes.memory.read(0x6000), 0x12, "The save should be loaded with the ROM");
+    assert_eq!(nes.memory.read(0x7FFF), 0x34);
+
+    // Dropping the console flushes the last changes
+    nes.memory.write(0x6001, 0x56);
+    drop(nes);
+    assert_eq!(fs::read(dir.join("game.sav")).unwrap()[1], 0x56);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_autosave_only_writes_changes() {
+    let storage = SharedStorage::default();
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&battery_rom()).unwrap()).unwrap();
+    nes.set_save_storage(Box::new(storage.clone())).unwrap();
+    nes.battery.as_mut().unwrap().set_autosave_interval(10);
+
+    for _ in 0..20 {
+        nes.run_frame();
+    }
+    assert_eq!(*storage.stores.borrow(), 0, "Unchanged RAM should not be saved");
+
+    nes.memory.write(0x6100, 0x99);
+    for _ in 0..9 {
+        nes.run_frame();
+    }
+    assert_eq!(*storage.stores.borrow(), 0);
+    nes.run_frame();
+    assert_eq!(*storage.stores.borrow(), 1, "The autosave should run every 10 frames");
+    assert_eq!(storage.data.borrow().as_ref().unwrap()[0x100], 0x99);
+}
+
+#[test]
+fn test_nes2_nvram_follows_volatile_ram() {
+    // MMC1 with 8 KiB of work RAM and 8 KiB of battery-backed RAM
+    let mut data = build_ines(1, 0x02, &nop_prg(0x8000), &[0; 0x2000]);
+    data[7] |= 0x08;
+    data[10] = 0x77;
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&data).unwrap()).unwrap();
+
+    let storage = SharedStorage::default();
+    *storage.data.borrow_mut() = Some(vec![0xAB; 0x2000]);
+    nes.set_save_storage(Box::new(storage)).unwrap();
+
+    let mapper = nes.memory.cartridge.as_ref().unwrap();
+    assert_eq!(mapper.prg_ram()[0x0000], 0x00, "Work RAM should not come from the save");
+    assert_eq!(mapper.prg_ram()[0x2000], 0xAB, "Battery RAM should come from the save");
+    assert_eq!(nes.save_data().unwrap().len(), 0x2000);
+}
+
+#[test]
+fn test_no_save_without_battery() {
+    let dir = scratch_dir("no_battery");
+    let rom_path = dir.join("game.nes");
+    fs::write(&rom_path, build_ines(0, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap();
+
+    let mut nes = Nes::new();
+    nes.load_rom(&rom_path).unwrap();
+    nes.memory.write(0x6000, 0x12);
+    nes.flush_save().unwrap();
+    assert!(nes.save_data().is_none());
+    assert!(!SaveFile::beside_rom(&rom_path).path().exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// Clocks the 24C02 on a Bandai LZ93D50 board through $800D: bit 5 is SCL, bit 6 SDA
+fn eeprom_lines(nes: &mut Nes, lines: &[(bool, bool)]) {
+    for &(scl, sda) in lines {
+        nes.memory.write(0x800D, 0x80 | (scl as u8) << 5 | (sda as u8) << 6);
+    }
+}
+
+// A start condition, then each byte MSB first followed by an acknowledge clock
+fn eeprom_send(nes: &mut Nes, bytes: &[u8]) {
+    eeprom_lines(nes, &[(false, true), (true, true), (true, false), (false, false)]);
+    for &byte in bytes {
+        for bit in (0..8).rev() {
+            let sda = byte >> bit & 1 != 0;
+            eeprom_lines(nes, &[(false, sda), (true, sda), (false, sda)]);
+        }
+        eeprom_lines(nes, &[(false, true), (true, true)]);
+        assert_eq!(nes.memory.read(0x6000) & 0x10, 0, "The EEPROM should acknowledge {:02X}", byte);
+        eeprom_lines(nes, &[(false, true)]);
+    }
+}
+
+#[test]
+fn test_bandai_eeprom_is_saved() {
+    // Mapper 16 with the battery bit: an LZ93D50 with a 24C02
+    let rom = build_ines(16, 0x02, &nop_prg(0x8000), &[0; 0x2000]);
+    let storage = SharedStorage::default();
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&rom).unwrap()).unwrap();
+    nes.set_save_storage(Box::new(storage.clone())).unwrap();
+    assert_eq!(nes.save_data().map(|data| data.len()), Some(256), "The save is the EEPROM, not PRG-RAM");
+
+    // Device select for a write, word address $40, two bytes, stop
+    eeprom_send(&mut nes, &[0xA0, 0x40, 0x12, 0x34]);
+    eeprom_lines(&mut nes, &[(false, false), (true, false), (true, true)]);
+    nes.flush_save().unwrap();
+    let save = storage.data.borrow().clone().unwrap();
+    assert_eq!(&save[0x40..0x42], [0x12, 0x34]);
+
+    // A fresh console reads the byte back with a random read
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&rom).unwrap()).unwrap();
+    nes.set_save_storage(Box::new(storage)).unwrap();
+    eeprom_send(&mut nes, &[0xA0, 0x41]);
+    eeprom_send(&mut nes, &[0xA1]);
+    let mut byte = 0;
+    for _ in 0..8 {
+        eeprom_lines(&mut nes, &[(true, true)]);
+        byte = byte << 1 | (nes.memory.read(0x6000) >> 4 & 1);
+        eeprom_lines(&mut nes, &[(false, true)]);
+    }
+    assert_eq!(byte, 0x34, "The EEPROM should come back from the save");
+}
@@ -0,0 +1,38 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+// Builds an iNES image in memory so tests don't need ROM files on disk
+#[allow(dead_code)]
+pub fn build_ines(mapper: u8, flags6: u8, prg_rom: &[u8], chr_rom: &[u8]) -> Vec<u8> {
+    let mut data = vec![0u8; 16];
+    data[0..4].copy_from_slice(b"NES\x1A");
+    data[4] = (prg_rom.len() / 0x4000) as u8;
+    data[5] = (chr_rom.len() / 0x2000) as u8;
+    data[6] = (mapper << 4) | (flags6 & 0x0F);
+    data[7] = mapper & 0xF0;
+    data.extend_from_slice(prg_rom);
+    data.extend_from_slice(chr_rom);
+    data
+}
+
+// PRG-ROM filled with NOPs whose vectors all point at the start of the last 16 KiB bank
+#[allow(dead_code)]
+pub fn nop_prg(size: usize) -> Vec<u8> {
+    let mut prg = vec![0xEA; size];
+    let last = size - 6;
+    for vector in 0..3 {
+        prg[last + vector * 2] = 0x00;
+        prg[last + vector * 2 + 1] = 0xC0;
+    }
+    prg
+}
+
+// A fresh directory for one test's files, unique to this test process
+#[allow(dead_code)]
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rusty_nes_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
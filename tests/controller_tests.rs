@@ -0,0 +1,86 @@
+mod common;
+
+use common::{build_ines, nop_prg};
+use rusty_nes::cartridge::Rom;
+use rusty_nes::controller::Controller;
+use rusty_nes::memory::Memory;
+use rusty_nes::nes::Nes;
+
+// Strobes both joypads and reads eight bits from the given port
+fn read_buttons(memory: &mut Memory, address: u16) -> Vec<u8> {
+    memory.write(0x4016, 1);
+    memory.write(0x4016, 0);
+    (0..8).map(|_| memory.read(address) & 0x01).collect()
+}
+
+#[test]
+fn test_strobe_and_shift_out() {
+    let mut memory = Memory::new();
+    memory.input.controllers[0].buttons = Controller::A | Controller::START | Controller::RIGHT;
+    memory.input.controllers[1].buttons = Controller::B;
+    assert_eq!(read_buttons(&mut memory, 0x4016), [1, 0, 0, 1, 0, 0, 0, 1]);
+    assert_eq!(read_buttons(&mut memory, 0x4017), [0, 1, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(memory.read(0x4017) & 0x01, 1, "Past the eighth button the register reads 1");
+
+    // The upper bits aren't driven and keep what was last on the bus
+    memory.write(0x0000, 0xFF);
+    memory.read(0x0000);
+    assert_eq!(memory.read(0x4016), 0xE1);
+    memory.write(0x0000, 0x00);
+    memory.read(0x0000);
+    assert_eq!(memory.read(0x4016), 0x00);
+
+    // While the strobe is high the latch follows the buttons and A is read over and over
+    memory.write(0x4016, 1);
+    assert_eq!(memory.read(0x4016) & 0x01, 1);
+    assert_eq!(memory.read(0x4016) & 0x01, 1);
+    memory.input.controllers[0].buttons = Controller::B;
+    assert_eq!(memory.read(0x4016) & 0x01, 0);
+    memory.write(0x4016, 0);
+    assert_eq!(memory.read(0x4016) & 0x01, 0);
+    assert_eq!(memory.read(0x4016) & 0x01, 1, "The buttons held when the strobe fell are shifted out");
+}
+
+#[test]
+fn test_dmc_fetch_during_read_skips_a_button() {
+    let mut memory = Memory::new();
+    memory.input.controllers[0].buttons = Controller::A | Controller::SELECT;
+    memory.write(0x4016, 1);
+    memory.write(0x4016, 0);
+    assert_eq!(memory.read(0x4016) & 0x01, 1);
+    memory.tick(1);
+    assert_eq!(memory.read(0x4016) & 0x01, 0, "Without DMC activity B comes next");
+
+    memory.write(0x4016, 1);
+    memory.write(0x4016, 0);
+    // Start a sample so the DMC wants its first byte straight away
+    memory.write(0x4012, 0x00);
+    memory.write(0x4013, 0x01);
+    memory.write(0x4015, 0x10);
+    assert_eq!(memory.read(0x4016) & 0x01, 1);
+    memory.tick(1);
+    assert_eq!(memory.read(0x4016) & 0x01, 1, "The fetch's extra read loses B, so Select comes next");
+    assert_eq!(memory.read(0x4016) & 0x01, 0);
+}
+
+#[test]
+fn test_buttons_survive_save_states() {
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&build_ines(0, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap()).unwrap();
+    nes.set_buttons(0, Controller::B | Controller::UP);
+    nes.run_frame();
+    nes.memory.write(0x4016, 1);
+    nes.memory.write(0x4016, 0);
+    nes.memory.read(0x4016);
+
+    let state = nes.save_state();
+    let rest: Vec<u8> = (0..7).map(|_| nes.memory.read(0x4016) & 0x01).collect();
+    assert_eq!(rest, [1, 0, 0, 1, 0, 0, 0]);
+
+    nes.set_buttons(0, 0);
+    nes.set_buttons(4, 0xFF);
+    nes.load_state(&state).unwrap();
+    assert_eq!(nes.memory.input.controllers[0].buttons, Controller::B | Controller::UP);
+    let again: Vec<u8> = (0..7).map(|_| nes.memory.read(0x4016) & 0x01).collect();
+    assert_eq!(again, rest, "The shift register picks up where the state left it");
+}
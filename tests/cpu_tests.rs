@@ -87,4 +87,126 @@ fn test_inx_overflow() {
     assert_eq!(cpu.x, 0x00, "X register should wrap around to 0x00");
     assert_eq!(cpu.p & 0b0000_0010, 0b0000_0010, "Zero flag should be set");
     assert_eq!(cpu.p & 0b1000_0000, 0, "Negative flag should be cleared");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_oam_dma_even_cycle_stall() {
+    let mut cpu = CPU::new();
+    let mut memory = Memory::new();
+
+    // Setup: Fill page $02 and write STA $4014 with A = $02
+    for i in 0..256u16 {
+        memory.write(0x0200 + i, i as u8);
+    }
+    cpu.a = 0x02;
+    memory.write(0x8000, 0x8D); // STA Absolute opcode
+    memory.write(0x8001, 0x14);
+    memory.write(0x8002, 0x40);
+
+    cpu.pc = 0x8000;
+    cpu.step(&mut memory);
+
+    // STA absolute takes 4 cycles, so the DMA starts on an even cycle
+    assert_eq!(cpu.pc, 0x8003, "PC should move past the STA operand");
+    assert_eq!(cpu.stall, 513, "DMA starting on an even cycle should stall for 513 cycles");
+    assert_eq!(memory.ppu.oam[0x00], 0x00);
+    assert_eq!(memory.ppu.oam[0x7F], 0x7F);
+    assert_eq!(memory.ppu.oam[0xFF], 0xFF, "All 256 bytes should be copied into OAM");
+
+    // The stall is consumed by the next step before any instruction runs
+    assert_eq!(cpu.step(&mut memory), 513);
+    assert_eq!(cpu.stall, 0);
+    assert_eq!(cpu.pc, 0x8003, "No instruction should run while stalled");
+}
+
+#[test]
+fn test_oam_dma_odd_cycle_stall() {
+    let mut cpu = CPU::new();
+    let mut memory = Memory::new();
+
+    // Setup: STA $10 (3 cycles) followed by STA $4014 (4 cycles) ends on an odd cycle
+    cpu.a = 0x03;
+    memory.write(0x8000, 0x85); // STA Zero Page opcode
+    memory.write(0x8001, 0x10);
+    memory.write(0x8002, 0x8D); // STA Absolute opcode
+    memory.write(0x8003, 0x14);
+    memory.write(0x8004, 0x40);
+
+    cpu.pc = 0x8000;
+    cpu.step(&mut memory);
+    cpu.step(&mut memory);
+
+    assert_eq!(cpu.cycles, 7);
+    assert_eq!(cpu.stall, 514, "DMA starting on an odd cycle needs an extra alignment cycle");
+}
+
+#[test]
+fn test_dma_stall_requested_through_bus() {
+    let mut cpu = CPU::new();
+    let mut memory = Memory::new();
+
+    memory.write(0x8000, 0xEA); // NOP opcode
+    cpu.pc = 0x8000;
+
+    memory.stall_cpu(4);
+    assert_eq!(cpu.step(&mut memory), 2, "NOP should take 2 cycles");
+    assert_eq!(cpu.stall, 4, "Bus stall requests should be picked up after the instruction");
+}
+
+#[test]
+fn test_jsr_rts_stack_layout() {
+    let mut cpu = CPU::new();
+    let mut memory = Memory::new();
+
+    // Setup: JSR $9000 at $8000, RTS at $9000
+    memory.write(0x8000, 0x20); // JSR opcode
+    memory.write(0x8001, 0x00);
+    memory.write(0x8002, 0x90);
+    memory.write(0x9000, 0x60); // RTS opcode
+
+    cpu.pc = 0x8000;
+    cpu.execute_instruction(&mut memory);
+    assert_eq!(cpu.pc, 0x9000);
+    assert_eq!(memory.read(0x01FD), 0x80, "JSR pushes the high byte first");
+    assert_eq!(memory.read(0x01FC), 0x02, "JSR pushes the address of its last byte");
+
+    cpu.execute_instruction(&mut memory);
+    assert_eq!(cpu.pc, 0x8003, "RTS should continue after the JSR");
+    assert_eq!(cpu.sp, 0xFD);
+}
+
+#[test]
+fn test_sbc_sets_overflow() {
+    let mut cpu = CPU::new();
+    let mut memory = Memory::new();
+
+    // Setup: 0x80 - 0x01 with carry set overflows from negative to positive
+    cpu.a = 0x80;
+    cpu.p |= 0x01;
+    memory.write(0x8000, 0xE9); // SBC Immediate opcode
+    memory.write(0x8001, 0x01);
+
+    cpu.pc = 0x8000;
+    cpu.execute_instruction(&mut memory);
+
+    assert_eq!(cpu.a, 0x7F);
+    assert_eq!(cpu.p & 0x40, 0x40, "Overflow flag should be set");
+    assert_eq!(cpu.p & 0x01, 0x01, "Carry should stay set when no borrow occurs");
+}
+
+#[test]
+fn test_ror_accumulator_through_carry() {
+    let mut cpu = CPU::new();
+    let mut memory = Memory::new();
+
+    cpu.a = 0x01;
+    cpu.p |= 0x01;
+    memory.write(0x8000, 0x6A); // ROR Accumulator opcode
+
+    cpu.pc = 0x8000;
+    cpu.execute_instruction(&mut memory);
+
+    assert_eq!(cpu.a, 0x80, "Carry should rotate into bit 7");
+    assert_eq!(cpu.p & 0x01, 0x01, "Bit 0 should rotate into carry");
+    assert_eq!(cpu.pc, 0x8001);
+}
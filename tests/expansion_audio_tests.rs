@@ -0,0 +1,161 @@
+use rusty_nes::apu::fds::FdsAudio;
+use rusty_nes::apu::n163::N163Audio;
+use rusty_nes::apu::sunsoft5b::Sunsoft5bAudio;
+use rusty_nes::apu::vrc7::Vrc7Audio;
+
+const CPU_HZ: u32 = 1_789_773;
+
+// Clocks a chip and records its output after every cycle
+fn record<F: FnMut() -> f32>(cycles: u32, mut clock: F) -> Vec<f32> {
+    (0..cycles).map(|_| clock()).collect()
+}
+
+// How many times the output rises through `level`
+fn rising_edges(samples: &[f32], level: f32) -> usize {
+    samples.windows(2).filter(|pair| pair[0] <= level && pair[1] > level).count()
+}
+
+fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+}
+
+#[test]
+fn test_vrc7_fm_note_and_release() {
+    let mut vrc7 = Vrc7Audio::new();
+    let write = |vrc7: &mut Vrc7Audio, register: u8, value: u8| {
+        vrc7.write_address(register);
+        vrc7.write_data(value);
+    };
+    // Custom instrument: modulator turned all the way down, carrier with instant attack and release
+    let patch = [0x21, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x00, 0x0F];
+    for (register, value) in patch.into_iter().enumerate() {
+        write(&mut vrc7, register as u8, value);
+    }
+    write(&mut vrc7, 0x30, 0x00); // Instrument 0, loudest
+    write(&mut vrc7, 0x10, 0x20);
+    write(&mut vrc7, 0x20, 0x19); // Key on, octave 4, F-number $120: about 437 Hz
+
+    let samples = record(CPU_HZ, || {
+        vrc7.clock();
+        vrc7.output()
+    });
+    let edges = rising_edges(&samples, 0.0);
+    assert!((434..=440).contains(&edges), "The carrier should play near 437 Hz, got {}", edges);
+    assert!(peak(&samples) > 0.07 && peak(&samples) <= 0.075, "A carrier at full volume peaks at the chip's level");
+
+    write(&mut vrc7, 0x20, 0x09); // Key off
+    let samples = record(CPU_HZ / 10, || {
+        vrc7.clock();
+        vrc7.output()
+    });
+    assert!(peak(&samples[samples.len() / 2..]) < 0.0001, "Release rate 15 silences the note quickly");
+}
+
+#[test]
+fn test_fds_wavetable_and_modulation() {
+    let mut fds = FdsAudio::new();
+    fds.write(0x4089, 0x80);
+    for index in 0..64 {
+        fds.write(0x4040 + index, if index < 32 { 0x3F } else { 0x00 });
+    }
+    assert_eq!(fds.read(0x4041), Some(0x3F));
+    fds.write(0x4089, 0x00);
+    fds.write(0x4080, 0x80 | 32); // Fixed gain of 32
+    fds.write(0x4082, 0x00);
+    fds.write(0x4083, 0x04); // Pitch $400: CPU clock * $400 / 2^22, about 437 Hz
+
+    let samples = record(CPU_HZ, || {
+        fds.clock();
+        fds.output()
+    });
+    let edges = rising_edges(&samples, 0.0);
+    assert!((436..=438).contains(&edges), "The wave should play near 437 Hz, got {}", edges);
+    assert!((peak(&samples) - 2.4 * 0.149).abs() < 0.001, "A full wave is 2.4 times an APU pulse");
+
+    // Modulation counter +32 at sweep gain 32 bends the pitch up an octave
+    fds.write(0x4084, 0x80 | 32);
+    fds.write(0x4085, 0x20);
+    fds.write(0x4086, 0x00);
+    fds.write(0x4087, 0x00);
+    let samples = record(CPU_HZ, || {
+        fds.clock();
+        fds.output()
+    });
+    let edges = rising_edges(&samples, 0.0);
+    assert!((872..=876).contains(&edges), "Modulation should double the pitch, got {}", edges);
+}
+
+#[test]
+fn test_n163_channels_share_the_dac() {
+    let mut n163 = N163Audio::new();
+    let write = |n163: &mut N163Audio, address: u8, value: u8| {
+        n163.write_address(address);
+        n163.write_data(value);
+    };
+    // A 16-sample square at $00: eight samples of 15, eight of 0
+    for address in 0..4 {
+        write(&mut n163, address, 0xFF);
+    }
+    // Channel 7 steps one sample per update and plays at full volume
+    write(&mut n163, 0x7C, 0xF0 | 0x01);
+    write(&mut n163, 0x7F, 0x0F);
+
+    // One channel updates every 15 cycles, so a period takes 15 * 16
+    let samples = record(15 * 16 * 100, || {
+        n163.clock();
+        n163.output()
+    });
+    // The first edge comes from the silence before the first update
+    assert_eq!(rising_edges(&samples, 0.0), 101);
+    let alone = peak(&samples);
+    assert!((alone - 8.0 * 15.0 * 0.149 / 225.0).abs() < 0.0001);
+
+    // With a second, silent channel enabled, channel 7 gets half the updates and half the level
+    write(&mut n163, 0x7F, 0x1F);
+    let samples = record(15 * 16 * 100, || {
+        n163.clock();
+        n163.output()
+    });
+    let edges = rising_edges(&samples, 0.0);
+    assert!((49..=51).contains(&edges), "Two channels should halve the pitch, got {}", edges);
+    assert!((peak(&samples) - alone / 2.0).abs() < 0.0001, "Two channels should halve the level");
+}
+
+#[test]
+fn test_sunsoft5b_tone_volume_and_envelope() {
+    let mut chip = Sunsoft5bAudio::new();
+    let write = |chip: &mut Sunsoft5bAudio, register: u8, value: u8| {
+        chip.write_address(register);
+        chip.write_data(value);
+    };
+    write(&mut chip, 0x00, 100); // Tone A period 100: a full cycle every 32 * 100 CPU cycles
+    write(&mut chip, 0x07, 0x3E); // Tone A only, no noise
+    write(&mut chip, 0x08, 0x0F);
+
+    let samples = record(32 * 100 * 50, || {
+        chip.clock();
+        chip.output()
+    });
+    assert_eq!(rising_edges(&samples, 0.0), 50);
+    assert!((peak(&samples) - 0.149).abs() < 0.0001, "Volume 15 is as loud as a full APU pulse");
+
+    // Each volume step is 3 dB
+    write(&mut chip, 0x08, 0x0D);
+    let samples = record(32 * 100, || {
+        chip.clock();
+        chip.output()
+    });
+    assert!((peak(&samples) / 0.149 - 10f32.powf(-6.0 / 20.0)).abs() < 0.001);
+
+    // Envelope shape 0 decays once and stays silent
+    write(&mut chip, 0x07, 0x3F);
+    write(&mut chip, 0x08, 0x10);
+    write(&mut chip, 0x0B, 0x01);
+    write(&mut chip, 0x0D, 0x00);
+    let samples = record(32 * 100 * 2, || {
+        chip.clock();
+        chip.output()
+    });
+    assert!(samples[0] > 0.14, "The decay starts at full volume");
+    assert_eq!(peak(&samples[8 * 32..]), 0.0, "After 32 steps the envelope holds at silence");
+}
@@ -0,0 +1,113 @@
+use rusty_nes::controller::Controller;
+use rusty_nes::input::{Devices, ExpansionDevice, PortDevice};
+use rusty_nes::memory::Memory;
+
+fn with_devices(ports: [PortDevice; 2], expansion: ExpansionDevice) -> Memory {
+    let mut memory = Memory::new();
+    memory.input.devices = Devices { ports, expansion };
+    memory
+}
+
+// Strobes the ports and reads the given number of values from one of them, masked
+fn read_serial(memory: &mut Memory, address: u16, count: usize, mask: u8) -> Vec<u8> {
+    memory.write(0x4016, 1);
+    memory.write(0x4016, 0);
+    (0..count).map(|_| memory.read(address) & mask).collect()
+}
+
+#[test]
+fn test_four_score_and_famicom_players() {
+    let mut memory = with_devices([PortDevice::FourScore; 2], ExpansionDevice::Empty);
+    memory.input.controllers[0].buttons = Controller::A;
+    memory.input.controllers[1].buttons = Controller::B;
+    memory.input.controllers[2].buttons = Controller::START;
+    memory.input.controllers[3].buttons = Controller::RIGHT;
+    let port1 = read_serial(&mut memory, 0x4016, 25, 0x01);
+    assert_eq!(port1[..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(port1[8..16], [0, 0, 0, 1, 0, 0, 0, 0], "Player 3 follows player 1");
+    assert_eq!(port1[16..], [0, 0, 0, 1, 0, 0, 0, 0, 1], "Then the signature, then 1s");
+    let port2 = read_serial(&mut memory, 0x4017, 24, 0x01);
+    assert_eq!(port2[8..16], [0, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(port2[16..], [0, 0, 1, 0, 0, 0, 0, 0]);
+
+    // On a Famicom, players 3 and 4 come in on D1 instead, alongside the joypads
+    memory.input.devices = Devices { ports: [PortDevice::Controller; 2], expansion: ExpansionDevice::Controllers };
+    assert_eq!(read_serial(&mut memory, 0x4016, 4, 0x03), [1, 0, 0, 2]);
+    assert_eq!(read_serial(&mut memory, 0x4017, 2, 0x03), [0, 1]);
+}
+
+#[test]
+fn test_vaus_and_power_pad() {
+    let mut memory = with_devices([PortDevice::Controller, PortDevice::Vaus], ExpansionDevice::Empty);
+    memory.input.vaus.position = 0xA5;
+    memory.input.vaus.button = true;
+    let bits = read_serial(&mut memory, 0x4017, 9, 0x18);
+    let knob: Vec<u8> = bits.iter().map(|bits| bits >> 4).collect();
+    assert_eq!(knob, [0, 1, 0, 1, 1, 0, 1, 0, 1], "The knob comes out inverted, high bit first");
+    assert!(bits.iter().all(|bits| bits & 0x08 != 0), "Fire is on D3");
+
+    // The Famicom paddle has the button on $4016 D1 and the knob on $4017 D1
+    memory.input.devices = Devices { ports: [PortDevice::Controller; 2], expansion: ExpansionDevice::Vaus };
+    assert_eq!(memory.read(0x4016) & 0x02, 0x02);
+    assert_eq!(read_serial(&mut memory, 0x4017, 2, 0x02), [0, 2]);
+
+    memory.input.devices = Devices { ports: [PortDevice::Controller, PortDevice::PowerPad], ..Devices::default() };
+    memory.input.power_pad.buttons = 1 << 0 | 1 << 11; // Buttons 1 and 12
+    let bits = read_serial(&mut memory, 0x4017, 9, 0x18);
+    let d3: Vec<u8> = bits.iter().map(|bits| bits >> 3 & 1).collect();
+    let d4: Vec<u8> = bits.iter().map(|bits| bits >> 4 & 1).collect();
+    assert_eq!(d3, [0, 1, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(d4, [0, 0, 1, 0, 1, 1, 1, 1, 1], "D4 has only four buttons before reading 1");
+
+    // The Family Trainer is scanned a row at a time through the $4016 write
+    memory.input.devices = Devices { expansion: ExpansionDevice::FamilyTrainer, ..Devices::default() };
+    memory.write(0x4016, 0x03);
+    assert_eq!(memory.read(0x4017) & 0x1E, 0x0E, "Button 1 is D4 of the first row, active low");
+    memory.write(0x4016, 0x06);
+    assert_eq!(memory.read(0x4017) & 0x1E, 0x1C, "Button 12 is D1 of the last row");
+    memory.write(0x4016, 0x05);
+    assert_eq!(memory.read(0x4017) & 0x1E, 0x1E);
+
+    memory.input.power_pad.buttons = 0xFFFF;
+    assert!(memory.input.power_pad.pressed(16), "The top bit is button 16");
+    assert!(!memory.input.power_pad.pressed(0), "There is no button 0");
+    assert!(!memory.input.power_pad.pressed(17), "Buttons past 16 read as released");
+}
+
+#[test]
+fn test_family_basic_keyboard_scan() {
+    let mut memory = with_devices([PortDevice::Controller; 2], ExpansionDevice::Keyboard);
+    memory.input.keyboard.set_key(0, 1, 2, true);
+    memory.input.keyboard.set_key(3, 0, 0, true);
+    // Out-of-range keys are ignored
+    memory.input.keyboard.set_key(9, 0, 0, true);
+    memory.input.keyboard.set_key(5, 2, 0, true);
+    memory.input.keyboard.set_key(5, 1, 4, true);
+
+    // Reset to row 0, then walk the rows reading both columns
+    memory.write(0x4016, 0x05);
+    let mut scan = Vec::new();
+    for _ in 0..9 {
+        memory.write(0x4016, 0x04);
+        let column0 = memory.read(0x4017) & 0x1E;
+        memory.write(0x4016, 0x06);
+        let column1 = memory.read(0x4017) & 0x1E;
+        scan.push((column0, column1));
+    }
+    assert_eq!(scan[0], (0x1E, 0x16), "Row 0 column 1 has its third key held");
+    assert_eq!(scan[3], (0x1C, 0x1E));
+    assert!(scan.iter().enumerate().all(|(row, keys)| row == 0 || row == 3 || *keys == (0x1E, 0x1E)));
+
+    memory.write(0x4016, 0x00);
+    assert_eq!(memory.read(0x4017) & 0x1E, 0x00, "Powered off, the keyboard drives nothing");
+}
+
+#[test]
+fn test_header_picks_devices() {
+    assert_eq!(Devices::from_nes2_expansion(0x01), Devices::default());
+    assert_eq!(Devices::from_nes2_expansion(0x02).ports, [PortDevice::FourScore; 2]);
+    assert_eq!(Devices::from_nes2_expansion(0x08).ports, [PortDevice::Controller, PortDevice::Zapper]);
+    assert_eq!(Devices::from_nes2_expansion(0x0F).ports[1], PortDevice::Vaus);
+    assert_eq!(Devices::from_nes2_expansion(0x10).expansion, ExpansionDevice::Vaus);
+    assert_eq!(Devices::from_nes2_expansion(0x23).expansion, ExpansionDevice::Keyboard);
+}
@@ -0,0 +1,766 @@
+mod common;
+
+use common::{build_ines, nop_prg};
+use rusty_nes::cartridge::{Rom, RomError};
+use rusty_nes::mapper::{
+    self, Bandai, Board, Discrete, Fme7, IrqRevision, Mapper, Mmc1, Mmc2, Mmc3, Namco163, Vrc4, Vrc6, Vrc7,
+};
+use rusty_nes::nes::Nes;
+use rusty_nes::ppu_bus::Mirroring;
+
+#[test]
+fn test_nrom_128_is_mirrored() {
+    let mut prg = nop_prg(0x4000);
+    prg[0x0000] = 0xAA;
+    let rom = Rom::parse(&build_ines(0, 0, &prg, &[0; 0x2000])).unwrap();
+
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+
+    assert_eq!(nes.memory.read(0x8000), 0xAA);
+    assert_eq!(nes.memory.read(0xC000), 0xAA, "16 KiB of PRG-ROM should appear twice");
+    assert_eq!(nes.cpu.pc, 0xC000, "PC should come from the reset vector in ROM");
+}
+
+#[test]
+fn test_nrom_boots_program() {
+    let mut prg = nop_prg(0x8000);
+    // $C000: LDA #$42; STA $0200; JSR $C010; JMP $C00B
+    let program = [0xA9, 0x42, 0x8D, 0x00, 0x02, 0x20, 0x10, 0xC0, 0x4C, 0x0B, 0xC0, 0x4C, 0x0B, 0xC0];
+    prg[0x4000..0x4000 + program.len()].copy_from_slice(&program);
+    // $C010: INX; RTS
+    prg[0x4010] = 0xE8;
+    prg[0x4011] = 0x60;
+    let rom = Rom::parse(&build_ines(0, 0, &prg, &[0; 0x2000])).unwrap();
+
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+    nes.run_frame();
+
+    assert_eq!(nes.memory.read(0x0200), 0x42);
+    assert_eq!(nes.memory.read(0x0A00), 0x42, "RAM should be mirrored every 2 KiB");
+    assert_eq!(nes.cpu.x, 0x01, "The subroutine should run exactly once");
+    assert_eq!(nes.cpu.pc, 0xC00B, "RTS should return past the JSR");
+    assert_eq!(nes.cpu.sp, 0xFD, "The stack should be balanced");
+}
+
+#[test]
+fn test_nrom_pattern_tables_and_mirroring() {
+    let mut chr = vec![0u8; 0x2000];
+    chr[0x1234] = 0x5A;
+    let rom = Rom::parse(&build_ines(0, 0x01, &nop_prg(0x4000), &chr)).unwrap();
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+
+    nes.memory.write(0x2006, 0x12);
+    nes.memory.write(0x2006, 0x34);
+    nes.memory.read(0x2007);
+    assert_eq!(nes.memory.read(0x2007), 0x5A, "Pattern tables should read from CHR-ROM");
+
+    nes.memory.write(0x2006, 0x12);
+    nes.memory.write(0x2006, 0x34);
+    nes.memory.write(0x2007, 0x00);
+    nes.memory.write(0x2006, 0x12);
+    nes.memory.write(0x2006, 0x34);
+    nes.memory.read(0x2007);
+    assert_eq!(nes.memory.read(0x2007), 0x5A, "CHR-ROM should not be writable");
+
+    assert_eq!(nes.memory.cartridge.as_ref().unwrap().mirroring(), Mirroring::Vertical);
+}
+
+#[test]
+fn test_nrom_chr_ram() {
+    let rom = Rom::parse(&build_ines(0, 0, &nop_prg(0x4000), &[])).unwrap();
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+
+    nes.memory.write(0x2006, 0x00);
+    nes.memory.write(0x2006, 0x10);
+    nes.memory.write(0x2007, 0x77);
+    nes.memory.write(0x2006, 0x00);
+    nes.memory.write(0x2006, 0x10);
+    nes.memory.read(0x2007);
+    assert_eq!(nes.memory.read(0x2007), 0x77, "Boards without CHR-ROM get CHR-RAM");
+}
+
+#[test]
+fn test_rom_errors() {
+    let data = build_ines(0xFE, 0, &nop_prg(0x4000), &[]);
+    let rom = Rom::parse(&data).unwrap();
+    assert!(matches!(mapper::create(rom), Err(RomError::UnsupportedMapper(0xFE))));
+
+    assert!(matches!(Rom::parse(&data[..100]), Err(RomError::Truncated { .. })));
+    assert!(matches!(Rom::parse(b"not a rom at all"), Err(RomError::InvalidHeader)));
+}
+
+#[test]
+fn test_malformed_rom_sizes() {
+    // No PRG-ROM banks, on the NROM and MMC3 boards that divide by the PRG size
+    for mapper in [0, 4] {
+        let data = build_ines(mapper, 0, &[], &[0; 0x2000]);
+        assert!(matches!(Rom::parse(&data), Err(RomError::NoPrgRom)), "Mapper {} with no PRG-ROM", mapper);
+    }
+
+    // NES 2.0 exponent form with the largest exponent and multiplier
+    let mut data = build_ines(0, 0, &nop_prg(0x4000), &[]);
+    data[7] |= 0x08;
+    data[4] = 0xFF;
+    data[9] = 0x0F;
+    assert!(matches!(Rom::parse(&data), Err(RomError::SizeOverflow)));
+}
+
+// Loads an MMC1 register through the serial port, one bit per write
+fn mmc1_write(mapper: &mut Mmc1, address: u16, value: u8) {
+    for bit in 0..5 {
+        mapper.write_prg(address, (value >> bit) & 0x01);
+        mapper.cpu_clock();
+    }
+}
+
+// PRG-ROM whose 16 KiB banks start with their own bank number
+fn numbered_prg(banks: usize) -> Vec<u8> {
+    let mut prg = vec![0; banks * 0x4000];
+    for bank in 0..banks {
+        prg[bank * 0x4000] = bank as u8;
+    }
+    prg
+}
+
+#[test]
+fn test_mmc1_prg_banking_modes() {
+    let rom = Rom::parse(&build_ines(1, 0, &numbered_prg(8), &[0; 0x2000])).unwrap();
+    let mut mapper = Mmc1::new(rom);
+
+    assert_eq!(mapper.peek_prg(0xC000), Some(7), "The last bank should be fixed at $C000 on power-up");
+    mmc1_write(&mut mapper, 0xE000, 3);
+    assert_eq!(mapper.peek_prg(0x8000), Some(3));
+    assert_eq!(mapper.peek_prg(0xC000), Some(7));
+
+    mmc1_write(&mut mapper, 0x8000, 0x08); // Fix the first bank at $8000
+    assert_eq!(mapper.peek_prg(0x8000), Some(0));
+    assert_eq!(mapper.peek_prg(0xC000), Some(3));
+
+    mmc1_write(&mut mapper, 0x8000, 0x00); // 32 KiB mode ignores the low bit
+    assert_eq!(mapper.peek_prg(0x8000), Some(2));
+    assert_eq!(mapper.peek_prg(0xC000), Some(3));
+    assert_eq!(mapper.mirroring(), Mirroring::SingleScreenA);
+
+    mapper.write_prg(0x8000, 0x80);
+    assert_eq!(mapper.peek_prg(0xC000), Some(7), "Resetting the shift register restores PRG mode 3");
+}
+
+#[test]
+fn test_mmc1_ignores_consecutive_writes() {
+    let rom = Rom::parse(&build_ines(1, 0, &numbered_prg(8), &[0; 0x2000])).unwrap();
+    let mut mapper = Mmc1::new(rom);
+
+    // A read-modify-write instruction writes twice on back-to-back cycles
+    for bit in [1, 0, 0, 0, 0] {
+        mapper.write_prg(0xE000, bit);
+        mapper.write_prg(0xE000, 1);
+        mapper.cpu_clock();
+    }
+    assert_eq!(mapper.peek_prg(0x8000), Some(1), "The second write of each pair should be ignored");
+}
+
+#[test]
+fn test_mmc1_chr_banking_and_prg_ram() {
+    let mut chr = vec![0u8; 0x8000];
+    for bank in 0..8 {
+        chr[bank * 0x1000] = bank as u8;
+    }
+    let rom = Rom::parse(&build_ines(1, 0, &numbered_prg(2), &chr)).unwrap();
+    let mut mapper = Mmc1::new(rom);
+
+    mmc1_write(&mut mapper, 0xA000, 5);
+    assert_eq!(mapper.read_chr(0x0000), 4, "8 KiB mode should ignore the low bit");
+    assert_eq!(mapper.read_chr(0x1000), 5);
+
+    mmc1_write(&mut mapper, 0x8000, 0x1F); // 4 KiB CHR mode, horizontal mirroring
+    mmc1_write(&mut mapper, 0xC000, 2);
+    assert_eq!(mapper.read_chr(0x0000), 5);
+    assert_eq!(mapper.read_chr(0x1000), 2);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+    mapper.write_prg(0x6000, 0x99);
+    assert_eq!(mapper.peek_prg(0x6000), Some(0x99));
+    mmc1_write(&mut mapper, 0xE000, 0x10);
+    assert_eq!(mapper.peek_prg(0x6000), None, "PRG-RAM should be disabled by bit 4");
+}
+
+#[test]
+fn test_mmc1_surom_outer_bank() {
+    let rom = Rom::parse(&build_ines(1, 0, &numbered_prg(32), &[])).unwrap();
+    let mut mapper = Mmc1::new(rom);
+
+    assert_eq!(mapper.peek_prg(0xC000), Some(15));
+    mmc1_write(&mut mapper, 0xA000, 0x10);
+    mmc1_write(&mut mapper, 0xE000, 2);
+    assert_eq!(mapper.peek_prg(0x8000), Some(18), "CHR bit 4 should select the upper 256 KiB");
+    assert_eq!(mapper.peek_prg(0xC000), Some(31));
+}
+
+// Toggles PPU A12 the way one scanline of background at $0000 and sprites at $1000 does
+fn mmc3_scanline(mapper: &mut Mmc3) {
+    for _ in 0..20 {
+        mapper.ppu_clock(0x0000);
+    }
+    mapper.ppu_clock(0x1000);
+}
+
+#[test]
+fn test_mmc3_prg_and_chr_banking() {
+    let mut prg = vec![0u8; 0x10000];
+    for bank in 0..8 {
+        prg[bank * 0x2000] = bank as u8;
+    }
+    let mut chr = vec![0u8; 0x2000 * 2];
+    for bank in 0..16 {
+        chr[bank * 0x0400] = bank as u8;
+    }
+    let rom = Rom::parse(&build_ines(4, 0, &prg, &chr)).unwrap();
+    let mut mapper = Mmc3::new(rom);
+
+    mapper.write_prg(0x8000, 6);
+    mapper.write_prg(0x8001, 3);
+    mapper.write_prg(0x8000, 7);
+    mapper.write_prg(0x8001, 4);
+    assert_eq!(mapper.peek_prg(0x8000), Some(3));
+    assert_eq!(mapper.peek_prg(0xA000), Some(4));
+    assert_eq!(mapper.peek_prg(0xC000), Some(6), "$C000 should hold the second-last bank");
+    assert_eq!(mapper.peek_prg(0xE000), Some(7), "$E000 should hold the last bank");
+
+    mapper.write_prg(0x8000, 0x40);
+    assert_eq!(mapper.peek_prg(0x8000), Some(6), "PRG mode 1 swaps $8000 and $C000");
+    assert_eq!(mapper.peek_prg(0xC000), Some(3));
+
+    mapper.write_prg(0x8000, 0);
+    mapper.write_prg(0x8001, 9); // 2 KiB bank, low bit ignored
+    mapper.write_prg(0x8000, 5);
+    mapper.write_prg(0x8001, 12);
+    assert_eq!(mapper.read_chr(0x0000), 8);
+    assert_eq!(mapper.read_chr(0x0400), 9);
+    assert_eq!(mapper.read_chr(0x1C00), 12);
+
+    mapper.write_prg(0x8000, 0x80);
+    assert_eq!(mapper.read_chr(0x1000), 8, "CHR inversion moves the 2 KiB banks to $1000");
+    assert_eq!(mapper.read_chr(0x0C00), 12);
+
+    mapper.write_prg(0xA000, 1);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn test_mmc3_prg_ram_protect() {
+    let rom = Rom::parse(&build_ines(4, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap();
+    let mut mapper = Mmc3::new(rom);
+
+    mapper.write_prg(0x6000, 0x12);
+    mapper.write_prg(0xA001, 0xC0);
+    mapper.write_prg(0x6000, 0x34);
+    assert_eq!(mapper.peek_prg(0x6000), Some(0x12), "Writes should be ignored while protected");
+
+    mapper.write_prg(0xA001, 0x00);
+    assert_eq!(mapper.peek_prg(0x6000), None, "Disabled PRG-RAM leaves the bus open");
+}
+
+#[test]
+fn test_mmc3_irq_revisions() {
+    for (revision, expected) in [(IrqRevision::Sharp, 3), (IrqRevision::Nec, 1)] {
+        let rom = Rom::parse(&build_ines(4, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap();
+        let mut mapper = Mmc3::with_revision(rom, revision);
+
+        // A latch of zero reloads the counter with zero on every scanline
+        mapper.write_prg(0xC000, 0);
+        mapper.write_prg(0xC001, 0);
+        mapper.write_prg(0xE001, 0);
+        let mut irqs = 0;
+        for _ in 0..3 {
+            mmc3_scanline(&mut mapper);
+            if mapper.irq() {
+                irqs += 1;
+                mapper.write_prg(0xE000, 0);
+                mapper.write_prg(0xE001, 0);
+            }
+        }
+        assert_eq!(irqs, expected, "{:?} revision", revision);
+    }
+}
+
+#[test]
+fn test_mmc3_scanline_irq_reaches_cpu() {
+    let mut prg = nop_prg(0x8000);
+    let program = [
+        0xA9, 0x40, 0x8D, 0x17, 0x40, // Inhibit the APU frame IRQ
+        0xA9, 0x08, 0x8D, 0x00, 0x20, // Sprites at $1000
+        0xA9, 0x18, 0x8D, 0x01, 0x20, // Show background and sprites
+        0xA9, 0x13, 0x8D, 0x00, 0xC0, // IRQ every 20 scanlines
+        0x8D, 0x01, 0xC0, 0x8D, 0x01, 0xE0, // Reload, enable IRQ
+        0x58, // CLI
+        0x4C, 0x1B, 0xC0, // JMP $C01B
+    ];
+    prg[0x4000..0x4000 + program.len()].copy_from_slice(&program);
+    // IRQ handler at $C020: INC $10; acknowledge; RTI
+    let handler = [0xE6, 0x10, 0x8D, 0x00, 0xE0, 0x8D, 0x01, 0xE0, 0x40];
+    prg[0x4020..0x4020 + handler.len()].copy_from_slice(&handler);
+    prg[0x7FFE] = 0x20;
+    let rom = Rom::parse(&build_ines(4, 0, &prg, &[0; 0x2000])).unwrap();
+
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+    nes.run_frame();
+    let before = nes.memory.read(0x0010);
+    nes.run_frame();
+    let irqs = nes.memory.read(0x0010) - before;
+
+    // 241 rendered lines clock the counter every frame, one IRQ per 20 of them
+    assert!((11..=13).contains(&irqs), "Expected about 12 IRQs per frame, got {}", irqs);
+}
+
+// CHR-ROM whose 8 KiB banks start with their own bank number
+fn numbered_chr(banks: usize) -> Vec<u8> {
+    let mut chr = vec![0; banks * 0x2000];
+    for bank in 0..banks {
+        chr[bank * 0x2000] = bank as u8;
+    }
+    chr
+}
+
+#[test]
+fn test_discrete_boards_from_mapper_number() {
+    // (mapper, latch value, expected first byte at $8000, expected CHR bank)
+    let cases = [(2, 3, 3, 0), (3, 2, 0, 2), (7, 1, 2, 0), (11, 0x21, 2, 2), (34, 1, 2, 0), (66, 0x12, 2, 2)];
+    for (number, latch, prg_byte, chr_bank) in cases {
+        let chr = if number == 34 { Vec::new() } else { numbered_chr(4) };
+        // Write where the ROM reads $FF so bus conflicts don't interfere
+        let mut prg = numbered_prg(8);
+        prg[0x0001] = 0xFF;
+        let rom = Rom::parse(&build_ines(number, 0, &prg, &chr)).unwrap();
+        let mut board = mapper::create(rom).unwrap();
+
+        board.write_prg(0x8001, latch);
+        assert_eq!(board.peek_prg(0x8000), Some(prg_byte), "Mapper {} PRG bank", number);
+        assert_eq!(board.read_chr(0x0000), chr_bank, "Mapper {} CHR bank", number);
+    }
+}
+
+#[test]
+fn test_uxrom_fixed_bank_and_bus_conflicts() {
+    let mut prg = numbered_prg(8);
+    prg[0x4000 * 7 + 0x10] = 0x05; // The fixed bank holds $05 at $C010
+    let rom = Rom::parse(&build_ines(2, 0, &prg, &[])).unwrap();
+    let mut board = Discrete::new(rom, Board::Uxrom);
+
+    assert_eq!(board.peek_prg(0xC000), Some(7));
+    board.write_prg(0xC010, 0x06);
+    assert_eq!(board.peek_prg(0x8000), Some(4), "The ROM's $05 should be ANDed with the written $06");
+    assert_eq!(board.peek_prg(0xC000), Some(7), "The last bank should stay fixed");
+}
+
+#[test]
+fn test_axrom_single_screen_mirroring() {
+    let rom = Rom::parse(&build_ines(7, 0, &numbered_prg(8), &[])).unwrap();
+    let mut board = Discrete::new(rom, Board::Axrom);
+
+    assert_eq!(board.mirroring(), Mirroring::SingleScreenA);
+    board.write_prg(0x8000, 0x13);
+    assert_eq!(board.mirroring(), Mirroring::SingleScreenB);
+    assert_eq!(board.peek_prg(0x8000), Some(6), "AxROM has no bus conflicts and switches 32 KiB");
+}
+
+// CHR-ROM whose 4 KiB banks start with their own bank number
+fn numbered_chr_4k(banks: usize) -> Vec<u8> {
+    let mut chr = vec![0; banks * 0x1000];
+    for bank in 0..banks {
+        chr[bank * 0x1000] = bank as u8;
+    }
+    chr
+}
+
+#[test]
+fn test_mmc2_latch_switches_after_fetch() {
+    let rom = Rom::parse(&build_ines(9, 0, &numbered_prg(8), &numbered_chr_4k(32))).unwrap();
+    let mut mapper = Mmc2::new(rom);
+    mapper.write_prg(0xB000, 4); // $0000 bank while latch 0 is $FD
+    mapper.write_prg(0xC000, 5); // $0000 bank while latch 0 is $FE
+    mapper.write_prg(0xD000, 6);
+    mapper.write_prg(0xE000, 7);
+
+    assert_eq!(mapper.read_chr(0x0000), 5, "Latches power on selecting $FE");
+    mapper.read_chr(0x0FD8);
+    assert_eq!(mapper.read_chr(0x0000), 4);
+    mapper.read_chr(0x0FD9);
+    mapper.read_chr(0x0FE9);
+    assert_eq!(mapper.read_chr(0x0000), 4, "MMC2 only reacts to $0FD8 and $0FE8 in the left table");
+
+    mapper.read_chr(0x1FDB);
+    assert_eq!(mapper.read_chr(0x1000), 6, "The right table reacts to the whole tile row");
+
+    mapper.write_prg(0xA000, 2);
+    assert_eq!(mapper.peek_prg(0x8000), Some(1), "8 KiB bank 2 is the start of 16 KiB bank 1");
+    assert_eq!(mapper.peek_prg(0xC000), Some(7), "The last three 8 KiB banks are fixed");
+}
+
+#[test]
+fn test_mmc4_latch_follows_ppu_fetches() {
+    let rom = Rom::parse(&build_ines(10, 0, &numbered_prg(8), &numbered_chr_4k(32))).unwrap();
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+    nes.memory.write(0xB000, 2);
+    nes.memory.write(0xC000, 3);
+    assert_eq!(nes.memory.read(0xC000), 7, "The last 16 KiB should be fixed at $C000");
+
+    // One $FD tile on an otherwise blank screen
+    nes.memory.write(0x2006, 0x20);
+    nes.memory.write(0x2006, 0x42);
+    nes.memory.write(0x2007, 0xFD);
+    nes.memory.write(0x2006, 0x00);
+    nes.memory.write(0x2006, 0x00);
+    nes.memory.write(0x2001, 0x08);
+    nes.run_frame();
+    nes.run_frame();
+
+    let mapper = nes.memory.cartridge.as_mut().unwrap();
+    assert_eq!(mapper.read_chr(0x0000), 2, "Fetching tile $FD should select the $FD bank");
+}
+
+// PRG-ROM whose 8 KiB banks start with their own bank number
+fn numbered_prg_8k(banks: usize) -> Vec<u8> {
+    let mut prg = vec![0; banks * 0x2000];
+    for bank in 0..banks {
+        prg[bank * 0x2000] = bank as u8;
+    }
+    prg
+}
+
+// CHR-ROM whose 1 KiB banks start with their own bank number
+fn numbered_chr_1k(banks: usize) -> Vec<u8> {
+    let mut chr = vec![0; banks * 0x0400];
+    for bank in 0..banks {
+        chr[bank * 0x0400] = bank as u8;
+    }
+    chr
+}
+
+fn vrc_rom(mapper: u8, submapper: u8) -> Rom {
+    let mut rom = Rom::parse(&build_ines(mapper, 0, &numbered_prg_8k(16), &numbered_chr_1k(64))).unwrap();
+    rom.header.submapper = submapper;
+    rom
+}
+
+#[test]
+fn test_vrc4_address_wiring_by_submapper() {
+    // (mapper, submapper, address of register select 0, address of register select 1)
+    let boards = [(21, 1, 0x02, 0x04), (21, 2, 0x40, 0x80), (23, 2, 0x04, 0x08), (25, 2, 0x08, 0x04)];
+    for (number, submapper, select0, select1) in boards {
+        let mut mapper = Vrc4::new(vrc_rom(number, submapper));
+        mapper.write_prg(0xB000 + select1, 0x05); // CHR bank 1, low nibble
+        mapper.write_prg(0xB000 + select1 + select0, 0x01); // CHR bank 1, high bits
+        assert_eq!(mapper.read_chr(0x0400), 0x15, "Mapper {} submapper {} CHR bank", number, submapper);
+
+        mapper.write_prg(0x8000, 3);
+        mapper.write_prg(0x9000 + select1, 0x02); // Swap $8000 and $C000
+        assert_eq!(mapper.peek_prg(0xC000), Some(3), "Mapper {} submapper {} PRG swap", number, submapper);
+        assert_eq!(mapper.peek_prg(0x8000), Some(14));
+        assert_eq!(mapper.peek_prg(0xE000), Some(15));
+    }
+
+    // iNES 1.0 files get both wirings at once
+    let mut mapper = Vrc4::new(vrc_rom(23, 0));
+    mapper.write_prg(0xB001, 0x07);
+    mapper.write_prg(0xB008, 0x09);
+    assert_eq!(mapper.read_chr(0x0400), 0x09);
+    mapper.write_prg(0x9000, 0x01);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn test_vrc2a_ignores_low_chr_bit() {
+    let mut mapper = Vrc4::new(vrc_rom(22, 0));
+    mapper.write_prg(0xB000, 0x07);
+    assert_eq!(mapper.read_chr(0x0000), 3, "VRC2a drops the low bit of CHR bank numbers");
+
+    mapper.write_prg(0x9000, 0x03);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal, "VRC2 only has one mirroring bit");
+    mapper.write_prg(0xF002, 0x02);
+    for _ in 0..1000 {
+        mapper.cpu_clock();
+    }
+    assert!(!mapper.irq(), "VRC2 has no IRQ counter");
+}
+
+#[test]
+fn test_vrc_irq_prescaler_and_cycle_mode() {
+    let mut mapper = Vrc4::new(vrc_rom(25, 1));
+    mapper.write_prg(0xF000, 0x0E);
+    mapper.write_prg(0xF002, 0x0F); // Latch $FE
+    mapper.write_prg(0xF001, 0x03); // Enable in scanline mode, re-enable after acknowledge
+
+    // Each scanline is 341 / 3 CPU cycles, so the second one overflows at cycle 228
+    for _ in 0..227 {
+        mapper.cpu_clock();
+    }
+    assert!(!mapper.irq());
+    mapper.cpu_clock();
+    assert!(mapper.irq(), "The counter should overflow after two scanlines");
+
+    mapper.write_prg(0xF003, 0);
+    assert!(!mapper.irq());
+    for _ in 0..341 {
+        mapper.cpu_clock();
+    }
+    assert!(mapper.irq(), "The counter should reload from the latch and keep running");
+
+    mapper.write_prg(0xF001, 0x06); // Cycle mode
+    mapper.cpu_clock();
+    assert!(!mapper.irq());
+    mapper.cpu_clock();
+    assert!(mapper.irq(), "Cycle mode counts every CPU cycle");
+}
+
+#[test]
+fn test_vrc6_banking_and_audio() {
+    let mut mapper = Vrc6::new(vrc_rom(26, 0));
+    mapper.write_prg(0x8000, 2);
+    mapper.write_prg(0xC000, 9);
+    assert_eq!(mapper.peek_prg(0x8000), Some(4), "$8000 is a 16 KiB bank");
+    assert_eq!(mapper.peek_prg(0xA000), Some(5));
+    assert_eq!(mapper.peek_prg(0xC000), Some(9));
+    assert_eq!(mapper.peek_prg(0xE000), Some(15));
+
+    mapper.write_prg(0xE001, 42); // VRC6b swaps A0 and A1, so this is $E002
+    assert_eq!(mapper.read_chr(0x1800), 42);
+    mapper.write_prg(0xB003, 0xA4);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    mapper.write_prg(0x6000, 0x5A);
+    assert_eq!(mapper.peek_prg(0x6000), Some(0x5A), "PRG-RAM is enabled by $B003 bit 7");
+
+    assert_eq!(mapper.audio_output(), 0.0);
+    mapper.write_prg(0x9000, 0x8F); // Pulse 1 in digitized mode at full volume
+    mapper.write_prg(0x9001, 0x80); // $9002: enable
+    mapper.cpu_clock();
+    assert!(mapper.audio_output() > 0.0, "The pulse should be audible");
+}
+
+#[test]
+fn test_vrc7_banking() {
+    let mut mapper = Vrc7::new(vrc_rom(85, 2));
+    mapper.write_prg(0x8000, 4);
+    mapper.write_prg(0x8010, 5);
+    mapper.write_prg(0x9000, 6);
+    assert_eq!(mapper.peek_prg(0x8000), Some(4));
+    assert_eq!(mapper.peek_prg(0xA000), Some(5));
+    assert_eq!(mapper.peek_prg(0xC000), Some(6));
+    assert_eq!(mapper.peek_prg(0xE000), Some(15));
+
+    mapper.write_prg(0xA010, 33);
+    mapper.write_prg(0xD000, 34);
+    assert_eq!(mapper.read_chr(0x0400), 33);
+    assert_eq!(mapper.read_chr(0x1800), 34);
+
+    mapper.write_prg(0x9010, 0x10);
+    mapper.write_prg(0x9030, 0xAB);
+    assert_eq!(mapper.audio.register(0x10), 0xAB, "Writes should reach the FM chip's registers");
+    assert_eq!(mapper.peek_prg(0xC000), Some(6), "The audio ports should not touch PRG banking");
+
+    mapper.write_prg(0xE000, 0x83);
+    assert_eq!(mapper.mirroring(), Mirroring::SingleScreenB);
+    mapper.write_prg(0x6000, 0x11);
+    assert_eq!(mapper.peek_prg(0x6000), Some(0x11));
+}
+
+#[test]
+fn test_namco163_banking_irq_and_audio() {
+    let mut mapper = Namco163::new(vrc_rom(19, 0));
+    mapper.write_prg(0xE000, 3);
+    mapper.write_prg(0xE800, 4);
+    mapper.write_prg(0xF000, 5);
+    assert_eq!(mapper.peek_prg(0x8000), Some(3));
+    assert_eq!(mapper.peek_prg(0xA000), Some(4));
+    assert_eq!(mapper.peek_prg(0xC000), Some(5));
+    assert_eq!(mapper.peek_prg(0xE000), Some(15));
+    mapper.write_prg(0x8800, 40);
+    assert_eq!(mapper.read_chr(0x0400), 40);
+
+    // Nametable registers pick a console VRAM page or a CHR-ROM bank
+    let mut vram = [0; 4096];
+    vram[0x0400] = 0x77;
+    mapper.write_prg(0xC000, 0xE1);
+    mapper.write_prg(0xC800, 9);
+    assert_eq!(mapper.read_nametable(0x2000, &vram), 0x77);
+    assert_eq!(mapper.read_nametable(0x2400, &vram), 9, "Nametable 1 comes from CHR bank 9");
+    mapper.write_nametable(0x2400, 0x55, &mut vram);
+    assert_eq!(mapper.read_nametable(0x2400, &vram), 9, "CHR-ROM nametables are read-only");
+
+    mapper.write_prg(0x6000, 0x12);
+    assert_eq!(mapper.peek_prg(0x6000), Some(0x00), "PRG-RAM is write-protected until $F800 allows it");
+    mapper.write_prg(0xF800, 0x40);
+    mapper.write_prg(0x6000, 0x12);
+    assert_eq!(mapper.peek_prg(0x6000), Some(0x12));
+
+    mapper.write_prg(0x5000, 0xFD);
+    mapper.write_prg(0x5800, 0xFF); // Counter at $7FFD, enabled
+    mapper.cpu_clock();
+    assert!(!mapper.irq());
+    mapper.cpu_clock();
+    assert!(mapper.irq(), "The IRQ fires when the counter reaches $7FFF");
+    assert_eq!(mapper.peek_prg(0x5000), Some(0xFF));
+    mapper.write_prg(0x5800, 0x00);
+    assert!(!mapper.irq(), "Writing the counter acknowledges the IRQ");
+
+    // A one-channel wave at full volume through the $F800/$4800 ports
+    mapper.write_prg(0xF800, 0x80);
+    for _ in 0..16 {
+        mapper.write_prg(0x4800, 0xFF);
+    }
+    mapper.write_prg(0xF800, 0x7C);
+    mapper.write_prg(0x4800, 0xE0); // 32-sample wave
+    mapper.write_prg(0xF800, 0x7F);
+    mapper.write_prg(0x4800, 0x0F); // One channel, volume 15
+    for _ in 0..15 {
+        mapper.cpu_clock();
+    }
+    assert!(mapper.audio_output() > 0.0, "Channel 7 should be audible");
+    mapper.write_prg(0xE000, 0x43);
+    assert_eq!(mapper.audio_output(), 0.0, "$E000 bit 6 silences the audio");
+}
+
+#[test]
+fn test_fme7_banking_irq_and_audio() {
+    let mut mapper = Fme7::new(vrc_rom(69, 0));
+    for (command, bank) in [(0x9, 3), (0xA, 4), (0xB, 5), (0x8, 6), (0x1, 33)] {
+        mapper.write_prg(0x8000, command);
+        mapper.write_prg(0xA000, bank);
+    }
+    assert_eq!(mapper.peek_prg(0x6000), Some(6), "$6000 holds a ROM bank unless RAM is selected");
+    assert_eq!(mapper.peek_prg(0x8000), Some(3));
+    assert_eq!(mapper.peek_prg(0xA000), Some(4));
+    assert_eq!(mapper.peek_prg(0xC000), Some(5));
+    assert_eq!(mapper.peek_prg(0xE000), Some(15));
+    assert_eq!(mapper.read_chr(0x0400), 33);
+
+    mapper.write_prg(0x8000, 0x8);
+    mapper.write_prg(0xA000, 0x40);
+    assert_eq!(mapper.peek_prg(0x6000), None, "Disabled PRG-RAM leaves the bus open");
+    mapper.write_prg(0xA000, 0xC0);
+    mapper.write_prg(0x6000, 0x34);
+    assert_eq!(mapper.peek_prg(0x6000), Some(0x34));
+
+    mapper.write_prg(0x8000, 0xC);
+    mapper.write_prg(0xA000, 0x01);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+    for (command, value) in [(0xE, 0x01), (0xF, 0x00), (0xD, 0x81)] {
+        mapper.write_prg(0x8000, command);
+        mapper.write_prg(0xA000, value);
+    }
+    mapper.cpu_clock();
+    assert!(!mapper.irq());
+    mapper.cpu_clock();
+    assert!(mapper.irq(), "The IRQ fires when the counter wraps past zero");
+    mapper.write_prg(0xA000, 0x81);
+    assert!(!mapper.irq(), "Writing the IRQ control acknowledges it");
+
+    // Tone A at volume 15 through the $C000/$E000 ports
+    for (register, value) in [(0x00, 0x01), (0x07, 0x3E), (0x08, 0x0F)] {
+        mapper.write_prg(0xC000, register);
+        mapper.write_prg(0xE000, value);
+    }
+    for _ in 0..16 {
+        mapper.cpu_clock();
+    }
+    assert!(mapper.audio_output() > 0.0, "Tone A should be audible");
+    assert_eq!(mapper.peek_prg(0xE000), Some(15), "The audio ports should not touch PRG banking");
+}
+
+#[test]
+fn test_bandai_banking_and_irq_by_chip() {
+    let rom = |mapper, submapper| {
+        let mut rom = Rom::parse(&build_ines(mapper, 0, &numbered_prg(8), &numbered_chr_1k(64))).unwrap();
+        rom.header.submapper = submapper;
+        rom
+    };
+
+    // The LZ93D50 decodes $8000-$FFFF, and the IRQ control loads the counter from the latch
+    let mut mapper = Bandai::new(rom(16, 5));
+    for (register, value) in [(0x8, 3), (0x2, 40), (0x9, 1)] {
+        mapper.write_prg(0x6000 + register, 0);
+        mapper.write_prg(0x8000 + register, value);
+    }
+    assert_eq!(mapper.peek_prg(0x8000), Some(3));
+    assert_eq!(mapper.peek_prg(0xC000), Some(7), "The last bank is fixed at $C000");
+    assert_eq!(mapper.read_chr(0x0800), 40);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    assert_eq!(mapper.peek_prg(0x6000), None, "Without an EEPROM $6000 is open bus");
+
+    mapper.write_prg(0x800B, 0x02);
+    mapper.write_prg(0x800C, 0x00);
+    mapper.write_prg(0x800A, 0x01);
+    mapper.cpu_clock();
+    assert!(!mapper.irq());
+    mapper.cpu_clock();
+    assert!(mapper.irq(), "The IRQ fires when the counter reaches zero");
+    mapper.write_prg(0x800A, 0x00);
+    assert!(!mapper.irq(), "Writing the IRQ control acknowledges it");
+
+    // The FCG-1/2 decodes $6000-$7FFF and counts from whatever was written to the counter
+    let mut mapper = Bandai::new(rom(16, 4));
+    mapper.write_prg(0x8008, 2);
+    mapper.write_prg(0x6008, 5);
+    assert_eq!(mapper.peek_prg(0x8000), Some(5));
+    mapper.write_prg(0x600A, 0x01);
+    mapper.write_prg(0x600B, 0x01);
+    mapper.cpu_clock();
+    assert!(mapper.irq(), "The counter is written directly, even while running");
+}
+
+// Clocks SCL and SDA on a Bandai board's EEPROM through $800D, with reads enabled
+fn eeprom_lines(mapper: &mut dyn Mapper, scl: bool, sda: bool) {
+    mapper.write_prg(0x800D, 0x80 | (scl as u8) << 5 | (sda as u8) << 6);
+}
+
+// Sends a byte LSB first, as the 24C01 wants it, and returns whether it was acknowledged
+fn x24c01_send(mapper: &mut dyn Mapper, byte: u8) -> bool {
+    for bit in 0..8 {
+        let sda = byte >> bit & 1 != 0;
+        eeprom_lines(mapper, false, sda);
+        eeprom_lines(mapper, true, sda);
+        eeprom_lines(mapper, false, sda);
+    }
+    eeprom_lines(mapper, false, true);
+    eeprom_lines(mapper, true, true);
+    let ack = mapper.peek_prg(0x6000) == Some(0x00);
+    eeprom_lines(mapper, false, true);
+    ack
+}
+
+#[test]
+fn test_bandai_24c01_write_and_read() {
+    let mut mapper = Bandai::new(Rom::parse(&build_ines(159, 0, &numbered_prg(8), &numbered_chr_1k(64))).unwrap());
+    assert_eq!(mapper.eeprom().len(), 128);
+    let start = |mapper: &mut Bandai| {
+        for (scl, sda) in [(false, true), (true, true), (true, false), (false, false)] {
+            eeprom_lines(mapper, scl, sda);
+        }
+    };
+
+    // No device select byte: the address goes first, with the write bit on top
+    start(&mut mapper);
+    assert!(x24c01_send(&mut mapper, 0x05), "The address should be acknowledged");
+    assert!(x24c01_send(&mut mapper, 0xA5));
+    assert!(x24c01_send(&mut mapper, 0x3C));
+    for (scl, sda) in [(false, false), (true, false), (true, true)] {
+        eeprom_lines(&mut mapper, scl, sda);
+    }
+    assert_eq!(&mapper.eeprom()[5..7], [0xA5, 0x3C]);
+
+    start(&mut mapper);
+    assert!(x24c01_send(&mut mapper, 0x85), "Address 5 with the read bit");
+    let mut byte = 0;
+    for bit in 0..8 {
+        eeprom_lines(&mut mapper, true, true);
+        byte |= (mapper.peek_prg(0x6000).unwrap() >> 4 & 1) << bit;
+        eeprom_lines(&mut mapper, false, true);
+    }
+    assert_eq!(byte, 0xA5, "Reads come back LSB first");
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::{build_ines, nop_prg};
+use rusty_nes::audio::Channel;
+use rusty_nes::cartridge::Rom;
+use rusty_nes::mapper::Mmc5;
+use rusty_nes::memory::Memory;
+use rusty_nes::mixer::{Mixer, MixerInput};
+use rusty_nes::nes::Nes;
+
+const PULSE1: MixerInput = MixerInput::Channel(Channel::Pulse1);
+const TRIANGLE: MixerInput = MixerInput::Channel(Channel::Triangle);
+
+// NOPs forever, with pulse 1 and the triangle playing
+fn playing_nes() -> Nes {
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&build_ines(0, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap()).unwrap();
+    let writes = [
+        (0x4015, 0x05),
+        (0x4000, 0xBF), // Pulse 1: constant volume 15
+        (0x4002, 0xFD),
+        (0x4003, 0x00),
+        (0x4008, 0xFF), // Triangle: linear counter held
+        (0x400A, 0x40),
+        (0x400B, 0x00),
+    ];
+    for (address, value) in writes {
+        nes.memory.write(address, value);
+    }
+    nes
+}
+
+#[test]
+fn test_mute_solo_and_volume_gains() {
+    let mut mixer = Mixer::new();
+    assert!(mixer.is_neutral());
+    mixer.set_volume(PULSE1, 0.5);
+    assert_eq!(mixer.gain(PULSE1), 0.5);
+    assert!(!mixer.is_neutral());
+
+    mixer.set_solo(TRIANGLE, true);
+    assert_eq!(mixer.gain(PULSE1), 0.0, "Soloing one channel silences the others");
+    assert_eq!(mixer.gain(TRIANGLE), 1.0);
+    mixer.set_muted(TRIANGLE, true);
+    assert_eq!(mixer.gain(TRIANGLE), 0.0, "Mute wins over solo");
+
+    // Expansion channels also go through the cartridge's own settings
+    mixer.reset();
+    mixer.set_volume(MixerInput::Channel(Channel::Expansion), 0.5);
+    mixer.set_volume(MixerInput::Expansion(2), 0.5);
+    assert_eq!(mixer.gain(MixerInput::Expansion(2)), 0.25);
+    mixer.set_solo(MixerInput::Channel(Channel::Expansion), true);
+    assert_eq!(mixer.gain(MixerInput::Expansion(0)), 0.5, "Soloing the cartridge solos all of its channels");
+    assert_eq!(mixer.gain(PULSE1), 0.0);
+}
+
+#[test]
+fn test_mixing_leaves_emulation_alone() {
+    let mut plain = playing_nes();
+    let mut mixed = playing_nes();
+    mixed.memory.mixer.set_muted(PULSE1, true);
+    mixed.memory.mixer.set_volume(TRIANGLE, 0.5);
+
+    for _ in 0..10 {
+        plain.run_frame();
+        mixed.run_frame();
+    }
+    assert_eq!(plain.save_state(), mixed.save_state(), "The mixer only changes what is heard");
+
+    let triangle = plain.memory.apu.triangle.output() as f32;
+    assert_eq!(mixed.memory.audio_output(), 159.79 / (8227.0 / (triangle * 0.5) + 100.0));
+    assert!(plain.memory.audio_output() > mixed.memory.audio_output());
+
+    // Back to neutral the mix is the APU's own again
+    mixed.memory.mixer.reset();
+    assert_eq!(mixed.memory.audio_output(), plain.memory.audio_output());
+}
+
+#[test]
+fn test_mmc5_mix_goes_through_its_dac_once() {
+    let mut memory = Memory::new();
+    let rom = Rom::parse(&build_ines(5, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap();
+    memory.cartridge = Some(Box::new(Mmc5::new(rom)));
+    let writes = [
+        (0x5015, 0x03),
+        (0x5000, 0xFF), // Both pulses: 75% duty, constant volume 15
+        (0x5002, 0x40),
+        (0x5003, 0x08),
+        (0x5004, 0xFF),
+        (0x5006, 0x40),
+        (0x5007, 0x08),
+        (0x5011, 0x80), // A raw PCM level
+    ];
+    for (address, value) in writes {
+        memory.write(address, value);
+    }
+
+    for _ in 0..200 {
+        memory.tick(7);
+        let neutral = memory.audio_output();
+        memory.mixer.set_muted(MixerInput::Channel(Channel::Noise), true);
+        assert_eq!(memory.audio_output(), neutral, "Muting a silent APU channel leaves the MMC5 level alone");
+        memory.mixer.reset();
+    }
+}
+
+#[test]
+fn test_meters_follow_each_frame() {
+    let mut nes = playing_nes();
+    nes.memory.mixer.set_metering(true);
+    nes.run_frame();
+
+    let pulse = nes.memory.mixer.meter(PULSE1);
+    assert!((pulse.peak - 95.88 / (8128.0 / 15.0 + 100.0)).abs() < 0.0001, "Pulse 1 peaks at volume 15");
+    assert!(pulse.rms > 0.0 && pulse.rms < pulse.peak);
+    assert!(nes.memory.mixer.meter(TRIANGLE).peak > 0.0);
+    assert_eq!(nes.memory.mixer.meter(MixerInput::Channel(Channel::Noise)).peak, 0.0);
+
+    nes.memory.mixer.set_muted(PULSE1, true);
+    nes.run_frame();
+    assert_eq!(nes.memory.mixer.meter(PULSE1).peak, 0.0, "Meters show what is heard");
+    assert_eq!(nes.memory.mixer.meters().len(), Channel::ALL.len());
+}
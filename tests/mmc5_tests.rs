@@ -0,0 +1,274 @@
+mod common;
+
+use common::build_ines;
+use rusty_nes::cartridge::Rom;
+use rusty_nes::mapper::{Mapper, Mmc5};
+use rusty_nes::nes::Nes;
+
+// 128 KiB of PRG-ROM whose 8 KiB banks start with their own bank number.
+// The last bank holds a JMP-to-self at $E000 and vectors pointing at it.
+fn mmc5_prg() -> Vec<u8> {
+    let mut prg = vec![0u8; 0x20000];
+    for bank in 0..16 {
+        prg[bank * 0x2000] = bank as u8;
+    }
+    let last = 0x1E000;
+    prg[last..last + 4].copy_from_slice(&[0x0F, 0x4C, 0x01, 0xE0]); // Bank number, then JMP $E001
+    for vector in 0..3 {
+        prg[0x1FFFA + vector * 2] = 0x01;
+        prg[0x1FFFB + vector * 2] = 0xE0;
+    }
+    prg
+}
+
+// 64 KiB of CHR-ROM. Tile 0 of every 4 KiB bank is a solid block of colour
+// (bank % 3) + 1, except bank 2 whose tile 0 is colour 3 on rows 0-3 and 2 below.
+// Tile 1 of each 1 KiB bank starts with the bank's number.
+fn mmc5_chr() -> Vec<u8> {
+    let mut chr = vec![0u8; 0x10000];
+    for bank in 0..64 {
+        chr[bank * 0x0400 + 0x10] = bank as u8;
+    }
+    for bank in 0..16 {
+        let base = bank * 0x1000;
+        let colour = (bank % 3) + 1;
+        for row in 0..8 {
+            let colour = if bank == 2 && row >= 4 { 2 } else { colour };
+            chr[base + row] = if colour & 1 != 0 { 0xFF } else { 0x00 };
+            chr[base + row + 8] = if colour & 2 != 0 { 0xFF } else { 0x00 };
+        }
+    }
+    chr
+}
+
+fn mmc5_rom() -> Rom {
+    Rom::parse(&build_ines(5, 0, &mmc5_prg(), &mmc5_chr())).unwrap()
+}
+
+// Console running an idle loop, with palette entry N holding the value N
+fn mmc5_nes() -> Nes {
+    let mut nes = Nes::new();
+    nes.insert_rom(mmc5_rom()).unwrap();
+    nes.memory.write(0x2006, 0x3F);
+    nes.memory.write(0x2006, 0x00);
+    nes.memory.write(0x2007, 0x20);
+    for entry in 1..16 {
+        nes.memory.write(0x2007, entry);
+    }
+    nes
+}
+
+fn render(nes: &mut Nes) {
+    nes.memory.write(0x2006, 0x00);
+    nes.memory.write(0x2006, 0x00);
+    nes.memory.write(0x2001, 0x0A); // Background, including the left 8 pixels
+    nes.run_frame();
+    nes.run_frame();
+}
+
+fn pixel(nes: &Nes, x: usize, y: usize) -> u16 {
+    nes.frame()[y * 256 + x] & 0x3F
+}
+
+#[test]
+fn test_mmc5_prg_modes() {
+    let mut mapper = Mmc5::new(mmc5_rom());
+    assert_eq!(mapper.peek_prg(0xE000), Some(15), "$5117 should power on selecting the last bank");
+
+    mapper.write_prg(0x5100, 0); // One 32 KiB bank from $5117
+    mapper.write_prg(0x5117, 0x85);
+    assert_eq!(mapper.peek_prg(0x8000), Some(4));
+    assert_eq!(mapper.peek_prg(0xE000), Some(7));
+
+    mapper.write_prg(0x5100, 1); // Two 16 KiB banks
+    mapper.write_prg(0x5115, 0x83);
+    assert_eq!(mapper.peek_prg(0x8000), Some(2));
+    assert_eq!(mapper.peek_prg(0xA000), Some(3));
+    assert_eq!(mapper.peek_prg(0xC000), Some(4));
+
+    mapper.write_prg(0x5100, 2); // 16 KiB + 8 KiB + 8 KiB
+    mapper.write_prg(0x5116, 0x89);
+    assert_eq!(mapper.peek_prg(0xC000), Some(9));
+    assert_eq!(mapper.peek_prg(0xE000), Some(5));
+
+    mapper.write_prg(0x5100, 3); // Four 8 KiB banks
+    mapper.write_prg(0x5114, 0x8B);
+    assert_eq!(mapper.peek_prg(0x8000), Some(11));
+    assert_eq!(mapper.peek_prg(0xA000), Some(3));
+}
+
+#[test]
+fn test_mmc5_prg_ram_banking_and_protect() {
+    let mut rom = mmc5_rom();
+    rom.header.prg_ram_size = 0x10000;
+    let mut mapper = Mmc5::new(rom);
+
+    mapper.write_prg(0x6000, 0x11);
+    assert_eq!(mapper.peek_prg(0x6000), Some(0x00), "PRG-RAM should be write-protected at power-on");
+
+    mapper.write_prg(0x5102, 0x02);
+    mapper.write_prg(0x5103, 0x01);
+    mapper.write_prg(0x5113, 3);
+    mapper.write_prg(0x6000, 0x33);
+
+    // RAM bank 3 mapped into the CPU's ROM space by clearing bit 7
+    mapper.write_prg(0x5114, 0x03);
+    assert_eq!(mapper.peek_prg(0x8000), Some(0x33));
+    mapper.write_prg(0x8001, 0x44);
+    assert_eq!(mapper.peek_prg(0x6001), Some(0x44), "Writes through $8000 should reach the same RAM");
+
+    mapper.write_prg(0x5103, 0x00);
+    mapper.write_prg(0x6001, 0x55);
+    assert_eq!(mapper.peek_prg(0x6001), Some(0x44), "Clearing $5103 should protect the RAM again");
+}
+
+#[test]
+fn test_mmc5_chr_modes_and_register_sets() {
+    let mut mapper = Mmc5::new(mmc5_rom());
+    mapper.write_prg(0x5101, 3); // 1 KiB banks
+    for register in 0..8 {
+        mapper.write_prg(0x5120 + register, 8 + register as u8);
+    }
+    assert_eq!(mapper.read_chr(0x0010), 8);
+    assert_eq!(mapper.read_chr(0x1C10), 15);
+
+    // Reads outside rendering use the set written last; the second set repeats every 4 KiB
+    mapper.write_prg(0x5128, 40);
+    mapper.write_prg(0x512B, 43);
+    assert_eq!(mapper.read_chr(0x0010), 40);
+    assert_eq!(mapper.read_chr(0x1C10), 43);
+
+    mapper.write_prg(0x5101, 2); // 2 KiB banks from the odd registers
+    mapper.write_prg(0x5125, 5);
+    assert_eq!(mapper.read_chr(0x1010), 10);
+    assert_eq!(mapper.read_chr(0x1410), 11);
+
+    // 4 KiB mode uses $5123/$5127, and $5130 supplies the upper bits when a bank is written
+    mapper.write_prg(0x5101, 1);
+    mapper.write_prg(0x5123, 1);
+    mapper.write_prg(0x5130, 0x01);
+    mapper.write_prg(0x5127, 2);
+    mapper.write_prg(0x5130, 0x00);
+    assert_eq!(mapper.read_chr(0x0010), 4);
+    assert_eq!(mapper.read_chr(0x1010), 8, "Bank 258 wraps around the 64 KiB of CHR-ROM to bank 2");
+}
+
+#[test]
+fn test_mmc5_multiplier_and_exram() {
+    let mut mapper = Mmc5::new(mmc5_rom());
+    mapper.write_prg(0x5205, 200);
+    mapper.write_prg(0x5206, 150);
+    let product = 200u16 * 150;
+    assert_eq!(mapper.read_prg(0x5205), Some(product as u8));
+    assert_eq!(mapper.read_prg(0x5206), Some((product >> 8) as u8));
+
+    mapper.write_prg(0x5104, 2);
+    mapper.write_prg(0x5C10, 0x77);
+    assert_eq!(mapper.read_prg(0x5C10), Some(0x77), "Mode 2 makes ExRAM plain CPU RAM");
+
+    mapper.write_prg(0x5104, 3);
+    mapper.write_prg(0x5C10, 0x12);
+    assert_eq!(mapper.read_prg(0x5C10), Some(0x77), "Mode 3 makes ExRAM read-only");
+
+    mapper.write_prg(0x5104, 0);
+    assert_eq!(mapper.read_prg(0x5C10), None, "Nametable mode leaves CPU reads open");
+    mapper.write_prg(0x5C10, 0x12);
+    mapper.write_prg(0x5104, 2);
+    assert_eq!(mapper.read_prg(0x5C10), Some(0x00), "Writes outside rendering store zero");
+}
+
+#[test]
+fn test_mmc5_scanline_irq() {
+    let mut nes = mmc5_nes();
+    nes.memory.write(0x4017, 0x40); // Keep the APU frame IRQ off the shared line
+    nes.memory.write(0x5203, 100);
+    nes.memory.write(0x5204, 0x80);
+    render(&mut nes);
+    nes.memory.read(0x5204);
+
+    for frame in 0..2 {
+        while !nes.memory.irq() {
+            nes.step();
+        }
+        assert_eq!(nes.memory.ppu.scanline, 100, "Frame {} IRQ should arrive on the target line", frame);
+        let status = nes.memory.read(0x5204);
+        assert_eq!(status & 0xC0, 0xC0, "Status should report the IRQ and that the PPU is in frame");
+        assert!(!nes.memory.irq(), "Reading $5204 should acknowledge the IRQ");
+    }
+}
+
+#[test]
+fn test_mmc5_fill_mode() {
+    let mut nes = mmc5_nes();
+    nes.memory.write(0x5105, 0xFF);
+    nes.memory.write(0x5106, 0x00);
+    nes.memory.write(0x5107, 0x02);
+    render(&mut nes);
+
+    assert_eq!(pixel(&nes, 40, 40), 2 * 4 + 1, "Fill tile 0 with palette 2");
+    assert_eq!(pixel(&nes, 250, 200), 2 * 4 + 1);
+}
+
+#[test]
+fn test_mmc5_extended_attributes() {
+    let mut nes = mmc5_nes();
+    nes.memory.write(0x5104, 2);
+    for offset in 0..0x3C0 {
+        // Palette 3 and 4 KiB CHR bank 1 everywhere, except palette 1 and bank 0 on tile 33
+        let value = if offset == 33 { 0x40 } else { 0xC1 };
+        nes.memory.write(0x5C00 + offset, value);
+    }
+    nes.memory.write(0x5104, 1);
+    render(&mut nes);
+
+    assert_eq!(pixel(&nes, 100, 100), 3 * 4 + 2);
+    assert_eq!(pixel(&nes, 8, 8), 4 + 1, "Each tile takes its own palette and bank");
+    assert_eq!(pixel(&nes, 16, 8), 3 * 4 + 2);
+}
+
+#[test]
+fn test_mmc5_vertical_split() {
+    let mut nes = mmc5_nes();
+    nes.memory.write(0x5104, 0);
+    nes.memory.write(0x5200, 0x80 | 16); // Split the left 16 tiles
+    nes.memory.write(0x5201, 4);
+    nes.memory.write(0x5202, 2);
+    render(&mut nes);
+
+    assert_eq!(pixel(&nes, 200, 100), 1, "The right side shows the normal background");
+    assert_eq!(pixel(&nes, 50, 8), 2, "Split scroll 4 starts line 8 on row 4 of the split tile");
+    assert_eq!(pixel(&nes, 50, 12), 3);
+    assert_eq!(pixel(&nes, 127, 12), 3);
+    assert_eq!(pixel(&nes, 128, 12), 1);
+}
+
+#[test]
+fn test_mmc5_audio() {
+    let mut mapper = Mmc5::new(mmc5_rom());
+    mapper.write_prg(0x5015, 0x01);
+    mapper.write_prg(0x5000, 0xBF); // 50% duty, constant volume 15
+    mapper.write_prg(0x5002, 0x40);
+    mapper.write_prg(0x5003, 0x08);
+    assert_eq!(mapper.read_prg(0x5015), Some(0x01), "Pulse 1's length counter should be running");
+
+    let mut peak: f32 = 0.0;
+    for _ in 0..1000 {
+        mapper.cpu_clock();
+        peak = peak.max(mapper.audio_output());
+    }
+    assert!(peak > 0.1, "The pulse channel should produce a square wave");
+
+    mapper.write_prg(0x5015, 0x00);
+    mapper.write_prg(0x5011, 0x80);
+    let pcm = mapper.audio_output();
+    assert!(pcm > 0.0, "Raw PCM writes should be audible");
+    assert_eq!(mapper.read_prg(0x5015), Some(0x00));
+
+    // Read mode captures PRG reads, and reading $00 raises the PCM IRQ
+    mapper.write_prg(0x5010, 0x81);
+    mapper.write_prg(0x5114, 0x80);
+    mapper.read_prg(0x8000);
+    assert!(mapper.irq(), "A $00 sample should raise the PCM IRQ");
+    assert_eq!(mapper.read_prg(0x5010).map(|value| value & 0x80), Some(0x80));
+    assert!(!mapper.irq(), "Reading $5010 should acknowledge it");
+}
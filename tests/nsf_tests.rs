@@ -0,0 +1,165 @@
+use rusty_nes::audio::Channel;
+use rusty_nes::nsf::{Nsf, NsfError, NsfPlayer, FDS, VRC6};
+use rusty_nes::region::Region;
+
+// An NSF loaded at $8000 with init at $8000 and play at `play`
+fn build_nsf(code: &[u8], play: u16, expansion: u8, banks: [u8; 8]) -> Vec<u8> {
+    let mut data = vec![0; 0x80];
+    data[0..5].copy_from_slice(b"NESM\x1A");
+    data[5] = 1;
+    data[6] = 4; // Songs
+    data[7] = 1; // Starting song
+    data[0x08..0x0A].copy_from_slice(&0x8000u16.to_le_bytes());
+    data[0x0A..0x0C].copy_from_slice(&0x8000u16.to_le_bytes());
+    data[0x0C..0x0E].copy_from_slice(&play.to_le_bytes());
+    data[0x0E..0x13].copy_from_slice(b"Title");
+    data[0x2E..0x34].copy_from_slice(b"Artist");
+    data[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+    data[0x70..0x78].copy_from_slice(&banks);
+    data[0x78..0x7A].copy_from_slice(&19997u16.to_le_bytes());
+    data[0x7B] = expansion;
+    data.extend_from_slice(code);
+    data
+}
+
+// Init stores A and X at $0200/$0201 and starts pulse 1; play counts its calls at $0202
+const COUNTING_CODE: [u8; 26] = [
+    0x8D, 0x00, 0x02, // STA $0200
+    0x8E, 0x01, 0x02, // STX $0201
+    0xA9, 0xBF, 0x8D, 0x00, 0x40, // Constant volume 15, 50% duty
+    0xA9, 0xFD, 0x8D, 0x02, 0x40,
+    0xA9, 0x00, 0x8D, 0x03, 0x40,
+    0x60, // RTS
+    0xEE, 0x02, 0x02, // $8016: INC $0202
+    0x60,
+];
+
+fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = (body.len() as u32).to_le_bytes().to_vec();
+    data.extend_from_slice(id);
+    data.extend_from_slice(body);
+    data
+}
+
+#[test]
+fn test_parse_nsf_header() {
+    let nsf = Nsf::parse(&build_nsf(&COUNTING_CODE, 0x8016, VRC6, [0; 8])).unwrap();
+    assert_eq!((nsf.songs, nsf.starting_song), (4, 0), "The starting song is stored from 0");
+    assert_eq!((nsf.load_address, nsf.init_address, nsf.play_address), (0x8000, 0x8000, 0x8016));
+    assert_eq!((nsf.title.as_str(), nsf.artist.as_str(), nsf.copyright.as_str()), ("Title", "Artist", ""));
+    assert_eq!(nsf.banks, None, "All-zero bank bytes mean the rip isn't bankswitched");
+    assert_eq!(nsf.expansion, VRC6);
+    assert_eq!(nsf.data, COUNTING_CODE);
+    assert_eq!(nsf.region(), Region::Ntsc);
+    assert!((nsf.play_rate(Region::Ntsc) - 60.1).abs() < 0.01);
+    assert!(matches!(Nsf::parse(b"NES\x1A"), Err(NsfError::InvalidHeader)));
+}
+
+#[test]
+fn test_parse_nsfe_chunks() {
+    let mut info = vec![0x00, 0x80, 0x00, 0x80, 0x16, 0x80, 0x00, 0x00, 3, 1];
+    let mut data = b"NSFE".to_vec();
+    data.extend(chunk(b"INFO", &info));
+    data.extend(chunk(b"DATA", &COUNTING_CODE));
+    data.extend(chunk(b"auth", b"Song\0Someone\0(c) 1990\0Ripper\0"));
+    data.extend(chunk(b"tlbl", b"Intro\0Stage 1\0Boss\0"));
+    data.extend(chunk(b"time", &[0x10, 0x27, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+    data.extend(chunk(b"psfx", &[1, 2])); // Lowercase chunks can be skipped
+    data.extend(chunk(b"NEND", &[]));
+
+    let nsf = Nsf::parse(&data).unwrap();
+    assert_eq!((nsf.songs, nsf.starting_song), (3, 1));
+    assert_eq!(nsf.play_address, 0x8016);
+    assert_eq!((nsf.title.as_str(), nsf.artist.as_str(), nsf.copyright.as_str()), ("Song", "Someone", "(c) 1990"));
+    assert_eq!(nsf.track_labels, vec!["Intro", "Stage 1", "Boss"]);
+    assert_eq!(nsf.track_times, vec![Some(10000), None], "-1 means the length is unknown");
+    assert_eq!(nsf.ntsc_speed, 16639, "Without a RATE chunk the speed is the NTSC frame rate");
+
+    let mut required = data.clone();
+    required.truncate(required.len() - 8);
+    required.extend(chunk(b"XTRA", &[]));
+    assert!(matches!(Nsf::parse(&required), Err(NsfError::UnsupportedChunk(id)) if &id == b"XTRA"));
+
+    info.truncate(8);
+    let mut no_data = b"NSFE".to_vec();
+    no_data.extend(chunk(b"INFO", &info));
+    assert!(matches!(Nsf::parse(&no_data), Err(NsfError::MissingChunk("DATA"))));
+}
+
+#[test]
+fn test_player_calls_init_and_play() {
+    let nsf = Nsf::parse(&build_nsf(&COUNTING_CODE, 0x8016, 0, [0; 8])).unwrap();
+    let mut player = NsfPlayer::new(nsf);
+    player.start_track(2);
+    assert!(player.idle(), "Init should have returned");
+    assert_eq!(player.nes.memory.read(0x0200), 2, "Init gets the track in A");
+    assert_eq!(player.nes.memory.read(0x0201), 0, "and 0 in X for NTSC");
+
+    let cycles = player.nes.cpu.cycles;
+    for _ in 0..60 {
+        player.run_frame();
+    }
+    assert_eq!(player.nes.memory.read(0x0202), 60, "Play is called once per tick");
+    let elapsed = player.nes.cpu.cycles - cycles;
+    let expected = 60.0 * 0.016639 * 1_789_773.0; // 16639 microseconds per tick
+    assert!((elapsed as f64 - expected).abs() < 10.0, "60 ticks at 60.1 Hz, got {} cycles", elapsed);
+
+    let frames = player.nes.memory.audio.available();
+    assert!(frames > 40000, "About a second of audio, got {}", frames);
+    let mut samples = vec![0.0; frames * 2];
+    player.nes.memory.audio.read_f32(&mut samples);
+    assert!(samples.iter().any(|sample| sample.abs() > 0.05), "The pulse started by init should be audible");
+
+    player.start_track(0);
+    assert_eq!(player.nes.memory.read(0x0202), 0, "Starting a track clears RAM");
+}
+
+#[test]
+fn test_bankswitching_and_expansion_audio() {
+    let mut code = vec![0; 0x2000];
+    let init = [
+        0xAD, 0x00, 0x90, 0x85, 0x00, // LDA $9000; STA $00
+        0xA9, 0x00, 0x8D, 0xF9, 0x5F, // Map bank 0 at $9000
+        0xAD, 0x00, 0x90, 0x85, 0x01, // LDA $9000; STA $01
+        0xA9, 0x8F, 0x8D, 0x00, 0x90, // VRC6 pulse 1: digitized mode, volume 15
+        0xA9, 0x80, 0x8D, 0x02, 0x90, // Enable it
+        0x60,
+    ];
+    code[..init.len()].copy_from_slice(&init);
+    code[0x1000] = 0xAB;
+    let nsf = Nsf::parse(&build_nsf(&code, 0x8019, VRC6, [0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+    assert_eq!(nsf.banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+
+    let mut player = NsfPlayer::new(nsf);
+    assert_eq!(player.nes.memory.read(0x0000), 0xAB, "The header's banks are mapped before init");
+    assert_eq!(player.nes.memory.read(0x0001), 0xAD, "$5FF9 switches the bank at $9000");
+    assert!(player.nes.memory.channel_output(Channel::Expansion) > 0.1, "The VRC6 flagged in the header plays");
+
+    // Its channels can be mixed one by one
+    let inputs = player.nes.memory.mixer_inputs();
+    let names: Vec<_> = inputs.iter().map(|(_, name)| *name).collect();
+    assert_eq!(&names[6..], ["vrc6-pulse1", "vrc6-pulse2", "vrc6-saw"]);
+    let apu = player.nes.memory.apu.output();
+    player.nes.memory.mixer.set_muted(inputs[6].0, true);
+    assert_eq!(player.nes.memory.audio_output(), apu, "Muting the only playing VRC6 channel leaves the APU");
+}
+
+#[test]
+fn test_fds_rips_run_from_ram() {
+    let code = [
+        0xA9, 0x42, 0x8D, 0x00, 0x90, // LDA #$42; STA $9000
+        0xAD, 0x00, 0x90, 0x85, 0x00, // LDA $9000; STA $00
+        0xA9, 0x80, 0x8D, 0x89, 0x40, // Unlock the wavetable
+        0xA9, 0x3F, 0x8D, 0x40, 0x40, // STA $4040
+        0xAD, 0x40, 0x40, 0x85, 0x01, // LDA $4040; STA $01
+        0x60,
+    ];
+    let mut player = NsfPlayer::new(Nsf::parse(&build_nsf(&code, 0x8019, FDS, [0; 8])).unwrap());
+    assert_eq!(player.nes.memory.read(0x0000), 0x42, "$8000-$DFFF is RAM on the Disk System");
+    assert_eq!(player.nes.memory.read(0x0001), 0x7F, "Wavetable reads drive the low 6 bits");
+
+    // Starting a track copies the rip back in over what the last one changed
+    player.nes.memory.write(0x8001, 0x99);
+    player.start_track(0);
+    assert_eq!(player.nes.memory.read(0x8001), 0x42);
+}
@@ -0,0 +1,51 @@
+use rusty_nes::ntsc::{NtscFilter, NtscSettings, NTSC_OUTPUT_HEIGHT, NTSC_OUTPUT_WIDTH};
+use rusty_nes::palette::Palette;
+use rusty_nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+fn pixel_at(output: &[u8], x: usize, y: usize) -> [u8; 3] {
+    let i = (y * NTSC_OUTPUT_WIDTH + x) * 3;
+    [output[i], output[i + 1], output[i + 2]]
+}
+
+#[test]
+fn test_output_size() {
+    let filter = NtscFilter::default();
+    let frame = vec![0x0F; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+    let output = filter.filter(&frame, 0);
+    assert_eq!(output.len(), NTSC_OUTPUT_WIDTH * NTSC_OUTPUT_HEIGHT * 3);
+}
+
+#[test]
+fn test_flat_colour_matches_palette() {
+    let filter = NtscFilter::default();
+    let palette = Palette::new();
+    let frame = vec![0x16; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+    let output = filter.filter(&frame, 0);
+    let expected = palette.rgb(0x16);
+    let actual = pixel_at(&output, NTSC_OUTPUT_WIDTH / 2, 100);
+    for channel in 0..3 {
+        let diff = (actual[channel] as i16 - expected[channel] as i16).abs();
+        assert!(diff <= 2, "Decoded {:?} should match palette colour {:?}", actual, expected);
+    }
+}
+
+#[test]
+fn test_artifacts_and_dot_crawl() {
+    // Alternating black and white columns produce colour fringes on a composite TV
+    let frame: Vec<u16> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+        .map(|i| if i % 2 == 0 { 0x30 } else { 0x0F })
+        .collect();
+
+    let composite = NtscFilter::default();
+    let output = composite.filter(&frame, 0);
+    let [r, g, b] = pixel_at(&output, 300, 50);
+    assert!(r != g || g != b, "Full artifacts should colour a black/white pattern");
+    assert_ne!(output, composite.filter(&frame, 1), "The subcarrier phase should change every frame");
+
+    let clean = NtscFilter::new(NtscSettings { artifacts: 0.0, ..NtscSettings::default() });
+    let output = clean.filter(&frame, 0);
+    let [r, g, b] = pixel_at(&output, 300, 50);
+    assert!(r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1, "Without artifacts the pattern stays grey");
+}
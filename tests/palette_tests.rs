@@ -0,0 +1,58 @@
+use rusty_nes::palette::{Palette, PaletteError};
+
+#[test]
+fn test_default_palette_black_and_white() {
+    let palette = Palette::new();
+
+    assert_eq!(palette.rgb(0x0F), [0, 0, 0], "$0F should be black");
+    assert_eq!(palette.rgb(0x30), [255, 255, 255], "$30 should be white");
+    let [r, g, b] = palette.rgb(0x16);
+    assert!(r > g && r > b, "$16 should be red");
+    let [r, g, b] = palette.rgb(0x12);
+    assert!(b > r && b > g, "$12 should be blue");
+}
+
+#[test]
+fn test_load_64_colour_palette_synthesizes_emphasis() {
+    let data: Vec<u8> = (0..192).map(|i| (i % 256) as u8).collect();
+    let palette = Palette::from_bytes(&data).unwrap();
+
+    assert_eq!(palette.rgb(0x01), [3, 4, 5]);
+    // Red emphasis (PPUMASK bit 5) darkens green and blue only
+    let [r, g, b] = palette.rgb_with_mask(0x01, 0x20);
+    assert_eq!(r, 3);
+    assert!(g < 4 && b < 5);
+}
+
+#[test]
+fn test_load_512_colour_palette_uses_emphasis_entries() {
+    let mut data = vec![0u8; 1536];
+    data[(0x40 | 0x21) * 3] = 0xAB;
+    let palette = Palette::from_bytes(&data).unwrap();
+
+    assert_eq!(palette.rgb_with_mask(0x21, 0x20), [0xAB, 0, 0]);
+    assert_eq!(palette.rgb_with_mask(0x21, 0x00), [0, 0, 0]);
+}
+
+#[test]
+fn test_greyscale_mask() {
+    let palette = Palette::new();
+
+    assert_eq!(palette.rgb_with_mask(0x16, 0x01), palette.rgb(0x10), "Greyscale keeps only the level bits");
+}
+
+#[test]
+fn test_invalid_palette_size() {
+    match Palette::from_bytes(&[0; 100]) {
+        Err(PaletteError::InvalidSize(100)) => {}
+        _ => panic!("A 100-byte palette should be rejected"),
+    }
+}
+
+#[test]
+fn test_frame_to_rgb() {
+    let palette = Palette::new();
+    let rgb = palette.to_rgb(&[0x0F, 0x30]);
+
+    assert_eq!(rgb, vec![0, 0, 0, 255, 255, 255]);
+}
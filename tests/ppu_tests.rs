@@ -0,0 +1,108 @@
+use rusty_nes::memory::Memory;
+use rusty_nes::ppu::{PPU, SCREEN_WIDTH};
+use rusty_nes::ppu_bus::{Mirroring, PpuBus};
+
+#[test]
+fn test_nametable_mirroring_modes() {
+    let mut bus = PpuBus::new();
+
+    bus.set_mirroring(Mirroring::Vertical);
+    bus.write(0x2000, 0x11, &mut None);
+    assert_eq!(bus.read(0x2800, &mut None), 0x11, "Vertical: $2800 should mirror $2000");
+    assert_ne!(bus.read(0x2400, &mut None), 0x11, "Vertical: $2400 is a separate table");
+
+    bus.set_mirroring(Mirroring::Horizontal);
+    bus.write(0x2C05, 0x22, &mut None);
+    assert_eq!(bus.read(0x2805, &mut None), 0x22, "Horizontal: $2C00 should mirror $2800");
+    assert_eq!(bus.read(0x3C05, &mut None), 0x22, "$3000-$3EFF should mirror $2000-$2EFF");
+
+    bus.set_mirroring(Mirroring::SingleScreenB);
+    bus.write(0x2000, 0x33, &mut None);
+    assert_eq!(bus.read(0x2C00, &mut None), 0x33, "Single-screen: every table is the same page");
+    bus.set_mirroring(Mirroring::SingleScreenA);
+    assert_ne!(bus.read(0x2000, &mut None), 0x33, "Single-screen A and B use different pages");
+
+    bus.set_mirroring(Mirroring::FourScreen);
+    for (i, address) in [0x2000u16, 0x2400, 0x2800, 0x2C00].iter().enumerate() {
+        bus.write(*address + 0x10, i as u8, &mut None);
+    }
+    for (i, address) in [0x2000u16, 0x2400, 0x2800, 0x2C00].iter().enumerate() {
+        assert_eq!(bus.read(*address + 0x10, &mut None), i as u8, "Four-screen tables should be distinct");
+    }
+}
+
+#[test]
+fn test_palette_aliasing() {
+    let mut bus = PpuBus::new();
+
+    bus.write(0x3F10, 0x2A, &mut None);
+    assert_eq!(bus.read(0x3F00, &mut None), 0x2A, "$3F10 should alias $3F00");
+    bus.write(0x3F0C, 0x15, &mut None);
+    assert_eq!(bus.read(0x3F1C, &mut None), 0x15, "$3F1C should alias $3F0C");
+    bus.write(0x3F11, 0x01, &mut None);
+    assert_ne!(bus.read(0x3F01, &mut None), 0x01, "$3F11 is not an alias");
+    assert_eq!(bus.read(0x3F31, &mut None), 0x01, "Palette RAM repeats every 32 bytes");
+    bus.write(0x3F02, 0xFF, &mut None);
+    assert_eq!(bus.read(0x3F02, &mut None), 0x3F, "Palette entries are 6 bits wide");
+}
+
+#[test]
+fn test_ppudata_read_is_buffered() {
+    let mut memory = Memory::new();
+
+    // Setup: write $42 to $2105 through PPUADDR/PPUDATA
+    memory.write(0x2006, 0x21);
+    memory.write(0x2006, 0x05);
+    memory.write(0x2007, 0x42);
+
+    memory.write(0x2006, 0x21);
+    memory.write(0x2006, 0x05);
+    memory.read(0x2007); // First read only fills the buffer
+    assert_eq!(memory.read(0x2007), 0x42, "Second read should return the buffered byte");
+
+    // Palette reads are not buffered
+    memory.write(0x2006, 0x3F);
+    memory.write(0x2006, 0x00);
+    memory.write(0x2007, 0x0F);
+    memory.write(0x2006, 0x3F);
+    memory.write(0x2006, 0x00);
+    assert_eq!(memory.read(0x2007) & 0x3F, 0x0F);
+}
+
+#[test]
+fn test_vblank_raises_nmi() {
+    let mut ppu = PPU::new();
+    ppu.write_register(0x2000, 0x80, &mut None); // Enable NMI
+
+    while ppu.scanline != 241 || ppu.dot != 2 {
+        ppu.tick(&mut None);
+    }
+
+    assert!(ppu.take_nmi(), "Entering vblank should raise NMI");
+    assert!(!ppu.take_nmi(), "NMI should only be reported once");
+    assert_eq!(ppu.read_register(0x2002, &mut None) & 0x80, 0x80, "Vblank flag should be set");
+    assert_eq!(ppu.read_register(0x2002, &mut None) & 0x80, 0x00, "Reading PPUSTATUS clears vblank");
+}
+
+#[test]
+fn test_background_renders_through_pattern_table() {
+    let mut ppu = PPU::new();
+
+    // Setup: tile 1 is solid colour 1, placed at the top-left of nametable 0
+    for row in 0..8 {
+        ppu.bus.write(0x0010 + row, 0xFF, &mut None);
+    }
+    ppu.bus.write(0x2000, 0x01, &mut None);
+    ppu.bus.write(0x3F00, 0x0F, &mut None);
+    ppu.bus.write(0x3F01, 0x16, &mut None);
+    ppu.write_register(0x2001, 0x0A, &mut None); // Show background, including the left column
+
+    let start = ppu.frame_count;
+    while ppu.frame_count < start + 2 {
+        ppu.tick(&mut None);
+    }
+
+    assert_eq!(ppu.frame[0], 0x16, "Tile 1 should use palette entry 1");
+    assert_eq!(ppu.frame[7 * SCREEN_WIDTH + 7], 0x16);
+    assert_eq!(ppu.frame[8], 0x0F, "Tile 0 should show the backdrop colour");
+}
@@ -0,0 +1,86 @@
+use rusty_nes::cartridge::Header;
+use rusty_nes::memory::Memory;
+use rusty_nes::ppu::PPU;
+use rusty_nes::region::Region;
+
+fn dots_for_cpu_cycles(region: Region, cycles: u32) -> u32 {
+    let mut memory = Memory::new();
+    memory.set_region(region);
+    memory.tick(cycles);
+    memory.ppu.scanline as u32 * 341 + memory.ppu.dot as u32
+}
+
+#[test]
+fn test_ppu_cpu_ratio() {
+    assert_eq!(dots_for_cpu_cycles(Region::Ntsc, 10), 30, "NTSC runs 3 dots per CPU cycle");
+    assert_eq!(dots_for_cpu_cycles(Region::Dendy, 10), 30, "Dendy runs 3 dots per CPU cycle");
+    assert_eq!(dots_for_cpu_cycles(Region::Pal, 10), 32, "PAL runs 3.2 dots per CPU cycle");
+    assert_eq!(dots_for_cpu_cycles(Region::Pal, 1), 3, "Partial dots carry over to the next tick");
+}
+
+#[test]
+fn test_frame_layout() {
+    for (region, lines, vblank) in [(Region::Ntsc, 262, 241), (Region::Pal, 312, 241), (Region::Dendy, 312, 291)] {
+        let mut ppu = PPU::new();
+        ppu.set_region(region);
+
+        while ppu.status & 0x80 == 0 {
+            ppu.tick(&mut None);
+        }
+        assert_eq!(ppu.scanline, vblank, "{:?} vblank should start on line {}", region, vblank);
+
+        let mut max_line = 0;
+        while ppu.frame_count < 2 {
+            max_line = max_line.max(ppu.scanline);
+            ppu.tick(&mut None);
+        }
+        assert_eq!(max_line + 1, lines, "{:?} frames should have {} scanlines", region, lines);
+    }
+}
+
+#[test]
+fn test_region_tables() {
+    assert_eq!(Region::Ntsc.noise_periods()[15], 4068);
+    assert_eq!(Region::Pal.noise_periods()[15], 3778);
+    assert_eq!(Region::Pal.dmc_rates()[0], 398);
+    assert_eq!(Region::Pal.frame_counter_four_step()[3], 33253);
+    assert_eq!(Region::Pal.vblank_length(), 70);
+    assert_eq!(Region::Dendy.vblank_length(), 20);
+    assert!((Region::Pal.ppu_cpu_ratio() - 3.2).abs() < 1e-9);
+}
+
+#[test]
+fn test_region_from_nes2_header() {
+    let mut header = [0u8; 16];
+    header[0..4].copy_from_slice(b"NES\x1A");
+    header[4] = 2;
+    header[7] = 0x08; // NES 2.0
+    header[12] = 0x01; // PAL timing
+
+    let parsed = Header::parse(&header).unwrap();
+    assert!(parsed.nes2);
+    assert_eq!(parsed.region(), Some(Region::Pal));
+
+    header[12] = 0x03;
+    assert_eq!(Header::parse(&header).unwrap().region(), Some(Region::Dendy));
+
+    header[7] = 0x00; // iNES 1.0 has no timing field
+    assert_eq!(Header::parse(&header).unwrap().region(), None);
+}
+
+#[test]
+fn test_memory_selects_region_from_header() {
+    let mut header = [0u8; 16];
+    header[0..4].copy_from_slice(b"NES\x1A");
+    header[4] = 2;
+    header[7] = 0x08; // NES 2.0
+    header[12] = 0x01; // PAL timing
+
+    let mut memory = Memory::new();
+    memory.select_region(&Header::parse(&header).unwrap());
+    assert_eq!(memory.region(), Region::Pal, "NES 2.0 timing picks the region");
+
+    header[7] = 0x00;
+    memory.select_region(&Header::parse(&header).unwrap());
+    assert_eq!(memory.region(), Region::Pal, "iNES 1.0 headers keep the current region");
+}
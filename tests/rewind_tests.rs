@@ -0,0 +1,81 @@
+mod common;
+
+use common::{build_ines, nop_prg};
+use rusty_nes::cartridge::Rom;
+use rusty_nes::nes::Nes;
+use rusty_nes::rewind::{Rewind, RewindConfig};
+
+// INC $10; LDA $10; STA $2005; JMP $C000, with rendering on
+fn counting_nes() -> Nes {
+    let mut prg = nop_prg(0x8000);
+    let program = [0xE6, 0x10, 0xA5, 0x10, 0x8D, 0x05, 0x20, 0x4C, 0x00, 0xC0];
+    prg[0x4000..0x4000 + program.len()].copy_from_slice(&program);
+    let rom = Rom::parse(&build_ines(0, 0, &prg, &[0; 0x2000])).unwrap();
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+    nes.memory.write(0x2001, 0x18);
+    nes
+}
+
+#[test]
+fn test_step_back_frame_by_frame() {
+    let mut nes = counting_nes();
+    let mut rewind = Rewind::new(RewindConfig { keyframe_every: 4, ..RewindConfig::default() });
+    let mut states = Vec::new();
+    for _ in 0..10 {
+        nes.run_frame();
+        rewind.end_frame(&nes);
+        states.push(nes.save_state());
+    }
+    assert_eq!(rewind.len(), 10);
+
+    for frame in (0..9).rev() {
+        assert!(rewind.step_back(&mut nes).unwrap());
+        assert!(nes.save_state() == states[frame], "Stepping back should restore frame {}", frame);
+    }
+    assert!(!rewind.step_back(&mut nes).unwrap(), "There is nothing before the first snapshot");
+
+    // Playing on from a rewound point records a new history from there
+    nes.run_frame();
+    rewind.end_frame(&nes);
+    assert!(rewind.step_back(&mut nes).unwrap());
+    assert!(nes.save_state() == states[0]);
+}
+
+#[test]
+fn test_step_back_between_snapshots() {
+    let mut nes = counting_nes();
+    let mut rewind = Rewind::new(RewindConfig { interval: 5, ..RewindConfig::default() });
+    let mut snapshot = Vec::new();
+    for frame in 1..=7 {
+        nes.run_frame();
+        rewind.end_frame(&nes);
+        if frame == 5 {
+            snapshot = nes.save_state();
+        }
+    }
+    assert_eq!(rewind.len(), 1);
+    assert!(rewind.step_back(&mut nes).unwrap());
+    assert!(nes.save_state() == snapshot, "Frames since the last snapshot should be undone first");
+}
+
+#[test]
+fn test_depth_and_memory_budget() {
+    let mut nes = counting_nes();
+    let config = RewindConfig { seconds: 0.5, keyframe_every: 10, ..RewindConfig::default() };
+    let mut rewind = Rewind::new(config);
+    for _ in 0..120 {
+        nes.run_frame();
+        rewind.end_frame(&nes);
+    }
+    assert!(rewind.len() >= 30, "Half a second should be kept, got {}", rewind.len());
+    assert!(rewind.len() <= 40, "Whole keyframe groups past the depth should be dropped, got {}", rewind.len());
+
+    let state_size = nes.save_state().len();
+    assert!(rewind.memory_used() < rewind.len() * state_size / 4, "Deltas should be much smaller than states");
+
+    let budget = rewind.memory_used() / 2;
+    rewind.set_config(RewindConfig { memory_budget: budget, ..config });
+    assert!(rewind.memory_used() <= budget, "Old groups should be dropped to fit the budget");
+    assert!(rewind.step_back(&mut nes).unwrap(), "The newest group is always kept");
+}
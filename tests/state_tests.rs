@@ -0,0 +1,172 @@
+mod common;
+
+use common::{build_ines, nop_prg};
+use rusty_nes::cartridge::Rom;
+use rusty_nes::nes::Nes;
+use rusty_nes::region::Region;
+use rusty_nes::state::{StateError, StateHeader, STATE_VERSION};
+
+// NROM program that keeps changing RAM and the PPU: INC $10; LDA $10; STA $2005; JMP $C000
+fn counting_nes() -> Nes {
+    let mut prg = nop_prg(0x8000);
+    let program = [0xE6, 0x10, 0xA5, 0x10, 0x8D, 0x05, 0x20, 0x4C, 0x00, 0xC0];
+    prg[0x4000..0x4000 + program.len()].copy_from_slice(&program);
+    let rom = Rom::parse(&build_ines(0, 0, &prg, &[0; 0x2000])).unwrap();
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+    nes.memory.write(0x2001, 0x18);
+    nes
+}
+
+#[test]
+fn test_state_round_trip_is_deterministic() {
+    let mut nes = counting_nes();
+    for _ in 0..3 {
+        nes.run_frame();
+    }
+    let state = nes.save_state();
+    let header = StateHeader::parse(&state).unwrap();
+    assert_eq!(header.version, STATE_VERSION);
+    assert_eq!(header.region, Region::Ntsc);
+    assert_eq!(header.rom_hash, nes.rom_hash());
+
+    for _ in 0..2 {
+        nes.run_frame();
+    }
+    let expected = nes.save_state();
+    let expected_cycles = nes.cpu.cycles;
+
+    nes.load_state(&state).unwrap();
+    assert_ne!(nes.cpu.cycles, expected_cycles, "Loading should rewind the CPU");
+    for _ in 0..2 {
+        nes.run_frame();
+    }
+    assert_eq!(nes.cpu.cycles, expected_cycles);
+    assert!(nes.save_state() == expected, "Running from a loaded state should reproduce the same machine");
+}
+
+#[test]
+fn test_state_restores_mapper_registers() {
+    let mut prg = nop_prg(0x10000);
+    for bank in 0..8 {
+        prg[bank * 0x2000] = bank as u8;
+    }
+    let rom = Rom::parse(&build_ines(4, 0, &prg, &[0; 0x2000])).unwrap();
+    let mut nes = Nes::new();
+    nes.insert_rom(rom).unwrap();
+    nes.memory.write(0x8000, 6);
+    nes.memory.write(0x8001, 3);
+    nes.memory.write(0xA001, 0x80);
+    nes.memory.write(0x6000, 0x42);
+    let state = nes.save_state();
+
+    nes.memory.write(0x8001, 5);
+    nes.memory.write(0x6000, 0x00);
+    nes.load_state(&state).unwrap();
+    assert_eq!(nes.memory.read(0x8000), 3, "MMC3 bank registers should be restored");
+    assert_eq!(nes.memory.read(0x6000), 0x42, "PRG-RAM should be restored");
+}
+
+#[test]
+fn test_state_errors_leave_machine_untouched() {
+    let mut nes = counting_nes();
+    nes.run_frame();
+    let state = nes.save_state();
+    nes.run_frame();
+    let before = nes.save_state();
+
+    let mut other = Nes::new();
+    let rom = Rom::parse(&build_ines(0, 0, &nop_prg(0x4000), &[0; 0x2000])).unwrap();
+    other.insert_rom(rom).unwrap();
+    assert!(matches!(other.load_state(&state), Err(StateError::RomMismatch { .. })));
+
+    assert_eq!(nes.load_state(b"garbage"), Err(StateError::InvalidHeader));
+    let mut newer = state.clone();
+    newer[4] = 0xFF;
+    assert!(matches!(nes.load_state(&newer), Err(StateError::UnsupportedVersion(_))));
+    assert_eq!(nes.load_state(&state[..state.len() - 10]), Err(StateError::Truncated));
+    assert!(nes.save_state() == before, "Failed loads should not change the machine");
+}
+
+#[test]
+fn test_state_skips_unknown_sections() {
+    let mut nes = counting_nes();
+    nes.run_frame();
+    let mut state = nes.save_state();
+    state.extend_from_slice(b"NEW!");
+    state.extend_from_slice(&3u32.to_le_bytes());
+    state.extend_from_slice(&[1, 2, 3]);
+    let pc = nes.cpu.pc;
+
+    nes.run_frame();
+    nes.load_state(&state).unwrap();
+    assert_eq!(nes.cpu.pc, pc);
+}
+
+#[test]
+fn test_state_with_bad_ppu_fields() {
+    // Rendering on, with nothing rewriting the scroll
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&build_ines(0, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap()).unwrap();
+    nes.memory.write(0x2001, 0x18);
+    nes.run_frame();
+    nes.memory.ppu.frame_count = 0x1122_3344_5566_7788;
+    let state = nes.save_state();
+    // The PPU's scanline and dot come just before its frame count, fine X 12 bytes after its start
+    let frame_count = state.windows(8).position(|bytes| bytes == 0x1122_3344_5566_7788u64.to_le_bytes()).unwrap();
+
+    let mut bad_fine_x = state.clone();
+    bad_fine_x[frame_count + 12] = 0xFF;
+    nes.load_state(&bad_fine_x).unwrap();
+    nes.run_frame();
+
+    let mut bad_scanline = state.clone();
+    bad_scanline[frame_count - 3] = 0x10; // Scanline 4096 and up
+    assert_eq!(nes.load_state(&bad_scanline), Err(StateError::Invalid("PPU position")));
+    let mut bad_dot = state.clone();
+    bad_dot[frame_count - 1] = 0x10;
+    assert_eq!(nes.load_state(&bad_dot), Err(StateError::Invalid("PPU position")));
+}
+
+// Overwrites each byte at the end of the cartridge section, where the bank registers
+// are, then exercises the board. A corrupt state may load or be rejected, but must
+// not leave the board able to crash the emulator.
+fn corrupt_cartridge_state(mapper: u8) {
+    let prg: Vec<u8> = (0..0x20000).map(|offset| (offset / 0x2000) as u8).collect();
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&build_ines(mapper, 0, &prg, &[0; 0x20000])).unwrap()).unwrap();
+    nes.run_frame();
+    let state = nes.save_state();
+    let at = state.windows(4).position(|tag| tag == b"CART").unwrap();
+    let end = at + 8 + u32::from_le_bytes([state[at + 4], state[at + 5], state[at + 6], state[at + 7]]) as usize;
+
+    let mut vram = [0; 4096];
+    for offset in end.saturating_sub(400).max(at + 8)..end {
+        let mut corrupt = state.clone();
+        corrupt[offset] = 0xFF;
+        if nes.load_state(&corrupt).is_err() {
+            continue;
+        }
+        let cartridge = nes.memory.cartridge.as_mut().unwrap();
+        for address in (0x6000..=0xF800).step_by(0x800) {
+            cartridge.peek_prg(address);
+        }
+        for address in (0x0000..0x3000).step_by(0x400) {
+            cartridge.ppu_clock(address);
+            cartridge.read_chr(address & 0x1FFF);
+            cartridge.read_nametable(0x2000 | address, &vram);
+            cartridge.write_nametable(0x2000 | address, 0, &mut vram);
+        }
+        for _ in 0..100 {
+            cartridge.cpu_clock();
+        }
+        cartridge.audio_output();
+    }
+}
+
+#[test]
+fn test_corrupt_mapper_state_is_contained() {
+    for mapper in [0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 16, 19, 21, 22, 23, 24, 25, 26, 34, 66, 69, 85, 159] {
+        corrupt_cartridge_state(mapper);
+    }
+}
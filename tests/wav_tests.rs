@@ -0,0 +1,115 @@
+mod common;
+
+use std::fs;
+use std::io::Cursor;
+
+use common::{build_ines, nop_prg, scratch_dir};
+use rusty_nes::audio::{AudioConfig, Channel, Channels};
+use rusty_nes::cartridge::Rom;
+use rusty_nes::input_log::InputLog;
+use rusty_nes::nes::Nes;
+use rusty_nes::wav::{export_wav, stem_path, WavExport, WavWriter};
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn samples(data: &[u8]) -> Vec<i16> {
+    data[44..].chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect()
+}
+
+fn nop_nes() -> Nes {
+    let mut nes = Nes::new();
+    nes.insert_rom(Rom::parse(&build_ines(0, 0, &nop_prg(0x8000), &[0; 0x2000])).unwrap()).unwrap();
+    nes
+}
+
+#[test]
+fn test_wav_header() {
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), 48000, 2).unwrap();
+    writer.write_samples(&[1, -1, 2, -2]).unwrap();
+    writer.write_samples(&[3, -3]).unwrap();
+    let data = writer.finish().unwrap().into_inner();
+
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(u32_at(&data, 4), 36 + 12, "The RIFF size covers everything after it");
+    assert_eq!(&data[8..16], b"WAVEfmt ");
+    assert_eq!(u16::from_le_bytes([data[22], data[23]]), 2, "Channels");
+    assert_eq!(u32_at(&data, 24), 48000, "Sample rate");
+    assert_eq!(u32_at(&data, 28), 48000 * 4, "Byte rate");
+    assert_eq!(u16::from_le_bytes([data[34], data[35]]), 16, "Bits per sample");
+    assert_eq!(u32_at(&data, 40), 12, "The data size is filled in by finish");
+    assert_eq!(samples(&data), vec![1, -1, 2, -2, 3, -3]);
+}
+
+#[test]
+fn test_input_log_drives_controllers() {
+    let log = InputLog::parse("version 3\nromFilename game\n|0|.......A|R.......||\n|1|...UT...|........||\n");
+    assert_eq!(log.len(), 2, "Header lines aren't frames");
+    assert_eq!(log.frame(0).buttons, [0x01, 0x80]);
+    assert_eq!(log.frame(1).buttons, [0x18, 0x00]);
+    assert!(log.frame(1).reset, "Command 1 is a soft reset");
+    assert_eq!(log.frame(5).buttons, [0, 0], "Past the end nothing is held");
+
+    let mut nes = nop_nes();
+    nes.memory.input.controllers[0].buttons = log.frame(1).buttons[0];
+    nes.memory.write(0x4016, 1);
+    nes.memory.write(0x4016, 0);
+    let bits: Vec<u8> = (0..10).map(|_| nes.memory.read(0x4016) & 0x01).collect();
+    assert_eq!(bits, vec![0, 0, 0, 1, 1, 0, 0, 0, 1, 1], "A, B, Select, Start, Up, ... then 1s");
+}
+
+#[test]
+fn test_export_with_stems() {
+    let dir = scratch_dir("wav_export");
+    let path = dir.join("out.wav");
+    let mut nes = nop_nes();
+    nes.memory.write(0x4015, 0x01);
+    nes.memory.write(0x4000, 0xBF); // Pulse 1: constant volume 15, 50% duty
+    nes.memory.write(0x4002, 0xFD);
+    nes.memory.write(0x4003, 0x00);
+
+    let export = WavExport {
+        frames: 30,
+        config: AudioConfig { sample_rate: 22050, channels: Channels::Mono, filters: true },
+        stems: true,
+    };
+    let log = InputLog::parse("|0|........|........||\n|0|R......A|........||\n");
+    export_wav(&mut nes, &path, &export, Some(&log)).unwrap();
+
+    let mix = fs::read(&path).unwrap();
+    let count = u32_at(&mix, 40) as usize / 2;
+    assert!((10900..=11050).contains(&count), "30 frames are half a second at 22050 Hz, got {}", count);
+    assert_eq!(mix.len(), 44 + count * 2);
+
+    let loudest = |channel| {
+        let data = fs::read(stem_path(&path, channel)).unwrap();
+        assert_eq!(u32_at(&data, 40) as usize, count * 2, "Stems are as long as the mix");
+        samples(&data).iter().map(|sample| sample.unsigned_abs()).max().unwrap()
+    };
+    assert!(loudest(Channel::Pulse1) > 1000, "The pulse stem should carry the tone");
+    assert_eq!(loudest(Channel::Noise), 0, "The noise stem should be silent");
+    assert_eq!(loudest(Channel::Expansion), 0, "NROM has no expansion audio");
+    assert_eq!(nes.memory.input.controllers[0].buttons, 0x00, "Frames past the log have nothing held");
+    assert!(nes.memory.stems.is_empty(), "Stems are only rendered during the export");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_failed_export_restores_audio() {
+    let dir = scratch_dir("wav_export_failure");
+    let mut nes = nop_nes();
+    let previous = nes.memory.audio.config();
+    let export = WavExport {
+        frames: 1,
+        config: AudioConfig { sample_rate: 22050, channels: Channels::Mono, filters: false },
+        stems: true,
+    };
+    let path = dir.join("missing").join("out.wav");
+    assert!(export_wav(&mut nes, &path, &export, None).is_err(), "The output directory does not exist");
+    assert_eq!(nes.memory.audio.config(), previous, "The host's audio config is put back");
+    assert!(nes.memory.stems.is_empty());
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,56 @@
+use rusty_nes::input::PortDevice;
+use rusty_nes::memory::Memory;
+use rusty_nes::ppu::SCREEN_WIDTH;
+
+// A white square around (100, 100) on a black screen, with the beam at the given position
+fn screen_with_target(scanline: u16, dot: u16) -> Memory {
+    let mut memory = Memory::new();
+    memory.ppu.frame.fill(0x0F);
+    for y in 98..=102 {
+        for x in 98..=102 {
+            memory.ppu.frame[y * SCREEN_WIDTH + x] = 0x30;
+        }
+    }
+    memory.ppu.scanline = scanline;
+    memory.ppu.dot = dot;
+    memory
+}
+
+// Plugs the Zapper into port 2 and points it
+fn aim(memory: &mut Memory, aim: Option<(usize, usize)>, trigger: bool) {
+    memory.input.devices.ports[1] = PortDevice::Zapper;
+    memory.input.zapper.aim = aim;
+    memory.input.zapper.trigger = trigger;
+}
+
+#[test]
+fn test_light_sense_follows_the_beam() {
+    let mut memory = screen_with_target(104, 50);
+    aim(&mut memory, Some((100, 100)), false);
+    assert_eq!(memory.read(0x4017) & 0x18, 0x00, "The target was just drawn, so light is sensed");
+
+    aim(&mut memory, Some((20, 100)), false);
+    assert_eq!(memory.read(0x4017) & 0x18, 0x08, "Aiming at the black background senses nothing");
+    aim(&mut memory, None, false);
+    assert_eq!(memory.read(0x4017) & 0x18, 0x08);
+
+    // Before the beam reaches the target, and long after it has passed
+    aim(&mut memory, Some((100, 100)), false);
+    memory.ppu.scanline = 96;
+    assert_eq!(memory.read(0x4017) & 0x08, 0x08);
+    memory.ppu.scanline = 140;
+    assert_eq!(memory.read(0x4017) & 0x08, 0x08);
+}
+
+#[test]
+fn test_trigger_and_port_one() {
+    let mut memory = screen_with_target(200, 0);
+    aim(&mut memory, Some((100, 100)), true);
+    assert_eq!(memory.read(0x4017) & 0x1F, 0x18, "Trigger pulled, no light");
+
+    // The joypad on port 1 is unaffected
+    memory.input.controllers[0].buttons = 0x01;
+    memory.write(0x4016, 1);
+    memory.write(0x4016, 0);
+    assert_eq!(memory.read(0x4016) & 0x01, 1);
+}